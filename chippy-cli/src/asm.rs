@@ -0,0 +1,35 @@
+use chippy_core::{assembler, vm::VmMode};
+use pico_args::Arguments;
+use std::{fs, path::PathBuf};
+
+/// Assembles a source file, writing the ROM next to it unless `-o` is given.
+pub fn run(mut arguments: Arguments) -> Result<(), String> {
+	let mode: VmMode = arguments
+		.opt_value_from_str("--mode")
+		.map_err(|error| error.to_string())?
+		.unwrap_or(VmMode::XoChip);
+	let output: Option<PathBuf> = arguments
+		.opt_value_from_os_str(["-o", "--output"], |path| {
+			Ok::<_, String>(PathBuf::from(path))
+		})
+		.map_err(|error| error.to_string())?;
+	let source: PathBuf = arguments
+		.free_from_os_str(|path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let rest = arguments.finish();
+	if !rest.is_empty() {
+		return Err(format!("unexpected arguments: {:?}", rest));
+	}
+
+	let rom = assembler::assemble_file(&source, mode).map_err(|errors| {
+		errors
+			.iter()
+			.map(ToString::to_string)
+			.collect::<Vec<_>>()
+			.join("\n")
+	})?;
+	let output = output.unwrap_or_else(|| source.with_extension("ch8"));
+	fs::write(&output, &rom).map_err(|error| format!("{}: {}", output.display(), error))?;
+	println!("Assembled {} bytes into {}", rom.len(), output.display());
+	Ok(())
+}
@@ -0,0 +1,660 @@
+use chippy_core::{
+	assembler::{self, SourceMap},
+	breakpoint::Breakpoint,
+	instruction::{
+		branch::{BranchInstruction, BranchTarget, BranchType},
+		Address, Instruction,
+	},
+	octo,
+	vm::{Vm, VmMode, FRAME_TIME},
+};
+use pico_args::Arguments;
+use serde_json::{json, Value};
+use std::{
+	collections::{BTreeSet, HashMap},
+	fs,
+	io::{self, BufRead, ErrorKind, Write},
+	path::{Path, PathBuf},
+	sync::mpsc::{self, TryRecvError},
+	thread,
+	time::Instant,
+};
+
+/// The only thread, as the virtual machine runs a single program.
+const THREAD_ID: u64 = 1;
+/// The variables reference of the registers scope.
+const REGISTERS: u64 = 1;
+/// The variables reference of the timers scope.
+const TIMERS: u64 = 2;
+/// How many instructions a step may execute while looking for the next line.
+const STEP_LIMIT: usize = 10_000;
+
+/// Serves the Debug Adapter Protocol over stdin and stdout, until the client disconnects.
+pub fn run(arguments: Arguments) -> Result<(), String> {
+	let rest = arguments.finish();
+	if !rest.is_empty() {
+		return Err(format!("unexpected arguments: {:?}", rest));
+	}
+
+	// Requests are read on their own thread, so that they can arrive while the program runs.
+	let (sender, receiver) = mpsc::channel();
+	thread::spawn(move || {
+		let mut input = io::stdin().lock();
+		while let Ok(Some(message)) = read_message(&mut input) {
+			if sender.send(message).is_err() {
+				break;
+			}
+		}
+	});
+
+	let mut session = Session::new(io::stdout().lock());
+	while !session.disconnected {
+		if session.running {
+			match receiver.try_recv() {
+				Ok(request) => session.handle(&request),
+				Err(TryRecvError::Empty) => session.run_frame(),
+				Err(TryRecvError::Disconnected) => break,
+			}
+		} else {
+			match receiver.recv() {
+				Ok(request) => session.handle(&request),
+				Err(_) => break,
+			}
+		}
+		session.flush().map_err(|error| error.to_string())?;
+	}
+	Ok(())
+}
+
+/// Reads a message framed by a `Content-Length` header, or `None` at the end of input.
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Value>> {
+	let mut length = None;
+	loop {
+		let mut line = String::new();
+		if input.read_line(&mut line)? == 0 {
+			return Ok(None);
+		}
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		if let Some(value) = line.strip_prefix("Content-Length:") {
+			length = value.trim().parse().ok();
+		}
+	}
+	let length: usize =
+		length.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "missing Content-Length"))?;
+	let mut body = vec![0; length];
+	input.read_exact(&mut body)?;
+	serde_json::from_slice(&body)
+		.map(Some)
+		.map_err(|error| io::Error::new(ErrorKind::InvalidData, error))
+}
+
+/// Parses a number in hex with a `0x` prefix, or in decimal.
+fn parse_number(text: &str) -> Option<u64> {
+	let text = text.trim();
+	match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+		Some(hex) => u64::from_str_radix(hex, 16).ok(),
+		None => text.parse().ok(),
+	}
+}
+
+/// Whether two paths are the same file.
+fn same_file(a: &Path, b: &Path) -> bool {
+	let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+	canonical(a) == canonical(b)
+}
+
+/// A debugging session for a single program.
+struct Session<W: Write> {
+	output: W,
+	/// The sequence number of the next message sent.
+	seq: u64,
+	/// Messages to send after the response to the current request.
+	events: Vec<Value>,
+	vm: Vm,
+	source_map: SourceMap,
+	/// Whether to stop before the first instruction once configuration is done.
+	stop_on_entry: bool,
+	/// Whether the program is running, rather than stopped.
+	running: bool,
+	/// Whether the program has exited with 00FD.
+	exited: bool,
+	/// Whether the client has disconnected.
+	disconnected: bool,
+	/// The breakpoints set on lines of each source file.
+	source_breakpoints: HashMap<PathBuf, Vec<Address>>,
+	/// The breakpoints set on instructions.
+	instruction_breakpoints: Vec<Address>,
+	/// The breakpoint set to step over a call or out of a subroutine, which isn't the user's,
+	/// and the stack depth the step returns to.
+	step_breakpoint: Option<(Address, usize)>,
+}
+
+impl<W: Write> Session<W> {
+	fn new(output: W) -> Self {
+		Session {
+			output,
+			seq: 1,
+			events: Vec::new(),
+			vm: Vm::new(),
+			source_map: SourceMap::default(),
+			stop_on_entry: false,
+			running: false,
+			exited: false,
+			disconnected: false,
+			source_breakpoints: HashMap::new(),
+			instruction_breakpoints: Vec::new(),
+			step_breakpoint: None,
+		}
+	}
+
+	/// Sends a message.
+	fn send(&mut self, mut message: Value) -> io::Result<()> {
+		message["seq"] = json!(self.seq);
+		self.seq += 1;
+		let body = message.to_string();
+		write!(
+			self.output,
+			"Content-Length: {}\r\n\r\n{}",
+			body.len(),
+			body
+		)
+	}
+
+	/// Sends the events queued by the last request, or by the running program.
+	fn flush(&mut self) -> io::Result<()> {
+		for event in std::mem::take(&mut self.events) {
+			self.send(event)?;
+		}
+		self.output.flush()
+	}
+
+	/// Queues an event.
+	fn event(&mut self, event: &str, body: Value) {
+		self.events
+			.push(json!({ "type": "event", "event": event, "body": body }));
+	}
+
+	/// Handles a request, responding to it before any events it caused.
+	fn handle(&mut self, request: &Value) {
+		let command = request["command"].as_str().unwrap_or_default();
+		let arguments = &request["arguments"];
+		let result = match command {
+			"initialize" => {
+				self.event("initialized", json!({}));
+				Ok(json!({
+					"supportsConfigurationDoneRequest": true,
+					"supportsInstructionBreakpoints": true,
+					"supportsSetVariable": true,
+					"supportsTerminateRequest": true,
+				}))
+			}
+			"launch" => self.launch(arguments),
+			"setBreakpoints" => self.set_breakpoints(arguments),
+			"setInstructionBreakpoints" => self.set_instruction_breakpoints(arguments),
+			"setExceptionBreakpoints" => Ok(json!({ "breakpoints": [] })),
+			"configurationDone" => {
+				if self.stop_on_entry {
+					self.stopped("entry", None);
+				} else {
+					self.resume();
+				}
+				Ok(json!({}))
+			}
+			"threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+			"stackTrace" => Ok(self.stack_trace()),
+			"scopes" => Ok(json!({
+				"scopes": [
+					{ "name": "Registers", "variablesReference": REGISTERS, "expensive": false },
+					{ "name": "Timers", "variablesReference": TIMERS, "expensive": false },
+				]
+			})),
+			"variables" => Ok(self.variables(arguments)),
+			"setVariable" => self.set_variable(arguments),
+			"continue" => self.running_command(|session| {
+				session.resume();
+				json!({ "allThreadsContinued": true })
+			}),
+			"next" => self.running_command(|session| session.step(true)),
+			"stepIn" => self.running_command(|session| session.step(false)),
+			"stepOut" => self.running_command(Self::step_out),
+			"pause" => {
+				if self.running {
+					self.vm.paused = true;
+					self.stopped("pause", None);
+				}
+				Ok(json!({}))
+			}
+			"disconnect" | "terminate" => {
+				self.disconnected = true;
+				Ok(json!({}))
+			}
+			_ => Err(format!("unsupported request `{}`", command)),
+		};
+		let mut response = json!({
+			"type": "response",
+			"request_seq": request["seq"],
+			"command": command,
+			"success": result.is_ok(),
+		});
+		match result {
+			Ok(body) => response["body"] = body,
+			Err(message) => response["message"] = json!(message),
+		}
+		// Events caused by the request come after its response.
+		self.events.insert(0, response);
+	}
+
+	/// Runs a request that resumes the program, which can't happen once it has exited.
+	fn running_command(
+		&mut self,
+		command: impl FnOnce(&mut Self) -> Value,
+	) -> Result<Value, String> {
+		if self.exited {
+			return Err("the program has exited".to_string());
+		}
+		Ok(command(self))
+	}
+
+	/// Handles `launch`, which loads a ROM, or assembles or compiles a source file.
+	fn launch(&mut self, arguments: &Value) -> Result<Value, String> {
+		let program = arguments["program"]
+			.as_str()
+			.ok_or("`program` is required")?;
+		let mode: VmMode = match arguments["mode"].as_str() {
+			Some(mode) => mode.parse()?,
+			None => VmMode::XoChip,
+		};
+		let path = Path::new(program);
+		let (rom, source_map) = match path.extension().and_then(|extension| extension.to_str()) {
+			Some("8o") => {
+				octo::compile_file_with_map(path, mode).map_err(|error| error.to_string())?
+			}
+			Some("asm") => assembler::assemble_file_with_map(path, mode).map_err(|errors| {
+				let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+				errors.join("\n")
+			})?,
+			_ => (
+				fs::read(path).map_err(|error| format!("{}: {}", program, error))?,
+				SourceMap::default(),
+			),
+		};
+		self.vm.set_mode(mode);
+		self.vm
+			.load_program(&rom)
+			.map_err(|error| error.to_string())?;
+		self.vm.paused = true;
+		self.source_map = source_map;
+		self.stop_on_entry = arguments["stopOnEntry"].as_bool().unwrap_or(false);
+		self.exited = false;
+		Ok(json!({}))
+	}
+
+	/// Handles `setBreakpoints`, which replaces the breakpoints in a source file.
+	fn set_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+		let path = arguments["source"]["path"]
+			.as_str()
+			.ok_or("the source has no path")?;
+		let path = PathBuf::from(path);
+		let mut addresses = Vec::new();
+		let breakpoints = arguments["breakpoints"]
+			.as_array()
+			.into_iter()
+			.flatten()
+			.map(|breakpoint| {
+				let line = breakpoint["line"].as_u64().unwrap_or_default() as usize;
+				let found = self
+					.source_map
+					.address(|file| same_file(Path::new(file), &path), line);
+				match found {
+					Some((address, source)) => {
+						addresses.push(address as Address);
+						json!({
+							"verified": true,
+							"line": source.line,
+							"instructionReference": format!("0x{:03X}", address),
+						})
+					}
+					None => json!({
+						"verified": false,
+						"line": line,
+						"message": "there is no code at or after this line",
+					}),
+				}
+			})
+			.collect::<Vec<_>>();
+		self.source_breakpoints.insert(path, addresses);
+		self.update_breakpoints();
+		Ok(json!({ "breakpoints": breakpoints }))
+	}
+
+	/// Handles `setInstructionBreakpoints`, which replaces the breakpoints set by address.
+	fn set_instruction_breakpoints(&mut self, arguments: &Value) -> Result<Value, String> {
+		let mut addresses = Vec::new();
+		let breakpoints = arguments["breakpoints"]
+			.as_array()
+			.into_iter()
+			.flatten()
+			.map(|breakpoint| {
+				let reference = breakpoint["instructionReference"]
+					.as_str()
+					.and_then(parse_number);
+				let offset = breakpoint["offset"].as_i64().unwrap_or_default();
+				let address = reference.map(|reference| reference as i64 + offset);
+				match address {
+					Some(address) if (0..self.vm.memory.len() as i64).contains(&address) => {
+						addresses.push(address as Address);
+						json!({ "verified": true, "instructionReference": format!("0x{:03X}", address) })
+					}
+					_ => json!({ "verified": false, "message": "invalid address" }),
+				}
+			})
+			.collect::<Vec<_>>();
+		self.instruction_breakpoints = addresses;
+		self.update_breakpoints();
+		Ok(json!({ "breakpoints": breakpoints }))
+	}
+
+	/// Gives the virtual machine every breakpoint.
+	fn update_breakpoints(&mut self) {
+		let mut addresses = self.user_breakpoints();
+		addresses.extend(self.step_breakpoint.map(|(address, _)| address));
+		self.vm.breakpoints = addresses
+			.into_iter()
+			.map(|address| (address, Breakpoint::new()))
+			.collect();
+	}
+
+	/// The breakpoints set by the user.
+	fn user_breakpoints(&self) -> BTreeSet<Address> {
+		self.source_breakpoints
+			.values()
+			.flatten()
+			.chain(&self.instruction_breakpoints)
+			.copied()
+			.collect()
+	}
+
+	/// Reports that the program stopped.
+	fn stopped(&mut self, reason: &str, description: Option<String>) {
+		self.running = false;
+		self.step_breakpoint = None;
+		self.update_breakpoints();
+		let mut body =
+			json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+		if let Some(description) = description {
+			body["description"] = json!(description);
+			body["text"] = body["description"].clone();
+		}
+		self.event("stopped", body);
+	}
+
+	/// Reports why the virtual machine paused itself.
+	fn paused(&mut self) {
+		if let Some(fault) = self.vm.fault {
+			self.stopped("exception", Some(fault.to_string()));
+		} else if let Some(address) = self.vm.breakpoint {
+			let user = self.user_breakpoints().contains(&address);
+			// Recursive calls return to the same address in deeper frames first.
+			let deeper = self
+				.step_breakpoint
+				.is_some_and(|(step, depth)| step == address && self.vm.stack.len() > depth);
+			if deeper && !user {
+				self.resume();
+				return;
+			}
+			self.stopped(if user { "breakpoint" } else { "step" }, None);
+		} else {
+			self.running = false;
+			self.exited = true;
+			self.event("exited", json!({ "exitCode": 0 }));
+			self.event("terminated", json!({}));
+		}
+	}
+
+	/// Lets the program run until it pauses.
+	fn resume(&mut self) {
+		self.vm.fault = None;
+		self.vm.paused = false;
+		self.running = true;
+	}
+
+	/// Runs a frame at the normal speed.
+	fn run_frame(&mut self) {
+		let start = Instant::now();
+		let _ = self.vm.run_frame();
+		if self.vm.paused {
+			self.paused();
+		} else {
+			thread::sleep(FRAME_TIME.saturating_sub(start.elapsed()));
+		}
+	}
+
+	/// Runs until a return to the address after the call at the top of the stack.
+	fn return_from_call(&mut self) {
+		let call = *self
+			.vm
+			.stack
+			.last()
+			.expect("there is a call to return from");
+		self.step_breakpoint = Some((call.wrapping_add(2), self.vm.stack.len() - 1));
+		self.update_breakpoints();
+		self.resume();
+	}
+
+	/// Executes instructions until a different line is reached, or a single instruction
+	/// without source. Calls are stepped over when `over` is set.
+	fn step(&mut self, over: bool) -> Value {
+		let depth = self.vm.stack.len();
+		let line = self
+			.source_map
+			.line(self.vm.program_counter as usize)
+			.cloned();
+		for _ in 0..STEP_LIMIT {
+			self.vm.fault = None;
+			self.vm.paused = false;
+			let _ = self.vm.execute();
+			if self.vm.paused {
+				self.paused();
+				return json!({});
+			}
+			self.vm.paused = true;
+			if over && self.vm.stack.len() > depth {
+				self.return_from_call();
+				return json!({});
+			}
+			let next = self.source_map.line(self.vm.program_counter as usize);
+			if line.is_none() || next.is_some_and(|next| Some(next) != line.as_ref()) {
+				break;
+			}
+		}
+		self.stopped("step", None);
+		json!({})
+	}
+
+	/// Runs until the current subroutine returns.
+	fn step_out(&mut self) -> Value {
+		if self.vm.stack.is_empty() {
+			self.resume();
+		} else {
+			self.return_from_call();
+		}
+		json!({})
+	}
+
+	/// The name of the subroutine called at an address, like the disassembler's labels.
+	fn subroutine(&self, call: Address) -> String {
+		match Instruction::decode(&self.vm.memory, call as usize, self.vm.mode) {
+			Some(Instruction::Branch(BranchInstruction {
+				branch_type: BranchType::Call,
+				branch_target: BranchTarget::Address(target),
+				..
+			})) => format!("sub_{:03X}", target),
+			_ => format!("0x{:03X}", call),
+		}
+	}
+
+	/// Handles `stackTrace`, with a frame for the program counter and each call on the stack.
+	fn stack_trace(&self) -> Value {
+		let addresses =
+			std::iter::once(self.vm.program_counter).chain(self.vm.stack.iter().rev().copied());
+		// Each frame is in the subroutine called by the frame below it.
+		let names = self
+			.vm
+			.stack
+			.iter()
+			.rev()
+			.map(|call| self.subroutine(*call))
+			.chain(std::iter::once("main".to_string()));
+		let frames = addresses
+			.zip(names)
+			.enumerate()
+			.map(|(id, (address, name))| {
+				let mut frame = json!({
+					"id": id,
+					"name": name,
+					"line": 0,
+					"column": 0,
+					"instructionPointerReference": format!("0x{:03X}", address),
+				});
+				if let Some(source) = self.source_map.line(address as usize) {
+					let name = Path::new(&source.file)
+						.file_name()
+						.map_or(source.file.clone(), |name| {
+							name.to_string_lossy().to_string()
+						});
+					frame["source"] = json!({ "name": name, "path": source.file });
+					frame["line"] = json!(source.line);
+					frame["column"] = json!(1);
+				}
+				frame
+			})
+			.collect::<Vec<_>>();
+		json!({ "stackFrames": frames, "totalFrames": frames.len() })
+	}
+
+	/// The variables in a scope, as names and values.
+	fn scope(&self, reference: u64) -> Vec<(String, String)> {
+		let vm = &self.vm;
+		match reference {
+			REGISTERS => {
+				let mut variables = vm
+					.registers
+					.iter()
+					.enumerate()
+					.map(|(index, value)| (format!("V{:X}", index), format!("0x{:02X}", value)))
+					.collect::<Vec<_>>();
+				variables.push(("I".to_string(), format!("0x{:03X}", vm.index_register)));
+				variables.push(("PC".to_string(), format!("0x{:03X}", vm.program_counter)));
+				variables
+			}
+			TIMERS => vec![
+				("DT".to_string(), vm.delay_timer.to_string()),
+				("ST".to_string(), vm.sound_timer.to_string()),
+			],
+			_ => Vec::new(),
+		}
+	}
+
+	/// Handles `variables`.
+	fn variables(&self, arguments: &Value) -> Value {
+		let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+		let variables = self
+			.scope(reference)
+			.into_iter()
+			.map(|(name, value)| json!({ "name": name, "value": value, "variablesReference": 0 }))
+			.collect::<Vec<_>>();
+		json!({ "variables": variables })
+	}
+
+	/// Handles `setVariable`, which changes a register or timer.
+	fn set_variable(&mut self, arguments: &Value) -> Result<Value, String> {
+		let name = arguments["name"].as_str().unwrap_or_default();
+		let text = arguments["value"].as_str().unwrap_or_default();
+		let value = parse_number(text).ok_or_else(|| format!("invalid number `{}`", text))?;
+		let byte = || u8::try_from(value).map_err(|_| format!("{} doesn't fit in a byte", text));
+		let word = || u16::try_from(value).map_err(|_| format!("{} doesn't fit in 16 bits", text));
+		let vm = &mut self.vm;
+		match name {
+			"I" => vm.index_register = word()?,
+			"PC" => vm.program_counter = word()?,
+			"DT" => vm.delay_timer = byte()?,
+			"ST" => vm.sound_timer = byte()?,
+			_ => {
+				let register = name
+					.strip_prefix('V')
+					.and_then(|register| usize::from_str_radix(register, 16).ok())
+					.filter(|register| *register < vm.registers.len())
+					.ok_or_else(|| format!("unknown variable `{}`", name))?;
+				vm.registers[register] = byte()?;
+			}
+		}
+		let reference = arguments["variablesReference"].as_u64().unwrap_or_default();
+		let value = self
+			.scope(reference)
+			.into_iter()
+			.find(|(variable, _)| variable == name)
+			.map_or(text.to_string(), |(_, value)| value);
+		Ok(json!({ "value": value }))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Counts V0 down from 3, calling the subroutine at 0x206 once for each count.
+	const RECURSION: [u8; 18] = [
+		0x60, 0x03, // LD V0, 3
+		0x22, 0x06, // CALL 0x206
+		0x12, 0x04, // JP 0x204
+		0x30, 0x00, // SE V0, 0
+		0x12, 0x0C, // JP 0x20C
+		0x00, 0xEE, // RET
+		0x70, 0xFF, // ADD V0, 0xFF
+		0x22, 0x06, // CALL 0x206
+		0x00, 0xEE, // RET
+	];
+
+	/// A session paused at the start of the subroutine, called `depth` times.
+	fn paused_in_recursion(depth: usize) -> Session<Vec<u8>> {
+		let mut session = Session::new(Vec::new());
+		session.vm.load_program(&RECURSION).unwrap();
+		session.vm.paused = false;
+		while session.vm.program_counter != 0x206 || session.vm.stack.len() != depth {
+			session.vm.execute().unwrap();
+		}
+		session.vm.paused = true;
+		session
+	}
+
+	fn run_until_stopped(session: &mut Session<Vec<u8>>) {
+		while session.running {
+			session.run_frame();
+		}
+	}
+
+	#[test]
+	fn step_out_returns_to_the_calling_frame() {
+		let mut session = paused_in_recursion(2);
+		session.step_out();
+		run_until_stopped(&mut session);
+		assert_eq!(session.vm.program_counter, 0x210);
+		assert_eq!(session.vm.stack.len(), 1);
+		assert_eq!(session.step_breakpoint, None);
+	}
+
+	#[test]
+	fn next_steps_over_recursive_calls() {
+		let mut session = paused_in_recursion(1);
+		while session.vm.program_counter != 0x20E {
+			session.step(false);
+		}
+		session.step(true);
+		run_until_stopped(&mut session);
+		assert_eq!(session.vm.program_counter, 0x210);
+		assert_eq!(session.vm.stack.len(), 1);
+	}
+}
@@ -0,0 +1,39 @@
+use chippy_core::{disassembler::Disassembly, instruction::format::Syntax, vm::VmMode};
+use pico_args::Arguments;
+use std::{fs, path::PathBuf};
+
+/// Disassembles a ROM into source, printing it unless `-o` is given.
+pub fn run(mut arguments: Arguments) -> Result<(), String> {
+	let mode: VmMode = arguments
+		.opt_value_from_str("--mode")
+		.map_err(|error| error.to_string())?
+		.unwrap_or(VmMode::XoChip);
+	let syntax: Syntax = arguments
+		.opt_value_from_str("--syntax")
+		.map_err(|error| error.to_string())?
+		.unwrap_or(Syntax::Cowgod);
+	let output: Option<PathBuf> = arguments
+		.opt_value_from_os_str(["-o", "--output"], |path| {
+			Ok::<_, String>(PathBuf::from(path))
+		})
+		.map_err(|error| error.to_string())?;
+	let rom: PathBuf = arguments
+		.free_from_os_str(|path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let rest = arguments.finish();
+	if !rest.is_empty() {
+		return Err(format!("unexpected arguments: {:?}", rest));
+	}
+
+	let program = fs::read(&rom).map_err(|error| format!("{}: {}", rom.display(), error))?;
+	let source = Disassembly::new(&program, mode, mode.quirks()).source(syntax);
+	match output {
+		Some(output) => {
+			fs::write(&output, source).map_err(|error| format!("{}: {}", output.display(), error))
+		}
+		None => {
+			print!("{}", source);
+			Ok(())
+		}
+	}
+}
@@ -0,0 +1,80 @@
+use chippy_core::{
+	gdb::{Connection, GdbStub},
+	vm::{Vm, VmMode},
+};
+use pico_args::Arguments;
+use std::{fs, net::TcpListener, path::PathBuf};
+
+/// The port gdb servers conventionally listen on.
+const DEFAULT_PORT: u16 = 1234;
+
+/// Loads a ROM and serves a single gdb or lldb session for it.
+pub fn run(mut arguments: Arguments) -> Result<(), String> {
+	let mode: VmMode = arguments
+		.opt_value_from_str("--mode")
+		.map_err(|error| error.to_string())?
+		.unwrap_or(VmMode::XoChip);
+	let port: Option<u16> = arguments
+		.opt_value_from_str("--port")
+		.map_err(|error| error.to_string())?;
+	let socket: Option<PathBuf> = arguments
+		.opt_value_from_os_str("--socket", |path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let rom: PathBuf = arguments
+		.free_from_os_str(|path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let rest = arguments.finish();
+	if !rest.is_empty() {
+		return Err(format!("unexpected arguments: {:?}", rest));
+	}
+	if port.is_some() && socket.is_some() {
+		return Err("--port and --socket can't be used together".to_string());
+	}
+
+	let program = fs::read(&rom).map_err(|error| format!("{}: {}", rom.display(), error))?;
+	let mut vm = Vm::new();
+	vm.set_mode(mode);
+	vm.load_program(&program)
+		.map_err(|error| error.to_string())?;
+	match socket {
+		Some(socket) => serve_socket(&mut vm, socket),
+		None => {
+			// Only local debuggers can connect, as the protocol has no authentication.
+			let address = ("127.0.0.1", port.unwrap_or(DEFAULT_PORT));
+			let listener = TcpListener::bind(address)
+				.map_err(|error| format!("{}:{}: {}", address.0, address.1, error))?;
+			eprintln!("Waiting for a debugger on {}:{}", address.0, address.1);
+			let (stream, _) = listener.accept().map_err(|error| error.to_string())?;
+			serve(&mut vm, stream)
+		}
+	}
+}
+
+#[cfg(unix)]
+fn serve_socket(vm: &mut Vm, socket: PathBuf) -> Result<(), String> {
+	use std::os::unix::net::UnixListener;
+
+	let listener =
+		UnixListener::bind(&socket).map_err(|error| format!("{}: {}", socket.display(), error))?;
+	eprintln!("Waiting for a debugger on {}", socket.display());
+	let result = listener
+		.accept()
+		.map_err(|error| error.to_string())
+		.and_then(|(stream, _)| serve(vm, stream));
+	let _ = fs::remove_file(&socket);
+	result
+}
+
+#[cfg(not(unix))]
+fn serve_socket(_: &mut Vm, _: PathBuf) -> Result<(), String> {
+	Err("Unix sockets aren't supported on this platform".to_string())
+}
+
+/// Serves a session, until the debugger detaches or disconnects.
+fn serve(vm: &mut Vm, connection: impl Connection) -> Result<(), String> {
+	GdbStub::new(vm, connection)
+		.serve()
+		.map_err(|error| format!("debugger connection: {}", error))?;
+	eprintln!("Debugger disconnected");
+	Ok(())
+}
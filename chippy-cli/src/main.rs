@@ -0,0 +1,54 @@
+mod asm;
+mod dap;
+mod disasm;
+mod gdb;
+mod run;
+
+use pico_args::Arguments;
+use std::process;
+
+const USAGE: &str = "\
+Usage: chippy-cli <command> [options]
+
+Commands:
+  asm <source> [-o <rom>] [--mode <mode>]
+      Assembles Cowgod-style mnemonics into a ROM.
+  disasm <rom> [-o <source>] [--mode <mode>] [--syntax <syntax>]
+      Disassembles a ROM into source.
+  run --headless <rom> [--frames <n>] [--mode <mode>] [--speed <speed>]
+      [--key <key>@<frame>[+<frames>]]... [--keys <script>] [--screen <image>]
+      Runs a ROM without a window, then writes the display as PNG, PBM or
+      ASCII art (chosen by the extension, or printed) and prints the registers.
+      Exits with 2 if the virtual machine faults.
+  gdb <rom> [--mode <mode>] [--port <port> | --socket <path>]
+      Loads a ROM and waits for gdb or lldb to connect to it over the remote
+      serial protocol, on 127.0.0.1:1234 unless a port or Unix socket is given.
+  dap
+      Serves the Debug Adapter Protocol over stdin and stdout. The launch request
+      takes a ROM, .asm or .8o `program`, a `mode` and `stopOnEntry`.
+
+Modes: chip8, vip, chip48, schip, xochip (default)
+Syntaxes: cowgod (default), octo, hex
+Speeds: an instruction count per frame, unlimited or vip";
+
+fn main() {
+	let mut arguments = Arguments::from_env();
+	if arguments.contains(["-h", "--help"]) {
+		println!("{}", USAGE);
+		return;
+	}
+	let result = match arguments.subcommand() {
+		Ok(Some(command)) if command == "asm" => asm::run(arguments),
+		Ok(Some(command)) if command == "disasm" => disasm::run(arguments),
+		Ok(Some(command)) if command == "run" => run::run(arguments),
+		Ok(Some(command)) if command == "gdb" => gdb::run(arguments),
+		Ok(Some(command)) if command == "dap" => dap::run(arguments),
+		Ok(Some(command)) => Err(format!("unknown command `{}`\n\n{}", command, USAGE)),
+		Ok(None) => Err(USAGE.to_string()),
+		Err(error) => Err(error.to_string()),
+	};
+	if let Err(error) = result {
+		eprintln!("{}", error);
+		process::exit(1);
+	}
+}
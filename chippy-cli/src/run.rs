@@ -0,0 +1,192 @@
+use chippy_core::{
+	display::{Display, PALETTE},
+	vm::{Speed, Vm, VmMode},
+};
+use pico_args::Arguments;
+use std::{fs, path::PathBuf, process, str::FromStr};
+
+/// The exit code when the virtual machine faults, to tell it apart from bad arguments.
+const FAULT_EXIT_CODE: i32 = 2;
+
+/// A key held down for some frames.
+#[derive(Debug, Clone, Copy)]
+struct KeyPress {
+	key: usize,
+	frame: usize,
+	frames: usize,
+}
+
+impl FromStr for KeyPress {
+	type Err = String;
+
+	/// Parses `KEY@FRAME` or `KEY@FRAME+FRAMES`, such as `5@30+10`.
+	fn from_str(press: &str) -> Result<Self, Self::Err> {
+		let invalid = || format!("invalid key press `{}`, expected KEY@FRAME[+FRAMES]", press);
+		let (key, frames) = press.split_once('@').ok_or_else(invalid)?;
+		let (frame, length) = frames.split_once('+').unwrap_or((frames, "1"));
+		let key = usize::from_str_radix(key.trim(), 16)
+			.ok()
+			.filter(|key| *key <= 0xF)
+			.ok_or_else(invalid)?;
+		let frame: usize = frame.trim().parse().map_err(|_| invalid())?;
+		let frames: usize = length.trim().parse().map_err(|_| invalid())?;
+		// The press has to end on a frame that can be counted to.
+		frame.checked_add(frames).ok_or_else(invalid)?;
+		Ok(KeyPress { key, frame, frames })
+	}
+}
+
+/// Parses a speed, which is `vip`, `unlimited` or a number of instructions per frame.
+fn parse_speed(speed: &str) -> Result<Speed, String> {
+	match speed.to_ascii_lowercase().as_str() {
+		"vip" => Ok(Speed::CosmacVip),
+		"unlimited" => Ok(Speed::Unlimited),
+		instructions => instructions
+			.parse()
+			.map(Speed::InstructionsPerFrame)
+			.map_err(|_| format!("invalid speed `{}`", speed)),
+	}
+}
+
+/// Runs a ROM without a window, then writes the display and prints the registers.
+pub fn run(mut arguments: Arguments) -> Result<(), String> {
+	// There is never a window, but the flag makes scripts say what they mean.
+	arguments.contains("--headless");
+	let mode: VmMode = arguments
+		.opt_value_from_str("--mode")
+		.map_err(|error| error.to_string())?
+		.unwrap_or(VmMode::XoChip);
+	let speed = arguments
+		.opt_value_from_fn("--speed", parse_speed)
+		.map_err(|error| error.to_string())?;
+	let frames: usize = arguments
+		.opt_value_from_str("--frames")
+		.map_err(|error| error.to_string())?
+		.unwrap_or(60);
+	let mut presses: Vec<KeyPress> = arguments
+		.values_from_str("--key")
+		.map_err(|error| error.to_string())?;
+	let script: Option<PathBuf> = arguments
+		.opt_value_from_os_str("--keys", |path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let screen: Option<PathBuf> = arguments
+		.opt_value_from_os_str("--screen", |path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let rom: PathBuf = arguments
+		.free_from_os_str(|path| Ok::<_, String>(PathBuf::from(path)))
+		.map_err(|error| error.to_string())?;
+	let rest = arguments.finish();
+	if !rest.is_empty() {
+		return Err(format!("unexpected arguments: {:?}", rest));
+	}
+
+	// Key scripts have a key press on each line, with # comments.
+	if let Some(script) = script {
+		let source = fs::read_to_string(&script)
+			.map_err(|error| format!("{}: {}", script.display(), error))?;
+		for (index, line) in source.lines().enumerate() {
+			let line = line.split('#').next().unwrap_or_default().trim();
+			if !line.is_empty() {
+				let press = line
+					.parse()
+					.map_err(|error| format!("{}:{}: {}", script.display(), index + 1, error))?;
+				presses.push(press);
+			}
+		}
+	}
+
+	let program = fs::read(&rom).map_err(|error| format!("{}: {}", rom.display(), error))?;
+	let mut vm = Vm::new();
+	vm.set_mode(mode);
+	if let Some(speed) = speed {
+		vm.speed = speed;
+	}
+	vm.load_program(&program)
+		.map_err(|error| error.to_string())?;
+	for frame in 0..frames {
+		vm.keypad.set_all(false);
+		for press in &presses {
+			if (press.frame..press.frame + press.frames).contains(&frame) {
+				vm.keypad.set(press.key, true);
+			}
+		}
+		if vm.run_frame().is_err() {
+			break;
+		}
+	}
+
+	match screen {
+		Some(path) => {
+			let image = match path.extension().and_then(|extension| extension.to_str()) {
+				Some("png") => png(&vm.display)?,
+				Some("pbm") => vm.display.to_pbm(),
+				_ => vm.display.to_ascii().into_bytes(),
+			};
+			fs::write(&path, image).map_err(|error| format!("{}: {}", path.display(), error))?;
+		}
+		None => print!("{}", vm.display.to_ascii()),
+	}
+	print!("{}", register_dump(&vm));
+	if let Some(fault) = vm.fault {
+		eprintln!("fault: {}", fault);
+		process::exit(FAULT_EXIT_CODE);
+	}
+	Ok(())
+}
+
+/// Encodes the display as an indexed PNG image.
+fn png(display: &Display) -> Result<Vec<u8>, String> {
+	let mut image = Vec::new();
+	let mut encoder =
+		png::Encoder::new(&mut image, display.width() as u32, display.height() as u32);
+	encoder.set_color(png::ColorType::Indexed);
+	encoder.set_depth(png::BitDepth::Eight);
+	encoder.set_palette(PALETTE.concat());
+	let pixels = (0..display.height())
+		.flat_map(|y| (0..display.width()).map(move |x| display.pixel(x, y) as u8))
+		.collect::<Vec<_>>();
+	encoder
+		.write_header()
+		.and_then(|mut writer| writer.write_image_data(&pixels))
+		.map_err(|error| error.to_string())?;
+	Ok(image)
+}
+
+/// The state of the CPU, for comparing runs.
+fn register_dump(vm: &Vm) -> String {
+	let mut dump = format!(
+		"PC 0x{:03X}  I 0x{:03X}  DT {}  ST {}  frames {}  cycles {}\n",
+		vm.program_counter, vm.index_register, vm.delay_timer, vm.sound_timer, vm.frames, vm.cycles
+	);
+	for (index, value) in vm.registers.iter().enumerate() {
+		dump += &format!("V{:X} 0x{:02X}", index, value);
+		dump.push(if index % 8 == 7 { '\n' } else { ' ' });
+	}
+	let stack = vm
+		.stack
+		.iter()
+		.map(|address| format!("0x{:03X}", address))
+		.collect::<Vec<_>>();
+	dump += &format!("Stack [{}]\n", stack.join(", "));
+	dump
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parses_key_presses() {
+		let press: KeyPress = "a@30+10".parse().unwrap();
+		assert_eq!((press.key, press.frame, press.frames), (0xA, 30, 10));
+		let press: KeyPress = format!("5@{}", usize::MAX - 1).parse().unwrap();
+		assert_eq!(
+			(press.key, press.frame, press.frames),
+			(5, usize::MAX - 1, 1)
+		);
+		let overflowing = format!("5@{}+2", usize::MAX);
+		for invalid in ["5", "10@1", "5@x", "5@1+", &overflowing] {
+			assert!(invalid.parse::<KeyPress>().is_err(), "{}", invalid);
+		}
+	}
+}
@@ -0,0 +1,72 @@
+//! Replays a recorded Debug Adapter Protocol session against `chippy-cli dap`.
+//!
+//! The requests in `tests/dap/requests.jsonl` are sent one at a time, and every
+//! message the server sends back is compared against `tests/dap/transcript.jsonl`,
+//! which has one message on each line.
+
+use serde_json::Value;
+use std::{
+	fs,
+	io::{BufRead, BufReader, Write},
+	path::PathBuf,
+	process::{Command, Stdio},
+};
+
+/// The requests that run the program, which are followed by an event when it stops.
+const RUNNING: [&str; 5] = ["configurationDone", "continue", "next", "stepIn", "stepOut"];
+
+/// Reads a message framed by a `Content-Length` header.
+fn read_message(output: &mut impl BufRead) -> Value {
+	let mut length = 0;
+	loop {
+		let mut line = String::new();
+		output.read_line(&mut line).unwrap();
+		let line = line.trim_end();
+		if line.is_empty() {
+			break;
+		}
+		if let Some(value) = line.strip_prefix("Content-Length:") {
+			length = value.trim().parse().unwrap();
+		}
+	}
+	let mut body = vec![0; length];
+	output.read_exact(&mut body).unwrap();
+	serde_json::from_slice(&body).unwrap()
+}
+
+#[test]
+fn session() {
+	let directory = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+	let mut server = Command::new(env!("CARGO_BIN_EXE_chippy-cli"))
+		.arg("dap")
+		.current_dir(&directory)
+		.stdin(Stdio::piped())
+		.stdout(Stdio::piped())
+		.spawn()
+		.unwrap();
+	let mut input = server.stdin.take().unwrap();
+	let mut output = BufReader::new(server.stdout.take().unwrap());
+
+	let requests = fs::read_to_string(directory.join("tests/dap/requests.jsonl")).unwrap();
+	let mut transcript = Vec::new();
+	for request in requests.lines().filter(|line| !line.trim().is_empty()) {
+		let request: Value = serde_json::from_str(request).unwrap();
+		let body = request.to_string();
+		write!(input, "Content-Length: {}\r\n\r\n{}", body.len(), body).unwrap();
+		input.flush().unwrap();
+		// Wait for the response, and for the program to stop if the request ran it.
+		let mut responded = false;
+		let mut stopped = !RUNNING.contains(&request["command"].as_str().unwrap());
+		while !responded || !stopped {
+			let message = read_message(&mut output);
+			responded |= message["request_seq"] == request["seq"];
+			stopped |= matches!(message["event"].as_str(), Some("stopped" | "terminated"));
+			transcript.push(message.to_string());
+		}
+	}
+	drop(input);
+	assert!(server.wait().unwrap().success());
+
+	let expected = fs::read_to_string(directory.join("tests/dap/transcript.jsonl")).unwrap();
+	assert_eq!(transcript, expected.lines().collect::<Vec<_>>());
+}
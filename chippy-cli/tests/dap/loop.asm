@@ -0,0 +1,11 @@
+; Counts up in V0 until it reaches 3, copying it into V1 in a subroutine.
+	LD V0, 0
+loop:
+	ADD V0, 1
+	CALL copy
+	SE V0, 3
+	JP loop
+	EXIT
+copy:
+	LD V1, V0
+	RET
@@ -0,0 +1,998 @@
+use crate::{
+	instruction::{
+		arthimetic::{ArthimeticInstruction, ArthimeticOp, ArthimeticValue},
+		branch::{BranchInstruction, BranchTarget, BranchType},
+		format::Syntax,
+		load::{LoadInstruction, LoadTarget},
+		logical::{LogicalInstruction, LogicalOp},
+		Address, Instruction, Register,
+	},
+	vm::{VmMode, PROGRAM_START},
+};
+use std::{
+	collections::{BTreeMap, HashMap},
+	fs,
+	path::{Path, PathBuf},
+};
+
+/// How deeply include files may be nested.
+const MAX_INCLUDE_DEPTH: usize = 16;
+/// How deeply constants may refer to other constants.
+const MAX_CONSTANT_DEPTH: usize = 32;
+/// The mnemonics the assembler knows, used to tell unknown instructions from bad operands.
+const MNEMONICS: [&str; 32] = [
+	"CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN",
+	"SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH",
+	"PLANE", "AUDIO", "DB", "DW", "INCLUDE",
+];
+
+/// An error in assembly source.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display(fmt = "{}:{}:{}: {}", file, line, column, message)]
+pub struct AsmError {
+	/// The file the error is in.
+	pub file: String,
+	/// The line of the error, starting at 1.
+	pub line: usize,
+	/// The column of the error, starting at 1.
+	pub column: usize,
+	/// What went wrong.
+	pub message: String,
+}
+
+impl std::error::Error for AsmError {}
+
+/// A line of source that code was assembled or compiled from.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display(fmt = "{}:{}", file, line)]
+pub struct SourceLine {
+	/// The file the line is in.
+	pub file: String,
+	/// The line, starting at 1.
+	pub line: usize,
+}
+
+/// Where the instructions of a program came from, so that debuggers can show source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+	/// The source line of the instruction at each address.
+	pub lines: BTreeMap<usize, SourceLine>,
+}
+
+impl SourceMap {
+	/// The source line of the instruction at an address.
+	pub fn line(&self, address: usize) -> Option<&SourceLine> {
+		self.lines.get(&address)
+	}
+
+	/// The first instruction at or after a line of a file, with the line it's on.
+	/// Files are compared with `same_file`, as paths may be spelled differently.
+	pub fn address(
+		&self,
+		same_file: impl Fn(&str) -> bool,
+		line: usize,
+	) -> Option<(usize, &SourceLine)> {
+		self.lines
+			.iter()
+			.filter(|(_, source)| source.line >= line && same_file(&source.file))
+			.min_by_key(|(address, source)| (source.line, **address))
+			.map(|(address, source)| (*address, source))
+	}
+}
+
+/// Where a piece of source came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Location {
+	file: String,
+	line: usize,
+	column: usize,
+}
+
+impl Location {
+	fn error(&self, message: impl Into<String>) -> AsmError {
+		AsmError {
+			file: self.file.clone(),
+			line: self.line,
+			column: self.column,
+			message: message.into(),
+		}
+	}
+}
+
+/// A piece of source text, such as a mnemonic or an operand.
+#[derive(Debug, Clone)]
+struct Token {
+	text: String,
+	location: Location,
+}
+
+/// A statement that produces bytes.
+#[derive(Debug, Clone)]
+enum Statement {
+	Instruction {
+		mnemonic: Token,
+		operands: Vec<Token>,
+	},
+	Bytes(Vec<Token>),
+	Words(Vec<Token>),
+}
+
+/// A name defined in the source.
+#[derive(Debug, Clone)]
+enum Symbol {
+	Label(usize),
+	Constant(Token),
+}
+
+/// An instruction operand.
+#[derive(Debug, Clone)]
+enum Operand {
+	V(Register),
+	VRange(Register, Register),
+	I,
+	IndirectI,
+	DelayTimer,
+	SoundTimer,
+	Key,
+	Font,
+	BigFont,
+	Bcd,
+	Rpl,
+	Pitch,
+	Long(Token),
+	Expression(Token),
+}
+
+/// Assembles Cowgod-style mnemonics into a program that is loaded at 0x200.
+/// Included files are found relative to the current directory.
+pub fn assemble(source: &str, mode: VmMode) -> Result<Vec<u8>, Vec<AsmError>> {
+	Assembler::new(mode)
+		.run(source, "<source>", Path::new("."))
+		.map(|(rom, _)| rom)
+}
+
+/// Assembles a source file, finding included files relative to it.
+pub fn assemble_file(path: &Path, mode: VmMode) -> Result<Vec<u8>, Vec<AsmError>> {
+	assemble_file_with_map(path, mode).map(|(rom, _)| rom)
+}
+
+/// Assembles a source file, along with the source line of each instruction.
+pub fn assemble_file_with_map(
+	path: &Path,
+	mode: VmMode,
+) -> Result<(Vec<u8>, SourceMap), Vec<AsmError>> {
+	let source = fs::read_to_string(path).map_err(|error| {
+		vec![AsmError {
+			file: path.display().to_string(),
+			line: 0,
+			column: 0,
+			message: error.to_string(),
+		}]
+	})?;
+	let directory = path.parent().unwrap_or_else(|| Path::new("."));
+	Assembler::new(mode).run(&source, &path.display().to_string(), directory)
+}
+
+struct Assembler {
+	mode: VmMode,
+	statements: Vec<(usize, Statement)>,
+	symbols: HashMap<String, Symbol>,
+	address: usize,
+	errors: Vec<AsmError>,
+}
+
+impl Assembler {
+	fn new(mode: VmMode) -> Self {
+		Assembler {
+			mode,
+			statements: Vec::new(),
+			symbols: HashMap::new(),
+			address: PROGRAM_START,
+			errors: Vec::new(),
+		}
+	}
+
+	fn run(
+		mut self,
+		source: &str,
+		file: &str,
+		directory: &Path,
+	) -> Result<(Vec<u8>, SourceMap), Vec<AsmError>> {
+		self.read(source, file, directory, 0);
+		let mut rom = Vec::new();
+		let mut source_map = SourceMap::default();
+		for (address, statement) in std::mem::take(&mut self.statements) {
+			debug_assert_eq!(address, PROGRAM_START + rom.len());
+			if let Statement::Instruction { mnemonic, .. } = &statement {
+				let location = &mnemonic.location;
+				source_map.lines.insert(
+					address,
+					SourceLine {
+						file: location.file.clone(),
+						line: location.line,
+					},
+				);
+			}
+			match self.emit(&statement) {
+				Ok(bytes) => rom.extend(bytes),
+				Err(error) => {
+					self.errors.push(error);
+					// Keep later addresses in line with the first pass.
+					rom.resize(rom.len() + statement_size(&statement), 0);
+				}
+			}
+		}
+		if self.errors.is_empty() {
+			Ok((rom, source_map))
+		} else {
+			Err(self.errors)
+		}
+	}
+
+	/// The first pass, which splits lines into statements and defines symbols.
+	fn read(&mut self, source: &str, file: &str, directory: &Path, depth: usize) {
+		for (index, line) in source.lines().enumerate() {
+			let location = |column: usize| Location {
+				file: file.to_string(),
+				line: index + 1,
+				column: column + 1,
+			};
+			let mut rest = strip_comment(line);
+			let mut column = 0;
+			// A label starts the line and ends with a colon.
+			if let Some(colon) = rest.find(':') {
+				let label = rest[..colon].trim();
+				if is_identifier(label) {
+					let label_column = rest.find(label).unwrap_or(0);
+					self.define(label, Symbol::Label(self.address), location(label_column));
+					column = colon + 1;
+					rest = &rest[colon + 1..];
+				}
+			}
+			let trimmed = rest.trim_start();
+			if trimmed.is_empty() {
+				continue;
+			}
+			column += rest.len() - trimmed.len();
+			let rest = trimmed.trim_end();
+			let (word, arguments) = match rest.find(char::is_whitespace) {
+				Some(end) => (&rest[..end], &rest[end..]),
+				None => (rest, ""),
+			};
+			let arguments_column = column + word.len();
+			// NAME EQU expression
+			let trimmed_arguments = arguments.trim_start();
+			if trimmed_arguments
+				.get(..3)
+				.is_some_and(|equ| equ.eq_ignore_ascii_case("EQU"))
+				&& trimmed_arguments[3..].starts_with(char::is_whitespace)
+			{
+				let value_offset = arguments.len() - trimmed_arguments.len() + 3;
+				let value = &arguments[value_offset..];
+				let value_column =
+					arguments_column + value_offset + (value.len() - value.trim_start().len());
+				if !is_identifier(word) {
+					self.errors
+						.push(location(column).error(format!("invalid constant name `{}`", word)));
+					continue;
+				}
+				let token = Token {
+					text: value.trim().to_string(),
+					location: location(value_column),
+				};
+				self.define(word, Symbol::Constant(token), location(column));
+				continue;
+			}
+			let mnemonic = Token {
+				text: word.to_ascii_uppercase(),
+				location: location(column),
+			};
+			let operands = split_operands(arguments, arguments_column, &location);
+			let statement = match mnemonic.text.as_str() {
+				"INCLUDE" => {
+					self.include(&mnemonic, &operands, directory, depth);
+					continue;
+				}
+				"DB" => Statement::Bytes(operands),
+				"DW" => Statement::Words(operands),
+				_ => Statement::Instruction { mnemonic, operands },
+			};
+			let size = statement_size(&statement);
+			self.statements.push((self.address, statement));
+			self.address += size;
+		}
+	}
+
+	fn include(&mut self, mnemonic: &Token, operands: &[Token], directory: &Path, depth: usize) {
+		let path = match operands {
+			[path]
+				if path.text.len() >= 2
+					&& path.text.starts_with('"')
+					&& path.text.ends_with('"') =>
+			{
+				path
+			}
+			_ => {
+				self.errors.push(
+					mnemonic
+						.location
+						.error("expected a quoted file name to include"),
+				);
+				return;
+			}
+		};
+		if depth >= MAX_INCLUDE_DEPTH {
+			self.errors
+				.push(path.location.error("includes are nested too deeply"));
+			return;
+		}
+		let file: PathBuf = directory.join(&path.text[1..path.text.len() - 1]);
+		match fs::read_to_string(&file) {
+			Ok(source) => {
+				let directory = file.parent().unwrap_or(directory).to_path_buf();
+				self.read(&source, &file.display().to_string(), &directory, depth + 1);
+			}
+			Err(error) => self.errors.push(path.location.error(format!(
+				"can't include {}: {}",
+				file.display(),
+				error
+			))),
+		}
+	}
+
+	fn define(&mut self, name: &str, symbol: Symbol, location: Location) {
+		if self.symbols.contains_key(name) {
+			self.errors
+				.push(location.error(format!("`{}` is already defined", name)));
+		} else {
+			self.symbols.insert(name.to_string(), symbol);
+		}
+	}
+
+	/// The second pass, which turns a statement into bytes.
+	fn emit(&self, statement: &Statement) -> Result<Vec<u8>, AsmError> {
+		match statement {
+			Statement::Bytes(values) => values
+				.iter()
+				.map(|value| Ok(self.value(value, -0x80, 0xFF)? as u8))
+				.collect(),
+			Statement::Words(values) => {
+				let mut bytes = Vec::new();
+				for value in values {
+					bytes.extend_from_slice(
+						&(self.value(value, -0x8000, 0xFFFF)? as u16).to_be_bytes(),
+					);
+				}
+				Ok(bytes)
+			}
+			Statement::Instruction { mnemonic, operands } => {
+				let instruction = self.instruction(mnemonic, operands)?;
+				instruction.encode(self.mode).ok_or_else(|| {
+					mnemonic.location.error(format!(
+						"`{}` is not supported in {} mode",
+						Syntax::Cowgod.instruction(
+							&instruction,
+							self.mode,
+							&self.mode.quirks(),
+							|_| None
+						),
+						self.mode
+					))
+				})
+			}
+		}
+	}
+
+	fn instruction(&self, mnemonic: &Token, operands: &[Token]) -> Result<Instruction, AsmError> {
+		let operands = operands.iter().map(operand).collect::<Vec<_>>();
+		let address = |token: &Token| Ok::<_, AsmError>(self.value(token, 0, 0xFFF)? as Address);
+		let byte = |token: &Token| Ok::<_, AsmError>(self.value(token, -0x80, 0xFF)? as u8);
+		let nibble = |token: &Token| Ok::<_, AsmError>(self.value(token, 0, 0xF)? as u8);
+		let load = |from, into| Instruction::Load(LoadInstruction { from, into });
+		let skip = |branch_type, inverted| {
+			Instruction::Branch(BranchInstruction::new(
+				branch_type,
+				BranchTarget::Skip,
+				inverted,
+			))
+		};
+		let arthimetic = |op, values, carry_flag, inverted| {
+			Instruction::Arthimetic(ArthimeticInstruction {
+				op,
+				values,
+				carry_flag,
+				inverted,
+			})
+		};
+		let logical = |op, register_a, register_b| {
+			Instruction::Logical(LogicalInstruction {
+				op,
+				register_a,
+				register_b,
+			})
+		};
+		use Operand::*;
+		let instruction = match (mnemonic.text.as_str(), operands.as_slice()) {
+			("CLS", []) => Instruction::Clear,
+			("RET", []) => Instruction::Return,
+			("SYS", [Expression(target)]) => Instruction::Sys(address(target)?),
+			("JP", [Expression(target)]) => Instruction::Branch(BranchInstruction::new(
+				BranchType::Unconditional,
+				BranchTarget::Address(address(target)?),
+				false,
+			)),
+			// Bxnn jumps with Vx when the jump-with-Vx quirk is on, so x is the top of the address.
+			("JP", [V(x), Expression(target_token)]) => {
+				let target = address(target_token)?;
+				if *x != 0 && *x as Address != target >> 8 {
+					return Err(target_token.location.error(format!(
+						"`JP V{:X}` needs an address from 0x{:X}00 to 0x{:X}FF",
+						x, x, x
+					)));
+				}
+				Instruction::Branch(BranchInstruction::new(
+					BranchType::Unconditional,
+					BranchTarget::AddressOffset(target, (target >> 8) as Register),
+					false,
+				))
+			}
+			("CALL", [Expression(target)]) => Instruction::Branch(BranchInstruction::new(
+				BranchType::Call,
+				BranchTarget::Address(address(target)?),
+				false,
+			)),
+			("SE", [V(x), Expression(value)]) | ("SNE", [V(x), Expression(value)]) => skip(
+				BranchType::Equal {
+					register: *x,
+					value: byte(value)?,
+				},
+				mnemonic.text == "SNE",
+			),
+			("SE", [V(x), V(y)]) | ("SNE", [V(x), V(y)]) => skip(
+				BranchType::EqualRegister {
+					register_a: *x,
+					register_b: *y,
+				},
+				mnemonic.text == "SNE",
+			),
+			("SKP", [V(x)]) | ("SKNP", [V(x)]) => skip(
+				BranchType::KeyPressed { register: *x },
+				mnemonic.text == "SKNP",
+			),
+			("LD", [V(x), Expression(value)]) => {
+				load(LoadTarget::Value(byte(value)?), LoadTarget::Register(*x))
+			}
+			("LD", [V(x), V(y)]) => load(LoadTarget::Register(*y), LoadTarget::Register(*x)),
+			("LD", [I, Expression(target)]) => {
+				load(LoadTarget::Address(address(target)?), LoadTarget::I)
+			}
+			("LD", [I, Long(target)]) => load(
+				LoadTarget::LongAddress(self.value(target, 0, 0xFFFF)? as Address),
+				LoadTarget::I,
+			),
+			("LD", [V(x), DelayTimer]) => load(LoadTarget::DelayTimer, LoadTarget::Register(*x)),
+			("LD", [V(x), Key]) => Instruction::LoadKey(*x),
+			("LD", [DelayTimer, V(x)]) => load(LoadTarget::Register(*x), LoadTarget::DelayTimer),
+			("LD", [SoundTimer, V(x)]) => load(LoadTarget::Register(*x), LoadTarget::SoundTimer),
+			("LD", [Font, V(x)]) => load(LoadTarget::Font(*x), LoadTarget::I),
+			("LD", [BigFont, V(x)]) => load(LoadTarget::BigFont(*x), LoadTarget::I),
+			("LD", [Bcd, V(x)]) => load(LoadTarget::Register(*x), LoadTarget::Bcd),
+			("LD", [IndirectI, V(x)]) => load(LoadTarget::Register(*x), LoadTarget::I),
+			("LD", [V(x), IndirectI]) => load(LoadTarget::I, LoadTarget::Register(*x)),
+			("LD", [IndirectI, VRange(x, y)]) => {
+				load(LoadTarget::RegisterRange(*x, *y), LoadTarget::I)
+			}
+			("LD", [VRange(x, y), IndirectI]) => {
+				load(LoadTarget::I, LoadTarget::RegisterRange(*x, *y))
+			}
+			("LD", [Rpl, V(x)]) => load(LoadTarget::Register(*x), LoadTarget::Rpl),
+			("LD", [V(x), Rpl]) => load(LoadTarget::Rpl, LoadTarget::Register(*x)),
+			("LD", [Pitch, V(x)]) => load(LoadTarget::Register(*x), LoadTarget::Pitch),
+			("ADD", [V(x), Expression(value)]) => arthimetic(
+				ArthimeticOp::Add,
+				ArthimeticValue::RegisterValue(*x, byte(value)?),
+				false,
+				false,
+			),
+			("ADD", [V(x), V(y)]) => arthimetic(
+				ArthimeticOp::Add,
+				ArthimeticValue::RegisterRegister(*x, *y),
+				true,
+				false,
+			),
+			("ADD", [I, V(x)]) => Instruction::AddI(*x),
+			("OR", [V(x), V(y)]) => logical(LogicalOp::Or, *x, *y),
+			("AND", [V(x), V(y)]) => logical(LogicalOp::And, *x, *y),
+			("XOR", [V(x), V(y)]) => logical(LogicalOp::Xor, *x, *y),
+			("SUB", [V(x), V(y)]) | ("SUBN", [V(x), V(y)]) => arthimetic(
+				ArthimeticOp::Sub,
+				ArthimeticValue::RegisterRegister(*x, *y),
+				true,
+				mnemonic.text == "SUBN",
+			),
+			// Without Vy, the register is shifted in place whatever the shift quirk is.
+			("SHR", [V(x)]) | ("SHR", [V(x), V(_)]) | ("SHL", [V(x)]) | ("SHL", [V(x), V(_)]) => {
+				let y = match operands.as_slice() {
+					[_, V(y)] => *y,
+					_ => *x,
+				};
+				let op = if mnemonic.text == "SHR" {
+					ArthimeticOp::Shr
+				} else {
+					ArthimeticOp::Shl
+				};
+				arthimetic(op, ArthimeticValue::RegisterRegister(*x, y), false, false)
+			}
+			("RND", [V(x), Expression(value)]) => Instruction::Random(*x, byte(value)?),
+			("DRW", [V(x), V(y), Expression(rows)]) => Instruction::Draw(*x, *y, nibble(rows)?),
+			("SCD", [Expression(rows)]) => Instruction::ScrollDown(nibble(rows)?),
+			("SCU", [Expression(rows)]) => Instruction::ScrollUp(nibble(rows)?),
+			("SCR", []) => Instruction::ScrollRight,
+			("SCL", []) => Instruction::ScrollLeft,
+			("EXIT", []) => Instruction::Exit,
+			("LOW", []) => Instruction::SetHighResolution(false),
+			("HIGH", []) => Instruction::SetHighResolution(true),
+			("PLANE", [Expression(planes)]) => Instruction::SelectPlane(nibble(planes)?),
+			("AUDIO", []) => load(LoadTarget::I, LoadTarget::AudioPattern),
+			(name, _) if MNEMONICS.contains(&name) => {
+				return Err(mnemonic
+					.location
+					.error(format!("invalid operands for `{}`", name)))
+			}
+			(name, _) => {
+				return Err(mnemonic
+					.location
+					.error(format!("unknown instruction `{}`", name)))
+			}
+		};
+		Ok(instruction)
+	}
+
+	/// Evaluates an expression, checking that it's between `min` and `max`.
+	fn value(&self, token: &Token, min: i64, max: i64) -> Result<i64, AsmError> {
+		let value = self.evaluate(token, 0)?;
+		if value < min || value > max {
+			return Err(token.location.error(format!(
+				"{} is out of range ({} to 0x{:X})",
+				value, min, max
+			)));
+		}
+		Ok(value)
+	}
+
+	fn evaluate(&self, token: &Token, depth: usize) -> Result<i64, AsmError> {
+		let mut parser = Expression {
+			assembler: self,
+			token,
+			text: token.text.as_bytes(),
+			position: 0,
+			depth,
+		};
+		let value = parser.expression()?;
+		parser.skip_whitespace();
+		if parser.position < parser.text.len() {
+			return Err(parser.error("unexpected character"));
+		}
+		Ok(value)
+	}
+}
+
+/// A recursive-descent parser for expressions with `+ - * / & | << >>` and parentheses.
+struct Expression<'a> {
+	assembler: &'a Assembler,
+	token: &'a Token,
+	text: &'a [u8],
+	position: usize,
+	depth: usize,
+}
+
+impl Expression<'_> {
+	fn error(&self, message: &str) -> AsmError {
+		self.error_at(self.position, message)
+	}
+
+	fn error_at(&self, position: usize, message: &str) -> AsmError {
+		let mut location = self.token.location.clone();
+		location.column += position;
+		location.error(format!("{} in `{}`", message, self.token.text))
+	}
+
+	fn skip_whitespace(&mut self) {
+		while self
+			.text
+			.get(self.position)
+			.is_some_and(u8::is_ascii_whitespace)
+		{
+			self.position += 1;
+		}
+	}
+
+	fn eat(&mut self, operator: &str) -> bool {
+		self.skip_whitespace();
+		if self.text[self.position..].starts_with(operator.as_bytes()) {
+			self.position += operator.len();
+			true
+		} else {
+			false
+		}
+	}
+
+	fn expression(&mut self) -> Result<i64, AsmError> {
+		let mut value = self.term()?;
+		loop {
+			if self.eat("|") {
+				value |= self.term()?;
+			} else if self.eat("&") {
+				value &= self.term()?;
+			} else if self.eat("<<") {
+				value = value.checked_shl(self.term()? as u32).unwrap_or(0);
+			} else if self.eat(">>") {
+				value = value.checked_shr(self.term()? as u32).unwrap_or(0);
+			} else if self.eat("+") {
+				value = value.wrapping_add(self.term()?);
+			} else if self.eat("-") {
+				value = value.wrapping_sub(self.term()?);
+			} else {
+				return Ok(value);
+			}
+		}
+	}
+
+	fn term(&mut self) -> Result<i64, AsmError> {
+		let mut value = self.factor()?;
+		loop {
+			if self.eat("*") {
+				value = value.wrapping_mul(self.factor()?);
+			} else if self.eat("/") {
+				let operator = self.position - 1;
+				let divisor = self.factor()?;
+				if divisor == 0 {
+					return Err(self.error_at(operator, "division by zero"));
+				}
+				value = value
+					.checked_div(divisor)
+					.ok_or_else(|| self.error_at(operator, "overflow"))?;
+			} else {
+				return Ok(value);
+			}
+		}
+	}
+
+	fn factor(&mut self) -> Result<i64, AsmError> {
+		if self.eat("-") {
+			let operator = self.position - 1;
+			return self
+				.factor()?
+				.checked_neg()
+				.ok_or_else(|| self.error_at(operator, "overflow"));
+		}
+		if self.eat("(") {
+			let value = self.expression()?;
+			if !self.eat(")") {
+				return Err(self.error("expected `)`"));
+			}
+			return Ok(value);
+		}
+		self.skip_whitespace();
+		let start = self.position;
+		while self.text.get(self.position).is_some_and(|c| {
+			c.is_ascii_alphanumeric() || *c == b'_' || *c == b'$' || *c == b'#' || *c == b'%'
+		}) {
+			self.position += 1;
+		}
+		let word = std::str::from_utf8(&self.text[start..self.position]).unwrap_or_default();
+		if word.is_empty() {
+			return Err(self.error("expected a value"));
+		}
+		if let Some(value) = parse_number(word) {
+			return Ok(value);
+		}
+		match self.assembler.symbols.get(word) {
+			Some(Symbol::Label(address)) => Ok(*address as i64),
+			Some(Symbol::Constant(value)) => {
+				if self.depth >= MAX_CONSTANT_DEPTH {
+					return Err(self.error("constants refer to each other too deeply"));
+				}
+				self.assembler.evaluate(value, self.depth + 1)
+			}
+			None => {
+				let mut location = self.token.location.clone();
+				location.column += start;
+				Err(location.error(format!("undefined symbol `{}`", word)))
+			}
+		}
+	}
+}
+
+/// Parses a decimal, hexadecimal (`0x`, `$` or `#`) or binary (`0b` or `%`) number.
+fn parse_number(word: &str) -> Option<i64> {
+	let lower = word.to_ascii_lowercase();
+	let (digits, radix) = if let Some(digits) = lower.strip_prefix("0x") {
+		(digits, 16)
+	} else if let Some(digits) = lower.strip_prefix('$').or_else(|| lower.strip_prefix('#')) {
+		(digits, 16)
+	} else if let Some(digits) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+		(digits, 2)
+	} else if lower.starts_with(|c: char| c.is_ascii_digit()) {
+		(lower.as_str(), 10)
+	} else {
+		return None;
+	};
+	i64::from_str_radix(digits, radix).ok()
+}
+
+/// Classifies an operand.
+fn operand(token: &Token) -> Operand {
+	let upper = token.text.to_ascii_uppercase();
+	let register = |name: &str| {
+		let digit = name.strip_prefix('V')?;
+		(digit.len() == 1)
+			.then(|| u8::from_str_radix(digit, 16).ok())
+			.flatten()
+	};
+	if let Some(x) = register(&upper) {
+		return Operand::V(x);
+	}
+	if let Some((first, last)) = upper.split_once('-') {
+		if let (Some(x), Some(y)) = (register(first.trim()), register(last.trim())) {
+			return Operand::VRange(x, y);
+		}
+	}
+	if upper.starts_with("LONG") && upper[4..].starts_with(char::is_whitespace) {
+		let expression = token.text[4..].trim_start();
+		let mut location = token.location.clone();
+		location.column += token.text.len() - expression.len();
+		return Operand::Long(Token {
+			text: expression.to_string(),
+			location,
+		});
+	}
+	match upper.as_str() {
+		"I" => Operand::I,
+		"[I]" => Operand::IndirectI,
+		"DT" => Operand::DelayTimer,
+		"ST" => Operand::SoundTimer,
+		"K" => Operand::Key,
+		"F" => Operand::Font,
+		"HF" => Operand::BigFont,
+		"B" => Operand::Bcd,
+		"R" => Operand::Rpl,
+		"PITCH" => Operand::Pitch,
+		_ => Operand::Expression(token.clone()),
+	}
+}
+
+/// The number of bytes a statement assembles into.
+fn statement_size(statement: &Statement) -> usize {
+	match statement {
+		Statement::Bytes(values) => values.len(),
+		Statement::Words(values) => values.len() * 2,
+		Statement::Instruction { mnemonic, operands } => {
+			let long = mnemonic.text == "LD"
+				&& operands
+					.iter()
+					.any(|operand| matches!(self::operand(operand), Operand::Long(_)));
+			if long {
+				4
+			} else {
+				2
+			}
+		}
+	}
+}
+
+/// Removes a `;` comment from a line, ignoring semicolons in quotes.
+fn strip_comment(line: &str) -> &str {
+	let mut quoted = false;
+	for (index, c) in line.char_indices() {
+		match c {
+			'"' => quoted = !quoted,
+			';' if !quoted => return &line[..index],
+			_ => {}
+		}
+	}
+	line
+}
+
+fn is_identifier(name: &str) -> bool {
+	name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+		&& name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits comma-separated operands, keeping track of their columns.
+fn split_operands(
+	arguments: &str,
+	column: usize,
+	location: &impl Fn(usize) -> Location,
+) -> Vec<Token> {
+	if arguments.trim().is_empty() {
+		return Vec::new();
+	}
+	let mut operands = Vec::new();
+	let mut start = 0;
+	for part in arguments.split(',') {
+		let leading = part.len() - part.trim_start().len();
+		operands.push(Token {
+			text: part.trim().to_string(),
+			location: location(column + start + leading),
+		});
+		start += part.len() + 1;
+	}
+	operands
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Assembles source that should have errors, returning where they are and what they say.
+	fn errors(source: &str, mode: VmMode) -> Vec<(usize, usize, String)> {
+		assemble(source, mode)
+			.unwrap_err()
+			.into_iter()
+			.map(|error| (error.line, error.column, error.message))
+			.collect()
+	}
+
+	#[test]
+	fn assembles_instructions() {
+		let source = "
+			start: LD V0, 0x12 ; load
+			ADD V0, V1
+			JP start
+			DB 1, -1
+			DW 0x1234
+		";
+		assert_eq!(
+			assemble(source, VmMode::Chip8),
+			Ok(vec![
+				0x60, 0x12, 0x80, 0x14, 0x12, 0x00, 0x01, 0xFF, 0x12, 0x34
+			])
+		);
+	}
+
+	#[test]
+	fn expressions() {
+		// Only `*` and `/` bind more tightly, and everything else is evaluated left to right.
+		let source =
+			"LD V0, (1 + 2) * 3 - -1\nLD V1, 1 << 4 | 0b11 & %10\nLD V2, 0x10 / 3 + $1 + #1";
+		assert_eq!(
+			assemble(source, VmMode::Chip8),
+			Ok(vec![0x60, 10, 0x61, 2, 0x62, 7])
+		);
+	}
+
+	#[test]
+	fn constants() {
+		let source = "
+			SIZE equ 4
+			TWICE EQU SIZE * 2
+			LD V0, TWICE
+			LD V1, LATER
+			LATER EQU 1
+		";
+		assert_eq!(assemble(source, VmMode::Chip8), Ok(vec![0x60, 8, 0x61, 1]));
+		assert_eq!(
+			errors("A EQU B\nB EQU A\nLD V0, A", VmMode::Chip8),
+			[(
+				2,
+				8,
+				"constants refer to each other too deeply in `A`".to_string()
+			)]
+		);
+		assert_eq!(
+			errors("A EQU 1\nA EQU 2\n1A EQU 3", VmMode::Chip8),
+			[
+				(2, 1, "`A` is already defined".to_string()),
+				(3, 1, "invalid constant name `1A`".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn error_positions() {
+		let source = "
+CLS
+  FOO V0
+LD V0, missing
+LD V0, 0x100
+LD V0, (1 + 2
+JP V1, 0x200
+lbl: DRW V0, V1, 1 / 0
+";
+		assert_eq!(
+			errors(source, VmMode::Chip8),
+			[
+				(3, 3, "unknown instruction `FOO`".to_string()),
+				(4, 8, "undefined symbol `missing`".to_string()),
+				(5, 8, "256 is out of range (-128 to 0xFF)".to_string()),
+				(6, 14, "expected `)` in `(1 + 2`".to_string()),
+				(
+					7,
+					8,
+					"`JP V1` needs an address from 0x100 to 0x1FF".to_string()
+				),
+				(8, 20, "division by zero in `1 / 0`".to_string()),
+			]
+		);
+	}
+
+	#[test]
+	fn overflow_is_an_error() {
+		assert_eq!(
+			errors("LD V0, -(0-9223372036854775807-1)", VmMode::Chip8),
+			[(1, 8, "overflow in `-(0-9223372036854775807-1)`".to_string())]
+		);
+		assert_eq!(
+			errors("LD V0, (0-9223372036854775807-1)/-1", VmMode::Chip8),
+			[(
+				1,
+				33,
+				"overflow in `(0-9223372036854775807-1)/-1`".to_string()
+			)]
+		);
+	}
+
+	#[test]
+	fn jumps_with_vx() {
+		assert_eq!(
+			assemble("JP V0, 0x234\nJP V2, 0x234", VmMode::SuperChip),
+			Ok(vec![0xB2, 0x34, 0xB2, 0x34])
+		);
+	}
+
+	#[test]
+	fn unsupported_instructions_are_written_as_mnemonics() {
+		assert_eq!(
+			errors("SCD 4", VmMode::Chip8),
+			[(1, 1, "`SCD 4` is not supported in Chip8 mode".to_string())]
+		);
+	}
+
+	#[test]
+	fn includes() {
+		let directory =
+			std::env::temp_dir().join(format!("chippy-assembler-{}", std::process::id()));
+		let sprites = directory.join("sprites");
+		fs::create_dir_all(&sprites).unwrap();
+		fs::write(
+			directory.join("main.asm"),
+			"LD I, sprite\nINCLUDE \"sprites/sprite.asm\"\nDB SIZE",
+		)
+		.unwrap();
+		fs::write(
+			sprites.join("sprite.asm"),
+			"SIZE EQU 2\nsprite: DB 0xFF, 0x81",
+		)
+		.unwrap();
+		fs::write(
+			directory.join("broken.asm"),
+			"INCLUDE \"sprites/bad.asm\"\nINCLUDE missing",
+		)
+		.unwrap();
+		fs::write(sprites.join("bad.asm"), "CLS\nLD V0, nowhere").unwrap();
+		fs::write(directory.join("loop.asm"), "INCLUDE \"loop.asm\"").unwrap();
+
+		let (rom, source_map) =
+			assemble_file_with_map(&directory.join("main.asm"), VmMode::Chip8).unwrap();
+		assert_eq!(rom, [0xA2, 0x02, 0xFF, 0x81, 0x02]);
+		assert_eq!(source_map.line(0x200).unwrap().line, 1);
+
+		let errors = assemble_file(&directory.join("broken.asm"), VmMode::Chip8).unwrap_err();
+		assert_eq!(errors.len(), 2);
+		assert_eq!(
+			(errors[0].line, errors[0].column, errors[0].message.as_str()),
+			(2, 1, "expected a quoted file name to include")
+		);
+		assert!(errors[1].file.ends_with("bad.asm"));
+		assert_eq!(
+			(errors[1].line, errors[1].column, errors[1].message.as_str()),
+			(2, 8, "undefined symbol `nowhere`")
+		);
+
+		let errors = assemble_file(&directory.join("loop.asm"), VmMode::Chip8).unwrap_err();
+		assert_eq!(errors[0].message, "includes are nested too deeply");
+
+		fs::remove_dir_all(&directory).unwrap();
+	}
+}
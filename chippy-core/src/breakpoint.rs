@@ -0,0 +1,441 @@
+use crate::vm::Vm;
+use std::str::FromStr;
+
+/// A breakpoint, which pauses the virtual machine before the instruction at its address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Breakpoint {
+	/// Whether the breakpoint pauses the virtual machine.
+	pub enabled: bool,
+	/// What must hold for the breakpoint to pause the virtual machine.
+	pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+	/// Creates an enabled breakpoint that always pauses the virtual machine.
+	pub fn new() -> Self {
+		Breakpoint {
+			enabled: true,
+			condition: None,
+		}
+	}
+
+	/// Whether the breakpoint pauses the virtual machine in its current state.
+	pub fn is_hit(&self, vm: &Vm) -> bool {
+		self.enabled
+			&& self
+				.condition
+				.as_ref()
+				.is_none_or(|condition| condition.is_met(vm))
+	}
+}
+
+impl Default for Breakpoint {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// A value the virtual machine has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+	Register(usize),
+	Index,
+	ProgramCounter,
+	DelayTimer,
+	SoundTimer,
+	StackDepth,
+}
+
+/// An operator between two values, from the lowest precedence to the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+	Or,
+	And,
+	BitOr,
+	BitXor,
+	BitAnd,
+	Equal,
+	NotEqual,
+	Less,
+	LessEqual,
+	Greater,
+	GreaterEqual,
+	ShiftLeft,
+	ShiftRight,
+	Add,
+	Subtract,
+}
+
+impl Operator {
+	/// The operators, with the longest spellings first so that they're matched first.
+	const ALL: [(&'static str, Operator); 15] = [
+		("||", Operator::Or),
+		("&&", Operator::And),
+		("==", Operator::Equal),
+		("!=", Operator::NotEqual),
+		("<=", Operator::LessEqual),
+		(">=", Operator::GreaterEqual),
+		("<<", Operator::ShiftLeft),
+		(">>", Operator::ShiftRight),
+		("|", Operator::BitOr),
+		("^", Operator::BitXor),
+		("&", Operator::BitAnd),
+		("<", Operator::Less),
+		(">", Operator::Greater),
+		("+", Operator::Add),
+		("-", Operator::Subtract),
+	];
+
+	/// How tightly the operator binds, like in C.
+	fn precedence(self) -> u8 {
+		match self {
+			Operator::Or => 1,
+			Operator::And => 2,
+			Operator::BitOr => 3,
+			Operator::BitXor => 4,
+			Operator::BitAnd => 5,
+			Operator::Equal | Operator::NotEqual => 6,
+			Operator::Less | Operator::LessEqual | Operator::Greater | Operator::GreaterEqual => 7,
+			Operator::ShiftLeft | Operator::ShiftRight => 8,
+			Operator::Add | Operator::Subtract => 9,
+		}
+	}
+
+	fn apply(self, a: i64, b: i64) -> i64 {
+		match self {
+			Operator::Or => (a != 0 || b != 0) as i64,
+			Operator::And => (a != 0 && b != 0) as i64,
+			Operator::BitOr => a | b,
+			Operator::BitXor => a ^ b,
+			Operator::BitAnd => a & b,
+			Operator::Equal => (a == b) as i64,
+			Operator::NotEqual => (a != b) as i64,
+			Operator::Less => (a < b) as i64,
+			Operator::LessEqual => (a <= b) as i64,
+			Operator::Greater => (a > b) as i64,
+			Operator::GreaterEqual => (a >= b) as i64,
+			Operator::ShiftLeft => a.wrapping_shl(b as u32),
+			Operator::ShiftRight => a.wrapping_shr(b as u32),
+			Operator::Add => a.wrapping_add(b),
+			Operator::Subtract => a.wrapping_sub(b),
+		}
+	}
+}
+
+/// A parsed condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expression {
+	Number(i64),
+	Variable(Variable),
+	/// The byte of memory at an address, written `[address]`.
+	Memory(Box<Expression>),
+	Not(Box<Expression>),
+	Negate(Box<Expression>),
+	Binary(Operator, Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+	fn evaluate(&self, vm: &Vm) -> i64 {
+		match self {
+			Expression::Number(value) => *value,
+			Expression::Variable(variable) => match variable {
+				Variable::Register(register) => vm.registers[*register] as i64,
+				Variable::Index => vm.index_register as i64,
+				Variable::ProgramCounter => vm.program_counter as i64,
+				Variable::DelayTimer => vm.delay_timer as i64,
+				Variable::SoundTimer => vm.sound_timer as i64,
+				Variable::StackDepth => vm.stack.len() as i64,
+			},
+			Expression::Memory(address) => usize::try_from(address.evaluate(vm))
+				.ok()
+				.and_then(|address| vm.memory.get(address))
+				.map_or(0, |byte| *byte as i64),
+			Expression::Not(value) => (value.evaluate(vm) == 0) as i64,
+			Expression::Negate(value) => value.evaluate(vm).wrapping_neg(),
+			Expression::Binary(operator, a, b) => operator.apply(a.evaluate(vm), b.evaluate(vm)),
+		}
+	}
+}
+
+/// A condition on the state of the virtual machine, such as `V3 == 0x10 && I > 0x300`.
+///
+/// Conditions compare and combine V0 to VF, I, PC, DT, ST, the stack depth SP, bytes
+/// of memory like `[I + 1]` and numbers, with the operators of C. They hold when they
+/// aren't zero.
+#[derive(Debug, Clone, PartialEq, Eq, Display)]
+#[display(fmt = "{}", source)]
+pub struct Condition {
+	source: String,
+	expression: Expression,
+}
+
+impl Condition {
+	/// The value of the condition.
+	pub fn evaluate(&self, vm: &Vm) -> i64 {
+		self.expression.evaluate(vm)
+	}
+
+	/// Whether the condition holds.
+	pub fn is_met(&self, vm: &Vm) -> bool {
+		self.evaluate(vm) != 0
+	}
+}
+
+impl FromStr for Condition {
+	type Err = String;
+
+	fn from_str(source: &str) -> Result<Self, Self::Err> {
+		let mut parser = Parser {
+			source,
+			position: 0,
+		};
+		let expression = parser.expression(0)?;
+		parser.skip_whitespace();
+		if parser.position < source.len() {
+			return Err(parser.unexpected());
+		}
+		Ok(Condition {
+			source: source.trim().to_string(),
+			expression,
+		})
+	}
+}
+
+/// A precedence climbing parser for conditions.
+struct Parser<'a> {
+	source: &'a str,
+	position: usize,
+}
+
+impl<'a> Parser<'a> {
+	fn rest(&self) -> &'a str {
+		&self.source[self.position..]
+	}
+
+	fn skip_whitespace(&mut self) {
+		let rest = self.rest();
+		self.position += rest.len() - rest.trim_start().len();
+	}
+
+	/// Consumes `text` if it comes next.
+	fn eat(&mut self, text: &str) -> bool {
+		self.skip_whitespace();
+		if self.rest().starts_with(text) {
+			self.position += text.len();
+			true
+		} else {
+			false
+		}
+	}
+
+	fn unexpected(&self) -> String {
+		match self.rest().chars().next() {
+			Some(character) => {
+				format!("unexpected `{}` at column {}", character, self.position + 1)
+			}
+			None => "unexpected end of condition".to_string(),
+		}
+	}
+
+	/// Parses operators that bind tighter than `precedence`.
+	fn expression(&mut self, precedence: u8) -> Result<Expression, String> {
+		let mut expression = self.unary()?;
+		loop {
+			self.skip_whitespace();
+			let rest = self.rest();
+			let operator = Operator::ALL
+				.iter()
+				.find(|(text, _)| rest.starts_with(text))
+				.map(|(text, operator)| (text.len(), *operator));
+			let (length, operator) = match operator {
+				Some((length, operator)) if operator.precedence() > precedence => {
+					(length, operator)
+				}
+				_ => return Ok(expression),
+			};
+			self.position += length;
+			let right = self.expression(operator.precedence())?;
+			expression = Expression::Binary(operator, Box::new(expression), Box::new(right));
+		}
+	}
+
+	fn unary(&mut self) -> Result<Expression, String> {
+		if self.eat("!") {
+			return Ok(Expression::Not(Box::new(self.unary()?)));
+		}
+		if self.eat("-") {
+			return Ok(Expression::Negate(Box::new(self.unary()?)));
+		}
+		if self.eat("(") {
+			let expression = self.expression(0)?;
+			return match self.eat(")") {
+				true => Ok(expression),
+				false => Err(self.unexpected()),
+			};
+		}
+		if self.eat("[") {
+			let address = self.expression(0)?;
+			return match self.eat("]") {
+				true => Ok(Expression::Memory(Box::new(address))),
+				false => Err(self.unexpected()),
+			};
+		}
+		self.value()
+	}
+
+	/// Parses a number or a variable.
+	fn value(&mut self) -> Result<Expression, String> {
+		self.skip_whitespace();
+		let start = self.position;
+		let rest = self.rest();
+		let length = rest
+			.find(|character: char| !character.is_ascii_alphanumeric() && character != '_')
+			.unwrap_or(rest.len());
+		let word = &rest[..length];
+		if word.is_empty() {
+			return Err(self.unexpected());
+		}
+		self.position += length;
+		let lower = word.to_ascii_lowercase();
+		let variable = match lower.as_str() {
+			"i" => Some(Variable::Index),
+			"pc" => Some(Variable::ProgramCounter),
+			"dt" => Some(Variable::DelayTimer),
+			"st" => Some(Variable::SoundTimer),
+			"sp" => Some(Variable::StackDepth),
+			register if register.len() == 2 && register.starts_with('v') => {
+				usize::from_str_radix(&register[1..], 16)
+					.ok()
+					.map(Variable::Register)
+			}
+			_ => None,
+		};
+		if let Some(variable) = variable {
+			return Ok(Expression::Variable(variable));
+		}
+		let number = if let Some(hex) = lower.strip_prefix("0x") {
+			i64::from_str_radix(hex, 16)
+		} else if let Some(binary) = lower.strip_prefix("0b") {
+			i64::from_str_radix(binary, 2)
+		} else {
+			lower.parse()
+		};
+		number
+			.map(Expression::Number)
+			.map_err(|_| format!("unknown value `{}` at column {}", word, start + 1))
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Evaluates a condition in a virtual machine.
+	fn evaluate(source: &str, vm: &Vm) -> i64 {
+		source
+			.parse::<Condition>()
+			.unwrap_or_else(|error| panic!("{}: {}", source, error))
+			.evaluate(vm)
+	}
+
+	fn error(source: &str) -> String {
+		source.parse::<Condition>().unwrap_err()
+	}
+
+	#[test]
+	fn operators_follow_c_precedence() {
+		let vm = Vm::new();
+		assert_eq!(evaluate("1 + 2 << 1", &vm), 6);
+		assert_eq!(evaluate("1 << 1 + 2", &vm), 8);
+		assert_eq!(evaluate("1 | 2 & 3", &vm), 3);
+		assert_eq!(evaluate("6 ^ 3 & 1", &vm), 7);
+		assert_eq!(evaluate("2 + 3 == 5", &vm), 1);
+		assert_eq!(evaluate("1 < 2 == 1", &vm), 1);
+		assert_eq!(evaluate("0 && 0 || 1", &vm), 1);
+		assert_eq!(evaluate("1 || 0 && 0", &vm), 1);
+		assert_eq!(evaluate("(1 | 2) & 1", &vm), 1);
+	}
+
+	#[test]
+	fn operators_are_left_associative() {
+		let vm = Vm::new();
+		assert_eq!(evaluate("10 - 3 - 2", &vm), 5);
+		assert_eq!(evaluate("16 >> 2 >> 1", &vm), 2);
+		assert_eq!(evaluate("3 > 2 > 0", &vm), 1);
+		assert_eq!(evaluate("1-1", &vm), 0);
+	}
+
+	#[test]
+	fn unary_operators_and_numbers() {
+		let vm = Vm::new();
+		assert_eq!(evaluate("-3 + 5", &vm), 2);
+		assert_eq!(evaluate("--2", &vm), 2);
+		assert_eq!(evaluate("!0", &vm), 1);
+		assert_eq!(evaluate("!(1 + 1)", &vm), 0);
+		assert_eq!(evaluate("0x1F", &vm), 31);
+		assert_eq!(evaluate("0b101", &vm), 5);
+		assert_eq!(evaluate("42", &vm), 42);
+	}
+
+	#[test]
+	fn reads_the_virtual_machine() {
+		let mut vm = Vm::new();
+		vm.registers[0x3] = 0x10;
+		vm.registers[0xA] = 0xFF;
+		vm.index_register = 0x300;
+		vm.program_counter = 0x202;
+		vm.delay_timer = 5;
+		vm.sound_timer = 6;
+		vm.stack.push(0x204);
+		vm.memory[0x301] = 7;
+
+		assert_eq!(evaluate("V3", &vm), 0x10);
+		assert_eq!(evaluate("va + vA", &vm), 0x1FE);
+		assert_eq!(evaluate("I", &vm), 0x300);
+		assert_eq!(evaluate("PC", &vm), 0x202);
+		assert_eq!(evaluate("DT + ST", &vm), 11);
+		assert_eq!(evaluate("sp", &vm), 1);
+		assert_eq!(evaluate("[I + 1]", &vm), 7);
+		assert_eq!(evaluate("[[I + 1] + 0x2FA]", &vm), 7);
+		// Addresses outside of memory read as zero.
+		assert_eq!(evaluate("[-1]", &vm), 0);
+		assert_eq!(evaluate("[0x10000]", &vm), 0);
+
+		let condition = "V3 == 0x10 && I > 0x2FF".parse::<Condition>().unwrap();
+		assert!(condition.is_met(&vm));
+		vm.index_register = 0x200;
+		assert!(!condition.is_met(&vm));
+	}
+
+	#[test]
+	fn reports_parse_errors() {
+		assert_eq!(error(""), "unexpected end of condition");
+		assert_eq!(error("V0 =="), "unexpected end of condition");
+		assert_eq!(error("(1 + 2"), "unexpected end of condition");
+		assert_eq!(error("[I"), "unexpected end of condition");
+		assert_eq!(error("V0 $ 1"), "unexpected `$` at column 4");
+		assert_eq!(error("(1))"), "unexpected `)` at column 4");
+		assert_eq!(error("VG == 1"), "unknown value `VG` at column 1");
+		assert_eq!(error("1 + foo"), "unknown value `foo` at column 5");
+		assert_eq!(error("0x"), "unknown value `0x` at column 1");
+	}
+
+	#[test]
+	fn keeps_the_source() {
+		let condition = "  V0 == 1 ".parse::<Condition>().unwrap();
+		assert_eq!(condition.to_string(), "V0 == 1");
+	}
+
+	#[test]
+	fn breakpoints_are_hit_when_enabled_and_met() {
+		let mut vm = Vm::new();
+		let mut breakpoint = Breakpoint::new();
+		assert!(breakpoint.is_hit(&vm));
+		breakpoint.condition = Some("V0 == 1".parse().unwrap());
+		assert!(!breakpoint.is_hit(&vm));
+		vm.registers[0] = 1;
+		assert!(breakpoint.is_hit(&vm));
+		breakpoint.enabled = false;
+		assert!(!breakpoint.is_hit(&vm));
+	}
+}
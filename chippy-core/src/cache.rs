@@ -0,0 +1,335 @@
+use crate::{
+	instruction::{Address, Instruction},
+	vm::VmMode,
+};
+use bitvec::{bitvec, vec::BitVec};
+use std::{
+	ops::{Bound, RangeBounds, RangeInclusive},
+	sync::Arc,
+};
+
+/// The size of a page of memory that is invalidated as a whole.
+pub const PAGE_SIZE: usize = 256;
+/// The most instructions that are decoded into a single basic block.
+const MAX_BLOCK_LENGTH: usize = 64;
+/// The longest instruction, which may start up to this many bytes before a page.
+const MAX_INSTRUCTION_SIZE: usize = 4;
+
+/// A run of instructions with no control flow until its last instruction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+	/// The address of the first instruction.
+	pub start: Address,
+	/// The instructions of the block, in order.
+	pub instructions: Vec<Instruction>,
+}
+
+impl Block {
+	/// The address just past the end of the block.
+	pub fn end(&self) -> usize {
+		self.start as usize
+			+ self
+				.instructions
+				.iter()
+				.map(|instruction| instruction.size() as usize)
+				.sum::<usize>()
+	}
+
+	/// The pages of memory the block overlaps.
+	pub fn pages(&self) -> RangeInclusive<usize> {
+		self.start as usize / PAGE_SIZE..=(self.end() - 1) / PAGE_SIZE
+	}
+}
+
+/// Whether an instruction may not continue to the instruction after it.
+fn ends_block(instruction: &Instruction) -> bool {
+	matches!(
+		instruction,
+		Instruction::Sys(_)
+			| Instruction::Return
+			| Instruction::Exit
+			| Instruction::LoadKey(_)
+			| Instruction::Branch(_)
+	)
+}
+
+/// A cache of decoded instructions and basic blocks, indexed by address.
+///
+/// Writes to memory only mark pages as dirty. The instructions and blocks on
+/// dirty pages are thrown away the next time the cache is used.
+#[derive(Debug, Clone)]
+pub struct InstructionCache {
+	/// The decoded instruction at each address.
+	instructions: Vec<Option<Instruction>>,
+	/// The basic block starting at each address.
+	blocks: Vec<Option<Arc<Block>>>,
+	/// The start addresses of the blocks that overlap each page.
+	page_blocks: Vec<Vec<Address>>,
+	/// Which pages have been written to since they were last flushed.
+	dirty: BitVec,
+	/// Whether any page is dirty.
+	any_dirty: bool,
+	/// Which pages have decoded instructions starting on them.
+	cached_pages: BitVec,
+	/// The number of decoded instructions.
+	instruction_count: usize,
+	/// The number of basic blocks.
+	block_count: usize,
+}
+
+impl InstructionCache {
+	/// Creates an empty cache for a memory of `memory_size` bytes.
+	pub fn new(memory_size: usize) -> Self {
+		let pages = memory_size.div_ceil(PAGE_SIZE);
+		InstructionCache {
+			instructions: vec![None; memory_size],
+			blocks: vec![None; memory_size],
+			page_blocks: vec![Vec::new(); pages],
+			dirty: bitvec![0; pages],
+			any_dirty: false,
+			cached_pages: bitvec![0; pages],
+			instruction_count: 0,
+			block_count: 0,
+		}
+	}
+
+	/// Empties the cache, resizing it for a memory of `memory_size` bytes.
+	/// The cache is only reallocated if the size changes.
+	pub fn reset(&mut self, memory_size: usize) {
+		if memory_size == self.instructions.len() {
+			self.clear();
+		} else {
+			*self = Self::new(memory_size);
+		}
+	}
+
+	/// Empties the cache in place, only touching the pages that have something cached.
+	pub fn clear(&mut self) {
+		self.dirty.set_all(true);
+		self.any_dirty = true;
+		self.flush();
+	}
+
+	/// Marks the pages overlapping a range of memory as written to.
+	pub fn invalidate<R>(&mut self, memory_range: R)
+	where
+		R: RangeBounds<usize>,
+	{
+		let start = match memory_range.start_bound() {
+			Bound::Included(start) => *start,
+			Bound::Excluded(start) => start + 1,
+			Bound::Unbounded => 0,
+		};
+		let end = match memory_range.end_bound() {
+			Bound::Included(end) => end + 1,
+			Bound::Excluded(end) => *end,
+			Bound::Unbounded => self.instructions.len(),
+		}
+		.min(self.instructions.len());
+		if start >= end {
+			return;
+		}
+		self.dirty[start / PAGE_SIZE..=(end - 1) / PAGE_SIZE].set_all(true);
+		self.any_dirty = true;
+	}
+
+	/// Whether memory has been written to since the cache was last flushed.
+	pub fn is_dirty(&self) -> bool {
+		self.any_dirty
+	}
+
+	/// Throws away the instructions and blocks on dirty pages.
+	fn flush(&mut self) {
+		if !self.any_dirty {
+			return;
+		}
+		let dirty = std::mem::replace(&mut self.dirty, bitvec![0; self.page_blocks.len()]);
+		for page in dirty.iter_ones() {
+			for start in std::mem::take(&mut self.page_blocks[page]) {
+				self.remove_block(start);
+			}
+			// Instructions just before the page may extend into it.
+			let previous_cached = page > 0 && self.cached_pages[page - 1];
+			if !self.cached_pages[page] && !previous_cached {
+				continue;
+			}
+			let page_start = page * PAGE_SIZE;
+			let page_end = (page_start + PAGE_SIZE).min(self.instructions.len());
+			let start = page_start.saturating_sub(MAX_INSTRUCTION_SIZE - 1);
+			for instruction in &mut self.instructions[start..page_end] {
+				if instruction.take().is_some() {
+					self.instruction_count -= 1;
+				}
+			}
+			self.cached_pages.set(page, false);
+		}
+		self.any_dirty = false;
+	}
+
+	/// Removes a block, along with its entries for the pages it overlaps.
+	fn remove_block(&mut self, start: Address) {
+		if let Some(block) = self.blocks[start as usize].take() {
+			self.block_count -= 1;
+			for blocks in &mut self.page_blocks[block.pages()] {
+				blocks.retain(|block_start| *block_start != start);
+			}
+		}
+	}
+
+	/// The decoded instruction at an address, if it's cached.
+	pub fn get(&self, address: Address) -> Option<Instruction> {
+		self.instructions.get(address as usize).copied().flatten()
+	}
+
+	/// The number of decoded instructions in the cache.
+	pub fn len(&self) -> usize {
+		self.instruction_count
+	}
+
+	/// Whether there are no decoded instructions in the cache.
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// The number of basic blocks in the cache.
+	pub fn block_count(&self) -> usize {
+		self.block_count
+	}
+
+	/// Decodes the instruction at an address, using the cache if possible.
+	pub fn instruction(
+		&mut self,
+		memory: &[u8],
+		address: Address,
+		mode: VmMode,
+	) -> Option<Instruction> {
+		self.flush();
+		self.decode(memory, address as usize, mode)
+	}
+
+	fn decode(&mut self, memory: &[u8], address: usize, mode: VmMode) -> Option<Instruction> {
+		let cached = self.instructions.get_mut(address)?;
+		if cached.is_none() {
+			*cached = Instruction::decode(memory, address, mode);
+			if cached.is_some() {
+				self.instruction_count += 1;
+				self.cached_pages.set(address / PAGE_SIZE, true);
+			}
+		}
+		*cached
+	}
+
+	/// The basic block starting at an address, decoding it if it's not cached.
+	/// Returns `None` if the first instruction can't be decoded.
+	pub fn block(&mut self, memory: &[u8], start: Address, mode: VmMode) -> Option<Arc<Block>> {
+		self.flush();
+		if let Some(block) = self.blocks.get(start as usize)? {
+			return Some(block.clone());
+		}
+		let mut instructions = Vec::new();
+		let mut address = start as usize;
+		while instructions.len() < MAX_BLOCK_LENGTH {
+			// An invalid instruction ends the block, faulting when it's reached.
+			let instruction = match self.decode(memory, address, mode) {
+				Some(instruction) => instruction,
+				None => break,
+			};
+			instructions.push(instruction);
+			address += instruction.size() as usize;
+			if ends_block(&instruction) || address >= memory.len() {
+				break;
+			}
+		}
+		if instructions.is_empty() {
+			return None;
+		}
+		let block = Arc::new(Block {
+			start,
+			instructions,
+		});
+		for blocks in &mut self.page_blocks[block.pages()] {
+			blocks.push(start);
+		}
+		self.blocks[start as usize] = Some(block.clone());
+		self.block_count += 1;
+		Some(block)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vm::Vm;
+
+	#[test]
+	fn counts_what_it_caches() {
+		// LD V0, 1; ADD V0, 1; JP 0x200
+		let mut memory = vec![0; 0x1000];
+		memory[0x200..0x206].copy_from_slice(&[0x60, 0x01, 0x70, 0x01, 0x12, 0x00]);
+		let mut cache = InstructionCache::new(memory.len());
+		let block = cache.block(&memory, 0x200, VmMode::Chip8).unwrap();
+		assert_eq!(block.instructions.len(), 3);
+		assert_eq!(block.end(), 0x206);
+		assert_eq!((cache.len(), cache.block_count()), (3, 1));
+		cache.instruction(&memory, 0x300, VmMode::Chip8);
+		assert_eq!((cache.len(), cache.block_count()), (4, 1));
+		// Only the page that was written to is thrown away.
+		cache.invalidate(0x204..0x206);
+		cache.instruction(&memory, 0x300, VmMode::Chip8);
+		assert_eq!((cache.len(), cache.block_count()), (1, 0));
+		cache.clear();
+		assert!(cache.is_empty());
+		assert_eq!(cache.block_count(), 0);
+	}
+
+	#[test]
+	fn instructions_extending_into_a_page_are_invalidated() {
+		let mut memory = vec![0; 0x1000];
+		// LD I, long 0x1234 straddles the page at 0x300.
+		memory[0x2FE..0x302].copy_from_slice(&[0xF0, 0x00, 0x12, 0x34]);
+		let mut cache = InstructionCache::new(memory.len());
+		assert!(cache.instruction(&memory, 0x2FE, VmMode::XoChip).is_some());
+		memory[0x300] = 0x56;
+		cache.invalidate(0x300..=0x300);
+		assert_eq!(
+			cache.instruction(&memory, 0x2FE, VmMode::XoChip),
+			Instruction::decode(&memory, 0x2FE, VmMode::XoChip)
+		);
+	}
+
+	#[test]
+	fn self_modifying_code() {
+		let program = [
+			0x65, 0x01, // LD V5, 1
+			0x60, 0x65, // LD V0, 0x65
+			0x61, 0x07, // LD V1, 7
+			0xA2, 0x00, // LD I, 0x200
+			0xF1, 0x55, // LD [I], V1, which turns the first instruction into LD V5, 7
+			0x12, 0x00, // JP 0x200
+		];
+		let mut vm = Vm::new();
+		vm.load_program(&program).unwrap();
+		for _ in 0..6 {
+			vm.execute().unwrap();
+		}
+		assert_eq!(vm.registers[5], 1);
+		vm.execute().unwrap();
+		assert_eq!(vm.registers[5], 7);
+	}
+
+	#[test]
+	fn self_modifying_code_in_a_block() {
+		let program = [
+			0x60, 0x65, // LD V0, 0x65
+			0x61, 0x07, // LD V1, 7
+			0xA2, 0x08, // LD I, 0x208
+			0xF1, 0x55, // LD [I], V1, which turns the next instruction into LD V5, 7
+			0x65, 0x01, // LD V5, 1
+			0x12, 0x0A, // JP 0x20A
+		];
+		let mut vm = Vm::new();
+		vm.load_program(&program).unwrap();
+		vm.run_frame().unwrap();
+		assert_eq!(vm.registers[5], 7);
+	}
+}
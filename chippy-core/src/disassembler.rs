@@ -0,0 +1,405 @@
+use crate::{
+	instruction::{
+		branch::{BranchInstruction, BranchTarget, BranchType},
+		format::Syntax,
+		load::{LoadInstruction, LoadTarget},
+		Address, Instruction,
+	},
+	quirks::Quirks,
+	vm::{VmMode, PROGRAM_START},
+};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The most bytes of data on a single line.
+const BYTES_PER_LINE: usize = 8;
+
+/// How an address is referred to, which decides the name of its label.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Reference {
+	Data,
+	Jump,
+	Call,
+}
+
+/// What a line of disassembly contains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Item {
+	/// An instruction reached from the start of the program.
+	Instruction(Instruction),
+	/// Bytes that aren't reached as code, or code that doesn't re-assemble to the same bytes.
+	Bytes(Vec<u8>),
+	/// A sprite drawn by the program, with one row per byte, or per two bytes when wide.
+	Sprite { wide: bool, data: Vec<u8> },
+}
+
+/// A line of disassembly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Line {
+	/// The address of the line.
+	pub address: usize,
+	/// What the line contains.
+	pub item: Item,
+}
+
+impl Line {
+	/// The number of bytes the line covers.
+	pub fn size(&self) -> usize {
+		match &self.item {
+			Item::Instruction(instruction) => instruction.size() as usize,
+			Item::Bytes(data) | Item::Sprite { data, .. } => data.len(),
+		}
+	}
+}
+
+/// A program split into code and data by following its control flow from 0x200.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembly {
+	/// The mode the program was disassembled for.
+	pub mode: VmMode,
+	/// The quirks the program runs with, which change how some instructions are written.
+	pub quirks: Quirks,
+	/// The lines of the program, in order.
+	pub lines: Vec<Line>,
+	/// The generated labels of addresses in the program that are jumped to,
+	/// called or loaded into I.
+	pub labels: BTreeMap<usize, String>,
+}
+
+impl Disassembly {
+	/// Disassembles a program that is loaded at 0x200.
+	pub fn new(program: &[u8], mode: VmMode, quirks: Quirks) -> Self {
+		let end = PROGRAM_START + program.len();
+		let mut memory = vec![0; end];
+		memory[PROGRAM_START..].copy_from_slice(program);
+
+		let mut code = BTreeMap::new();
+		let mut covered = vec![false; end];
+		let mut references = BTreeMap::new();
+		let mut sprites = BTreeMap::new();
+		let mut pending = Vec::new();
+		if !program.is_empty() {
+			pending.push(PROGRAM_START);
+		}
+		let in_program = |address: usize| (PROGRAM_START..end).contains(&address);
+		let refer = |references: &mut BTreeMap<usize, Reference>, address: Address, reference| {
+			let address = address as usize;
+			if in_program(address) {
+				let existing = references.entry(address).or_insert(reference);
+				*existing = reference.max(*existing);
+			}
+			in_program(address)
+		};
+
+		while let Some(start) = pending.pop() {
+			let mut address = start;
+			// The address in I, while it's known.
+			let mut index = None;
+			while let Some(instruction) = Instruction::decode(&memory, address, mode) {
+				let next = address + instruction.size() as usize;
+				if code.contains_key(&address)
+					|| next > end
+					|| covered[address..next].contains(&true)
+				{
+					break;
+				}
+				code.insert(address, instruction);
+				covered[address..next].fill(true);
+				match instruction {
+					Instruction::Branch(BranchInstruction {
+						branch_type,
+						branch_target:
+							BranchTarget::Address(target) | BranchTarget::AddressOffset(target, _),
+						..
+					}) => {
+						let call = branch_type == BranchType::Call;
+						let reference = if call {
+							Reference::Call
+						} else {
+							Reference::Jump
+						};
+						if refer(&mut references, target, reference) {
+							pending.push(target as usize);
+						}
+						if !call {
+							break;
+						}
+					}
+					Instruction::Branch(BranchInstruction {
+						branch_target: BranchTarget::Skip,
+						..
+					}) => {
+						// XO-CHIP's F000 NNNN is twice as long as every other instruction.
+						let long = mode == VmMode::XoChip
+							&& memory.get(next..next + 2) == Some(&[0xF0, 0x00]);
+						pending.push(next + if long { 4 } else { 2 });
+					}
+					Instruction::Return | Instruction::Exit => break,
+					// Machine code is kept as data.
+					Instruction::Sys(target) => {
+						refer(&mut references, target, Reference::Data);
+					}
+					Instruction::Load(LoadInstruction {
+						from: LoadTarget::Address(target) | LoadTarget::LongAddress(target),
+						into: LoadTarget::I,
+					}) => {
+						refer(&mut references, target, Reference::Data);
+						index = Some(target as usize);
+					}
+					Instruction::Draw(_, _, rows) => {
+						let sprite = match rows {
+							0 if mode.is_super_chip() => Some((32, true)),
+							0 => None,
+							rows => Some((rows as usize, false)),
+						};
+						if let (Some(index), Some(sprite)) = (index, sprite) {
+							sprites.entry(index).or_insert(sprite);
+						}
+					}
+					// These may change I, depending on the quirks.
+					Instruction::AddI(_)
+					| Instruction::Load(LoadInstruction {
+						into: LoadTarget::I,
+						..
+					})
+					| Instruction::Load(LoadInstruction {
+						from: LoadTarget::I,
+						..
+					}) => index = None,
+					_ => {}
+				}
+				address = next;
+			}
+		}
+
+		let mut labels = references
+			.into_iter()
+			.map(|(address, reference)| {
+				let prefix = match reference {
+					Reference::Data => "data",
+					Reference::Jump => "label",
+					Reference::Call => "sub",
+				};
+				(address, format!("{}_{:03X}", prefix, address))
+			})
+			.collect::<BTreeMap<_, _>>();
+
+		let mut lines = Vec::new();
+		let mut address = PROGRAM_START;
+		while address < end {
+			if let Some(instruction) = code.get(&address) {
+				let size = instruction.size() as usize;
+				let bytes = &memory[address..address + size];
+				// Aliased opcodes, like 9XY1, are kept as bytes so that they re-assemble exactly.
+				let item = if instruction.encode(mode).as_deref() == Some(bytes) {
+					Item::Instruction(*instruction)
+				} else {
+					Item::Bytes(bytes.to_vec())
+				};
+				lines.push(Line { address, item });
+				address += size;
+				continue;
+			}
+			// Data runs until the next instruction, and is split at labels and sprites.
+			let data_end = code
+				.range(address..)
+				.next()
+				.map_or(end, |(start, _)| *start);
+			let breaks = labels
+				.range(address + 1..data_end)
+				.map(|(address, _)| *address)
+				.chain(
+					sprites
+						.range(address + 1..data_end)
+						.map(|(address, _)| *address),
+				)
+				.collect::<BTreeSet<_>>();
+			let next_break = breaks.iter().next().copied().unwrap_or(data_end);
+			let line = match sprites.get(&address) {
+				Some((length, wide)) if address + length <= data_end => Line {
+					address,
+					item: Item::Sprite {
+						wide: *wide,
+						data: memory[address..address + length].to_vec(),
+					},
+				},
+				_ => {
+					let line_end = next_break.min(address + BYTES_PER_LINE);
+					Line {
+						address,
+						item: Item::Bytes(memory[address..line_end].to_vec()),
+					}
+				}
+			};
+			address += line.size();
+			lines.push(line);
+		}
+
+		// Addresses in the middle of a line, like a jump into an instruction, are left as numbers.
+		let starts = lines
+			.iter()
+			.map(|line| line.address)
+			.collect::<BTreeSet<_>>();
+		labels.retain(|address, _| starts.contains(address));
+
+		Disassembly {
+			mode,
+			quirks,
+			lines,
+			labels,
+		}
+	}
+
+	/// The label of an address, if it has one.
+	pub fn label(&self, address: usize) -> Option<&str> {
+		self.labels.get(&address).map(String::as_str)
+	}
+
+	/// The text of a line, which has a line for each row of a sprite.
+	pub fn text(&self, line: &Line, syntax: Syntax) -> String {
+		match &line.item {
+			Item::Instruction(instruction) => {
+				syntax.instruction(instruction, self.mode, &self.quirks, |address| {
+					self.label(address as usize).map(str::to_string)
+				})
+			}
+			Item::Bytes(data) => syntax.bytes(data),
+			Item::Sprite { wide, data } => syntax.sprite(*wide, data),
+		}
+	}
+
+	/// Source for the whole program. In Cowgod or Octo syntax, the assembler or
+	/// the Octo compiler turns it back into the same bytes.
+	pub fn source(&self, syntax: Syntax) -> String {
+		let mut source = String::new();
+		// Octo jumps to main first, unless the program starts with it.
+		if syntax == Syntax::Octo {
+			source += &format!("{}\n", syntax.label("main"));
+		}
+		for line in &self.lines {
+			if let Some(label) = self.label(line.address) {
+				source += &format!("{}\n", syntax.label(label));
+			}
+			for text in self.text(line, syntax).lines() {
+				source += &format!("\t{}\n", text);
+			}
+		}
+		source
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::{assembler, octo};
+
+	/// A program with a subroutine, a sprite, an infinite loop and unreachable bytes.
+	const PROGRAM: [u8; 17] = [
+		0x22, 0x0A, // CALL 0x20A
+		0xA2, 0x0E, // LD I, 0x20E
+		0xD0, 0x13, // DRW V0, V1, 3
+		0x12, 0x06, // JP 0x206
+		0x00, 0x00, // Never reached
+		0x60, 0x01, // LD V0, 1
+		0x00, 0xEE, // RET
+		0xFF, 0x81, 0xFF, // The sprite
+	];
+
+	#[test]
+	fn follows_calls_and_jumps() {
+		let disassembly = Disassembly::new(&PROGRAM, VmMode::Chip8, VmMode::Chip8.quirks());
+		assert_eq!(
+			disassembly.source(Syntax::Cowgod),
+			"\tCALL sub_20A
+\tLD I, data_20E
+\tDRW V0, V1, 3
+label_206:
+\tJP label_206
+\tdb 0x00, 0x00
+sub_20A:
+\tLD V0, 0x01
+\tRET
+data_20E:
+\tdb %11111111
+\tdb %10000001
+\tdb %11111111
+"
+		);
+	}
+
+	#[test]
+	fn source_round_trips() {
+		let disassembly = Disassembly::new(&PROGRAM, VmMode::Chip8, VmMode::Chip8.quirks());
+		let cowgod = disassembly.source(Syntax::Cowgod);
+		assert_eq!(
+			assembler::assemble(&cowgod, VmMode::Chip8).unwrap(),
+			PROGRAM
+		);
+		let octo = disassembly.source(Syntax::Octo);
+		assert_eq!(octo::compile(&octo, VmMode::Chip8).unwrap(), PROGRAM);
+	}
+
+	#[test]
+	fn follows_both_sides_of_skips() {
+		let program = [
+			0x30, 0x01, // SE V0, 1
+			0x12, 0x06, // JP 0x206
+			0x00, 0xE0, // CLS
+			0x00, 0xEE, // RET
+		];
+		let disassembly = Disassembly::new(&program, VmMode::Chip8, VmMode::Chip8.quirks());
+		assert!(disassembly
+			.lines
+			.iter()
+			.all(|line| matches!(line.item, Item::Instruction(_))));
+	}
+
+	#[test]
+	fn skips_over_long_loads() {
+		let program = [
+			0x30, 0x01, // SE V0, 1
+			0xF0, 0x00, 0x03, 0x00, // LD I, LONG 0x300
+			0x00, 0xFD, // EXIT
+		];
+		let disassembly = Disassembly::new(&program, VmMode::XoChip, VmMode::XoChip.quirks());
+		let addresses = disassembly
+			.lines
+			.iter()
+			.filter(|line| matches!(line.item, Item::Instruction(_)))
+			.map(|line| line.address)
+			.collect::<Vec<_>>();
+		assert_eq!(addresses, [0x200, 0x202, 0x206]);
+	}
+
+	#[test]
+	fn follows_offset_jumps() {
+		let program = [
+			0xB2, 0x04, // JP V0, 0x204
+			0x00, 0x00, // Never reached
+			0x00, 0xE0, // CLS
+		];
+		let disassembly = Disassembly::new(&program, VmMode::Chip8, VmMode::Chip8.quirks());
+		assert_eq!(disassembly.label(0x204), Some("label_204"));
+		assert_eq!(
+			disassembly.lines[2],
+			Line {
+				address: 0x204,
+				item: Item::Instruction(Instruction::Clear),
+			}
+		);
+		assert_eq!(disassembly.lines[1].item, Item::Bytes(vec![0x00, 0x00]));
+	}
+
+	#[test]
+	fn jumps_into_instructions_have_no_label() {
+		let program = [
+			0x60, 0x12, // LD V0, 0x12
+			0x12, 0x01, // JP 0x201
+		];
+		let disassembly = Disassembly::new(&program, VmMode::Chip8, VmMode::Chip8.quirks());
+		assert!(disassembly.labels.is_empty());
+		assert_eq!(
+			disassembly.text(&disassembly.lines[1], Syntax::Cowgod),
+			"JP 0x201"
+		);
+	}
+}
@@ -0,0 +1,117 @@
+use bitvec::{bitvec, slice::BitSlice, vec::BitVec};
+
+/// The number of display bitplanes.
+pub const PLANES: usize = 2;
+/// The width and height of the display in low resolution mode.
+pub const LOW_RESOLUTION: (usize, usize) = (64, 32);
+/// The width and height of the display in high resolution mode.
+pub const HIGH_RESOLUTION: (usize, usize) = (128, 64);
+/// The RGB color of each pixel, indexed by which display planes it's set in.
+pub const PALETTE: [[u8; 3]; 1 << PLANES] =
+	[[0, 0, 0], [255, 255, 255], [130, 130, 130], [80, 80, 80]];
+/// The characters used for each color of pixel in ASCII art.
+const ASCII_PIXELS: [char; 1 << PLANES] = ['.', '#', '+', '@'];
+
+/// The bitplanes of the CHIP-8 display.
+/// Only the first plane is used outside of XO-CHIP mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Display {
+	width: usize,
+	height: usize,
+	planes: [BitVec; PLANES],
+}
+
+impl Display {
+	/// Creates a blank display.
+	pub fn new(width: usize, height: usize) -> Self {
+		Display {
+			width,
+			height,
+			planes: [bitvec![0; width * height], bitvec![0; width * height]],
+		}
+	}
+
+	/// The width of the display in pixels.
+	pub fn width(&self) -> usize {
+		self.width
+	}
+
+	/// The height of the display in pixels.
+	pub fn height(&self) -> usize {
+		self.height
+	}
+
+	/// Changes the resolution of the display, clearing every plane.
+	pub fn resize(&mut self, width: usize, height: usize) {
+		self.width = width;
+		self.height = height;
+		for plane in self.planes.iter_mut() {
+			plane.set_all(false);
+			plane.resize(width * height, false);
+		}
+	}
+
+	/// Clears a plane.
+	pub fn clear(&mut self, plane: usize) {
+		self.planes[plane].set_all(false);
+	}
+
+	/// Whether a pixel is set in a plane.
+	pub fn get(&self, plane: usize, x: usize, y: usize) -> bool {
+		self.planes[plane][y * self.width + x]
+	}
+
+	/// Sets or unsets a pixel in a plane.
+	pub fn set(&mut self, plane: usize, x: usize, y: usize, value: bool) {
+		let index = y * self.width + x;
+		self.planes[plane].set(index, value);
+	}
+
+	/// The color of a pixel, with a bit set for each plane the pixel is set in.
+	pub fn pixel(&self, x: usize, y: usize) -> usize {
+		(0..PLANES)
+			.filter(|plane| self.get(*plane, x, y))
+			.fold(0, |color, plane| color | (1 << plane))
+	}
+
+	/// The pixels of a plane, row by row.
+	pub fn plane(&self, plane: usize) -> &BitSlice {
+		&self.planes[plane]
+	}
+
+	/// The mutable pixels of a plane, row by row.
+	pub fn plane_mut(&mut self, plane: usize) -> &mut BitSlice {
+		&mut self.planes[plane]
+	}
+
+	/// The display as ASCII art, with a line for each row and a character for each color.
+	pub fn to_ascii(&self) -> String {
+		let mut ascii = String::with_capacity((self.width + 1) * self.height);
+		for y in 0..self.height {
+			ascii.extend((0..self.width).map(|x| ASCII_PIXELS[self.pixel(x, y)]));
+			ascii.push('\n');
+		}
+		ascii
+	}
+
+	/// The display as a binary PBM image, with pixels that are set in any plane in black.
+	pub fn to_pbm(&self) -> Vec<u8> {
+		let mut pbm = format!("P4\n{} {}\n", self.width, self.height).into_bytes();
+		for y in 0..self.height {
+			// Each row is padded to a whole number of bytes.
+			for chunk in (0..self.width).collect::<Vec<_>>().chunks(8) {
+				let byte = chunk.iter().enumerate().fold(0, |byte, (bit, x)| {
+					byte | (u8::from(self.pixel(*x, y) != 0) << (7 - bit))
+				});
+				pbm.push(byte);
+			}
+		}
+		pbm
+	}
+}
+
+impl Default for Display {
+	fn default() -> Self {
+		Display::new(LOW_RESOLUTION.0, LOW_RESOLUTION.1)
+	}
+}
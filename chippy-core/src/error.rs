@@ -0,0 +1,88 @@
+use crate::instruction::Address;
+
+/// A fault raised by the CHIP-8 virtual machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum VmError {
+	/// The opcode could not be parsed in the current mode.
+	#[display(fmt = "invalid opcode {:04X} at 0x{:03X}", opcode, pc)]
+	InvalidOpcode { pc: Address, opcode: u16 },
+	/// A return was executed with an empty stack.
+	#[display(fmt = "stack underflow ({:04X} at 0x{:03X})", opcode, pc)]
+	StackUnderflow { pc: Address, opcode: u16 },
+	/// A call was executed with a full stack.
+	#[display(fmt = "stack overflow ({:04X} at 0x{:03X})", opcode, pc)]
+	StackOverflow { pc: Address, opcode: u16 },
+	/// An instruction accessed memory outside of the address space.
+	#[display(
+		fmt = "memory access out of bounds at 0x{:X} ({:04X} at 0x{:03X})",
+		address,
+		opcode,
+		pc
+	)]
+	MemoryOutOfBounds {
+		pc: Address,
+		opcode: u16,
+		address: usize,
+	},
+	/// The program does not fit into memory.
+	#[display(
+		fmt = "program is {} bytes, but only {} bytes are available",
+		size,
+		max
+	)]
+	ProgramTooLarge { size: usize, max: usize },
+	/// A 0nnn machine language subroutine was called outside of COSMAC VIP mode.
+	#[display(fmt = "unsupported system call {:04X} at 0x{:03X}", opcode, pc)]
+	UnsupportedSys { pc: Address, opcode: u16 },
+	/// A 0nnn machine language subroutine executed an undefined 1802 opcode.
+	#[display(
+		fmt = "invalid machine code at 0x{:03X} ({:04X} at 0x{:03X})",
+		address,
+		opcode,
+		pc
+	)]
+	InvalidMachineCode {
+		pc: Address,
+		opcode: u16,
+		address: Address,
+	},
+	/// A 0nnn machine language subroutine never returned.
+	#[display(
+		fmt = "machine code subroutine did not return ({:04X} at 0x{:03X})",
+		opcode,
+		pc
+	)]
+	MachineCodeTimeout { pc: Address, opcode: u16 },
+}
+
+impl VmError {
+	/// The address of the faulting instruction, if there is one.
+	pub fn pc(&self) -> Option<Address> {
+		match *self {
+			VmError::InvalidOpcode { pc, .. }
+			| VmError::StackUnderflow { pc, .. }
+			| VmError::StackOverflow { pc, .. }
+			| VmError::MemoryOutOfBounds { pc, .. }
+			| VmError::UnsupportedSys { pc, .. }
+			| VmError::InvalidMachineCode { pc, .. }
+			| VmError::MachineCodeTimeout { pc, .. } => Some(pc),
+			VmError::ProgramTooLarge { .. } => None,
+		}
+	}
+
+	/// The opcode of the faulting instruction, if there is one.
+	pub fn opcode(&self) -> Option<u16> {
+		match *self {
+			VmError::InvalidOpcode { opcode, .. }
+			| VmError::StackUnderflow { opcode, .. }
+			| VmError::StackOverflow { opcode, .. }
+			| VmError::MemoryOutOfBounds { opcode, .. }
+			| VmError::UnsupportedSys { opcode, .. }
+			| VmError::InvalidMachineCode { opcode, .. }
+			| VmError::MachineCodeTimeout { opcode, .. } => Some(opcode),
+			VmError::ProgramTooLarge { .. } => None,
+		}
+	}
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,830 @@
+//! A GDB remote serial protocol stub, so that gdb, lldb and IDEs can debug programs
+//! running on the virtual machine.
+//!
+//! The registers are V0 to VF, I, PC, the stack depth SP, the stack entries S0 to SF,
+//! and the delay and sound timers. They are sent big-endian, like CHIP-8 addresses,
+//! and described to the debugger by `target.xml`.
+
+use crate::{
+	error::VmError,
+	instruction::Address,
+	vm::{Vm, FRAME_TIME, STACK_SIZE},
+	watchpoint::{Access, Watchpoint},
+};
+use std::{
+	collections::VecDeque,
+	io::{self, ErrorKind, Read, Write},
+	net::TcpStream,
+	str, thread,
+	time::Instant,
+};
+
+/// The byte gdb sends to interrupt a running program.
+const INTERRUPT: u8 = 0x03;
+/// The largest packet the stub accepts, which is advertised to the debugger.
+const PACKET_SIZE: usize = 0x1000;
+
+/// The signals that stop replies report.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGSEGV: u8 = 11;
+
+/// The register numbers, in the order of `g` packets.
+const REGISTER_I: usize = 16;
+const REGISTER_PC: usize = 17;
+const REGISTER_SP: usize = 18;
+const REGISTER_STACK: usize = 19;
+const REGISTER_DT: usize = REGISTER_STACK + STACK_SIZE;
+const REGISTER_ST: usize = REGISTER_DT + 1;
+const REGISTER_COUNT: usize = REGISTER_ST + 1;
+
+/// A connection to a debugger.
+pub trait Connection: Read + Write {
+	/// Whether reads return immediately when no data has arrived.
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Connection for TcpStream {
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		TcpStream::set_nonblocking(self, nonblocking)
+	}
+}
+
+#[cfg(unix)]
+impl Connection for std::os::unix::net::UnixStream {
+	fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+		std::os::unix::net::UnixStream::set_nonblocking(self, nonblocking)
+	}
+}
+
+/// Why the program stopped, as reported to the debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stop {
+	/// The program stopped with a signal.
+	Signal(u8),
+	/// The program reached a breakpoint.
+	Breakpoint,
+	/// The program accessed memory under a watchpoint on these accesses, at an address.
+	Watchpoint(Access, Address),
+	/// The program exited with 00FD.
+	Exited,
+}
+
+impl Stop {
+	/// The stop reply packet.
+	fn reply(self) -> String {
+		match self {
+			Stop::Signal(signal) => format!("S{:02x}", signal),
+			Stop::Breakpoint => format!("T{:02x}swbreak:;", SIGTRAP),
+			Stop::Watchpoint(access, address) => {
+				let reason = match access {
+					Access::Write => "watch",
+					Access::Read => "rwatch",
+					Access::ReadWrite => "awatch",
+				};
+				format!("T{:02x}{}:{:x};", SIGTRAP, reason, address)
+			}
+			Stop::Exited => "W00".to_string(),
+		}
+	}
+}
+
+/// The signal for a fault.
+fn fault_signal(fault: VmError) -> u8 {
+	match fault {
+		VmError::MemoryOutOfBounds { .. }
+		| VmError::StackUnderflow { .. }
+		| VmError::StackOverflow { .. } => SIGSEGV,
+		_ => SIGILL,
+	}
+}
+
+/// The register description sent to the debugger.
+fn target_xml() -> String {
+	let mut registers = String::new();
+	let mut register = |name: &str, bits: usize, kind: &str, extra: &str| {
+		registers += &format!(
+			"<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"{}/>",
+			name, bits, kind, extra
+		);
+	};
+	for index in 0..16 {
+		register(&format!("v{:x}", index), 8, "uint8", "");
+	}
+	register("i", 16, "data_ptr", "");
+	register("pc", 16, "code_ptr", " generic=\"pc\"");
+	register("sp", 8, "uint8", "");
+	for index in 0..STACK_SIZE {
+		register(&format!("s{:x}", index), 16, "code_ptr", "");
+	}
+	register("dt", 8, "uint8", "");
+	register("st", 8, "uint8", "");
+	format!(
+		"<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+		<target version=\"1.0\"><feature name=\"org.chippy.chip8\">{}</feature></target>",
+		registers
+	)
+}
+
+/// Encodes bytes as lowercase hex.
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes hex into bytes.
+fn unhex(text: &str) -> Option<Vec<u8>> {
+	if !text.len().is_multiple_of(2) {
+		return None;
+	}
+	(0..text.len())
+		.step_by(2)
+		.map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+		.collect()
+}
+
+/// Parses a hex number.
+fn number(text: &str) -> Option<usize> {
+	usize::from_str_radix(text, 16).ok()
+}
+
+/// Parses `addr,length`.
+fn range(text: &str) -> Option<(usize, usize)> {
+	let (address, length) = text.split_once(',')?;
+	Some((number(address)?, number(length)?))
+}
+
+/// A debugging session with a single debugger.
+pub struct GdbStub<'a, C: Connection> {
+	vm: &'a mut Vm,
+	connection: C,
+	/// Whether packets are acknowledged, which is turned off by `QStartNoAckMode`.
+	acknowledge: bool,
+	/// The last packet sent, which is resent if the debugger asks for it again.
+	last_packet: Vec<u8>,
+	/// Bytes that arrived while the program was running, which are read before the connection.
+	received: VecDeque<u8>,
+}
+
+impl<'a, C: Connection> GdbStub<'a, C> {
+	/// Creates a session that debugs the program loaded in the virtual machine.
+	pub fn new(vm: &'a mut Vm, connection: C) -> Self {
+		GdbStub {
+			vm,
+			connection,
+			acknowledge: true,
+			last_packet: Vec::new(),
+			received: VecDeque::new(),
+		}
+	}
+
+	/// Handles packets until the debugger detaches, kills the program or disconnects.
+	/// The virtual machine is paused while the debugger isn't running it.
+	pub fn serve(&mut self) -> io::Result<()> {
+		self.vm.paused = true;
+		while let Some(packet) = self.receive()? {
+			let reply = match packet.first() {
+				Some(b'D') => {
+					self.send(b"OK")?;
+					break;
+				}
+				Some(b'k') => break,
+				Some(b'c') | Some(b's') => {
+					// Both take an optional address to resume at.
+					if let Some(address) = str::from_utf8(&packet[1..]).ok().and_then(number) {
+						self.vm.program_counter = address as Address;
+					}
+					let stop = if packet[0] == b'c' {
+						self.resume()?
+					} else {
+						self.step()
+					};
+					stop.reply().into_bytes()
+				}
+				_ => self.handle(&packet).into_bytes(),
+			};
+			self.send(&reply)?;
+		}
+		self.vm.paused = true;
+		Ok(())
+	}
+
+	/// Handles a packet that doesn't run the program, returning the reply.
+	fn handle(&mut self, packet: &[u8]) -> String {
+		// Only X carries binary data, everything else is text.
+		if let Some(data) = packet.strip_prefix(b"X") {
+			return self.write_binary(data).unwrap_or_else(|| "E01".to_string());
+		}
+		let packet = String::from_utf8_lossy(packet);
+		let (command, arguments) = packet.split_at(1.min(packet.len()));
+		let reply = match command {
+			"?" => Some(self.stop().reply()),
+			"g" => Some(hex(&(0..REGISTER_COUNT)
+				.flat_map(|register| self.register(register))
+				.collect::<Vec<_>>())),
+			"G" => self.write_registers(arguments),
+			"p" => number(arguments)
+				.filter(|register| *register < REGISTER_COUNT)
+				.map(|register| hex(&self.register(register))),
+			"P" => self.write_register(arguments),
+			"m" => self.read_memory(arguments),
+			"M" => self.write_memory(arguments),
+			"Z" | "z" => self.breakpoint(command == "Z", arguments),
+			"H" | "T" => Some("OK".to_string()),
+			"q" | "Q" => return self.query(&packet),
+			_ => return String::new(),
+		};
+		reply.unwrap_or_else(|| "E01".to_string())
+	}
+
+	/// Handles a general query or set packet, returning the reply.
+	fn query(&mut self, packet: &str) -> String {
+		if packet.starts_with("qSupported") {
+			return format!(
+				"PacketSize={:x};QStartNoAckMode+;qXfer:features:read+;swbreak+;hwbreak+",
+				PACKET_SIZE
+			);
+		}
+		if let Some(request) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+			let xml = target_xml();
+			return match range(request) {
+				Some((offset, length)) if offset <= xml.len() => {
+					let end = (offset + length).min(xml.len());
+					let more = if end < xml.len() { 'm' } else { 'l' };
+					format!("{}{}", more, &xml[offset..end])
+				}
+				_ => "E01".to_string(),
+			};
+		}
+		match packet {
+			"QStartNoAckMode" => {
+				self.acknowledge = false;
+				"OK".to_string()
+			}
+			"qAttached" => "1".to_string(),
+			"qC" => "QC1".to_string(),
+			"qfThreadInfo" => "m1".to_string(),
+			"qsThreadInfo" => "l".to_string(),
+			"qOffsets" => "Text=0;Data=0;Bss=0".to_string(),
+			_ => String::new(),
+		}
+	}
+
+	/// Why the program is stopped.
+	fn stop(&self) -> Stop {
+		match self.vm.fault {
+			Some(fault) => Stop::Signal(fault_signal(fault)),
+			None if self.vm.breakpoint.is_some() => Stop::Breakpoint,
+			None => match self.vm.watchpoint_hits.back() {
+				Some(hit) => {
+					// The reason names the kind of watchpoint, rather than the access.
+					let access = self
+						.vm
+						.watchpoints
+						.iter()
+						.find(|watchpoint| {
+							watchpoint.range.contains(&hit.address)
+								&& watchpoint.access.includes(hit.access)
+						})
+						.map_or(hit.access, |watchpoint| watchpoint.access);
+					Stop::Watchpoint(access, hit.address)
+				}
+				None => Stop::Signal(SIGTRAP),
+			},
+		}
+	}
+
+	/// Why the program paused itself, which is an exit if nothing else stopped it.
+	fn paused(&self) -> Stop {
+		match self.stop() {
+			Stop::Signal(SIGTRAP) => Stop::Exited,
+			stop => stop,
+		}
+	}
+
+	/// Lets the program run again.
+	fn start(&mut self) {
+		self.vm.paused = false;
+		self.vm.fault = None;
+		// Only accesses since the program resumed are reported as the reason it stopped.
+		self.vm.watchpoint_hits.clear();
+	}
+
+	/// Executes a single instruction.
+	fn step(&mut self) -> Stop {
+		self.start();
+		let _ = self.vm.execute();
+		if self.vm.paused {
+			return self.paused();
+		}
+		self.vm.paused = true;
+		self.stop()
+	}
+
+	/// Runs the program at its normal speed until it stops, or the debugger interrupts it.
+	fn resume(&mut self) -> io::Result<Stop> {
+		self.start();
+		loop {
+			let start = Instant::now();
+			let _ = self.vm.run_frame();
+			if self.vm.paused {
+				return Ok(self.paused());
+			}
+			if self.interrupted()? {
+				self.vm.paused = true;
+				return Ok(Stop::Signal(SIGINT));
+			}
+			thread::sleep(FRAME_TIME.saturating_sub(start.elapsed()));
+		}
+	}
+
+	/// Whether the debugger has sent an interrupt since the program resumed.
+	/// Any other byte is kept for the next packet.
+	fn interrupted(&mut self) -> io::Result<bool> {
+		self.connection.set_nonblocking(true)?;
+		let mut byte = [0];
+		let result = self.connection.read(&mut byte);
+		self.connection.set_nonblocking(false)?;
+		match result {
+			Ok(0) => Err(ErrorKind::UnexpectedEof.into()),
+			Ok(_) if byte[0] == INTERRUPT => Ok(true),
+			Ok(_) => {
+				self.received.push_back(byte[0]);
+				Ok(false)
+			}
+			Err(error) if error.kind() == ErrorKind::WouldBlock => Ok(false),
+			Err(error) => Err(error),
+		}
+	}
+
+	/// The value of a register, in target byte order.
+	fn register(&self, register: usize) -> Vec<u8> {
+		let vm = &self.vm;
+		match register {
+			0..=15 => vec![vm.registers[register]],
+			REGISTER_I => vm.index_register.to_be_bytes().to_vec(),
+			REGISTER_PC => vm.program_counter.to_be_bytes().to_vec(),
+			REGISTER_SP => vec![vm.stack.len() as u8],
+			REGISTER_DT => vec![vm.delay_timer],
+			REGISTER_ST => vec![vm.sound_timer],
+			_ => {
+				let entry = register - REGISTER_STACK;
+				let address = vm.stack.get(entry).copied().unwrap_or_default();
+				address.to_be_bytes().to_vec()
+			}
+		}
+	}
+
+	/// Sets a register from its value in target byte order, returning how many bytes it took.
+	fn set_register(&mut self, register: usize, value: &[u8]) -> Option<usize> {
+		let vm = &mut *self.vm;
+		let word = || Some(u16::from_be_bytes([*value.first()?, *value.get(1)?]));
+		match register {
+			0..=15 => vm.registers[register] = *value.first()?,
+			REGISTER_I => vm.index_register = word()?,
+			REGISTER_PC => vm.program_counter = word()?,
+			// Changing the depth drops entries, or pushes zeros.
+			REGISTER_SP => vm
+				.stack
+				.resize((*value.first()? as usize).min(STACK_SIZE), 0),
+			REGISTER_DT => vm.delay_timer = *value.first()?,
+			REGISTER_ST => vm.sound_timer = *value.first()?,
+			_ if register < REGISTER_COUNT => {
+				let word = word()?;
+				// Entries above the top of the stack don't exist.
+				if let Some(entry) = vm.stack.get_mut(register - REGISTER_STACK) {
+					*entry = word;
+				}
+			}
+			_ => return None,
+		}
+		Some(self.register(register).len())
+	}
+
+	/// Handles `G`, which sets every register.
+	fn write_registers(&mut self, values: &str) -> Option<String> {
+		let values = unhex(values)?;
+		let mut offset = 0;
+		for register in 0..REGISTER_COUNT {
+			offset += self.set_register(register, values.get(offset..)?)?;
+		}
+		Some("OK".to_string())
+	}
+
+	/// Handles `P n=value`, which sets a single register.
+	fn write_register(&mut self, arguments: &str) -> Option<String> {
+		let (register, value) = arguments.split_once('=')?;
+		self.set_register(number(register)?, &unhex(value)?)?;
+		Some("OK".to_string())
+	}
+
+	/// Handles `m addr,length`, which reads memory.
+	fn read_memory(&self, arguments: &str) -> Option<String> {
+		let (address, length) = range(arguments)?;
+		// Reads past the end of memory return what there is.
+		let end = address.saturating_add(length).min(self.vm.memory.len());
+		self.vm
+			.memory
+			.get(address..end)
+			.filter(|bytes| !bytes.is_empty() || length == 0)
+			.map(hex)
+	}
+
+	/// Writes to memory, returning the reply.
+	fn write(&mut self, address: usize, data: &[u8]) -> Option<String> {
+		let end = address.checked_add(data.len())?;
+		self.vm.memory.get_mut(address..end)?.copy_from_slice(data);
+		self.vm.invalidate_cache(address..end);
+		Some("OK".to_string())
+	}
+
+	/// Handles `M addr,length:data`, which writes memory.
+	fn write_memory(&mut self, arguments: &str) -> Option<String> {
+		let (range_text, data) = arguments.split_once(':')?;
+		let (address, length) = range(range_text)?;
+		let data = unhex(data).filter(|data| data.len() == length)?;
+		self.write(address, &data)
+	}
+
+	/// Handles `X addr,length:data`, which writes memory with binary data.
+	fn write_binary(&mut self, arguments: &[u8]) -> Option<String> {
+		let colon = arguments.iter().position(|byte| *byte == b':')?;
+		let (address, length) = range(str::from_utf8(&arguments[..colon]).ok()?)?;
+		let mut data = Vec::new();
+		let mut escaped = false;
+		for byte in &arguments[colon + 1..] {
+			match (escaped, byte) {
+				(false, b'}') => escaped = true,
+				(true, byte) => {
+					data.push(byte ^ 0x20);
+					escaped = false;
+				}
+				(false, byte) => data.push(*byte),
+			}
+		}
+		if data.len() != length {
+			return None;
+		}
+		self.write(address, &data)
+	}
+
+	/// Handles `Z` and `z`, which insert and remove breakpoints and watchpoints.
+	fn breakpoint(&mut self, insert: bool, arguments: &str) -> Option<String> {
+		let mut fields = arguments.split(',');
+		let kind = fields.next()?;
+		let address = number(fields.next()?)? as Address;
+		let access = match kind {
+			// Software and hardware breakpoints are the same here.
+			"0" | "1" => {
+				if insert {
+					self.vm.breakpoints.entry(address).or_default();
+				} else {
+					self.vm.breakpoints.remove(&address);
+				}
+				return Some("OK".to_string());
+			}
+			"2" => Access::Write,
+			"3" => Access::Read,
+			"4" => Access::ReadWrite,
+			_ => return Some(String::new()),
+		};
+		// The kind of a watchpoint is its length.
+		let length = number(fields.next()?)?.max(1);
+		let end = address.checked_add(Address::try_from(length - 1).ok()?)?;
+		let watchpoint = Watchpoint {
+			pause: true,
+			..Watchpoint::new(address..=end, access)
+		};
+		if insert {
+			self.vm.watchpoints.push(watchpoint);
+		} else {
+			self.vm.watchpoints.retain(|other| *other != watchpoint);
+		}
+		Some("OK".to_string())
+	}
+
+	/// Receives the next packet, acknowledging it. Returns `None` when the debugger disconnects.
+	fn receive(&mut self) -> io::Result<Option<Vec<u8>>> {
+		loop {
+			match self.read_byte()? {
+				None => return Ok(None),
+				Some(b'$') => {}
+				// A debugger that didn't receive the last packet intact asks for it again.
+				Some(b'-') => {
+					let packet = self.last_packet.clone();
+					self.connection.write_all(&packet)?;
+					continue;
+				}
+				// Acknowledgements and interrupts while stopped are ignored.
+				Some(_) => continue,
+			}
+			let mut packet = Vec::new();
+			loop {
+				match self.read_byte()? {
+					None => return Ok(None),
+					Some(b'#') => break,
+					Some(byte) if packet.len() < PACKET_SIZE => packet.push(byte),
+					Some(_) => {}
+				}
+			}
+			let mut checksum = [0; 2];
+			for byte in &mut checksum {
+				*byte = self
+					.read_byte()?
+					.ok_or_else(|| io::Error::from(ErrorKind::UnexpectedEof))?;
+			}
+			let valid = str::from_utf8(&checksum)
+				.ok()
+				.and_then(|checksum| u8::from_str_radix(checksum, 16).ok())
+				== Some(Self::checksum(&packet));
+			if self.acknowledge {
+				self.connection.write_all(if valid { b"+" } else { b"-" })?;
+			}
+			if valid || !self.acknowledge {
+				return Ok(Some(packet));
+			}
+		}
+	}
+
+	/// Sends a packet.
+	fn send(&mut self, data: &[u8]) -> io::Result<()> {
+		let mut packet = vec![b'$'];
+		// Characters that frame packets are escaped.
+		for byte in data {
+			if matches!(byte, b'$' | b'#' | b'}' | b'*') {
+				packet.extend([b'}', byte ^ 0x20]);
+			} else {
+				packet.push(*byte);
+			}
+		}
+		let checksum = Self::checksum(&packet[1..]);
+		packet.extend(format!("#{:02x}", checksum).into_bytes());
+		self.connection.write_all(&packet)?;
+		self.connection.flush()?;
+		self.last_packet = packet;
+		Ok(())
+	}
+
+	/// The checksum of a packet's data.
+	fn checksum(data: &[u8]) -> u8 {
+		data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+	}
+
+	/// Reads a byte, or `None` at the end of the connection.
+	fn read_byte(&mut self) -> io::Result<Option<u8>> {
+		if let Some(byte) = self.received.pop_front() {
+			return Ok(Some(byte));
+		}
+		let mut byte = [0];
+		match self.connection.read(&mut byte) {
+			Ok(0) => Ok(None),
+			Ok(_) => Ok(Some(byte[0])),
+			Err(error) if error.kind() == ErrorKind::Interrupted => self.read_byte(),
+			Err(error) => Err(error),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vm::VmMode;
+	use std::cell::Cell;
+
+	/// A connection that reads scripted bytes and records what is written.
+	#[derive(Default)]
+	struct MockConnection {
+		input: VecDeque<u8>,
+		output: Vec<u8>,
+		nonblocking: Cell<bool>,
+	}
+
+	impl Read for MockConnection {
+		fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+			match self.input.pop_front() {
+				Some(byte) => {
+					buffer[0] = byte;
+					Ok(1)
+				}
+				None if self.nonblocking.get() => Err(ErrorKind::WouldBlock.into()),
+				None => Ok(0),
+			}
+		}
+	}
+
+	impl Write for MockConnection {
+		fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+			self.output.extend_from_slice(buffer);
+			Ok(buffer.len())
+		}
+
+		fn flush(&mut self) -> io::Result<()> {
+			Ok(())
+		}
+	}
+
+	impl Connection for MockConnection {
+		fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+			self.nonblocking.set(nonblocking);
+			Ok(())
+		}
+	}
+
+	/// Frames data as a packet.
+	fn packet(data: &str) -> String {
+		let checksum = data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+		format!("${}#{:02x}", data, checksum)
+	}
+
+	/// Serves packets to a virtual machine running a program, returning what the stub sent.
+	fn serve_program(program: &[u8], input: &[u8]) -> String {
+		let mut vm = Vm::new();
+		vm.set_mode(VmMode::XoChip);
+		vm.load_program(program).unwrap();
+		let connection = MockConnection {
+			input: input.iter().copied().collect(),
+			..Default::default()
+		};
+		let mut stub = GdbStub::new(&mut vm, connection);
+		stub.serve().unwrap();
+		String::from_utf8(stub.connection.output).unwrap()
+	}
+
+	/// Serves packets, returning the replies without acknowledgements.
+	fn serve(program: &[u8], packets: &[&str]) -> Vec<String> {
+		let input = packets.iter().map(|data| packet(data)).collect::<String>();
+		let output = serve_program(program, input.as_bytes());
+		output
+			.split('+')
+			.skip(1)
+			.map(|reply| {
+				let data = reply
+					.strip_prefix('$')
+					.and_then(|reply| reply.rsplit_once('#'))
+					.map(|(data, _)| data)
+					.unwrap_or_else(|| panic!("malformed reply {:?}", reply));
+				assert_eq!(packet(data), reply);
+				data.to_string()
+			})
+			.collect()
+	}
+
+	/// Loops forever.
+	const LOOP: &[u8] = &[0x12, 0x00];
+
+	#[test]
+	fn acknowledges_and_replies() {
+		let output = serve_program(LOOP, packet("?").as_bytes());
+		assert_eq!(output, format!("+{}", packet("S05")));
+	}
+
+	#[test]
+	fn rejects_bad_checksums() {
+		let input = format!("$?#00{}", packet("?"));
+		let output = serve_program(LOOP, input.as_bytes());
+		assert_eq!(output, format!("-+{}", packet("S05")));
+	}
+
+	#[test]
+	fn stops_acknowledging() {
+		let input = format!("{}{}", packet("QStartNoAckMode"), packet("?"));
+		let output = serve_program(LOOP, input.as_bytes());
+		assert_eq!(output, format!("+{}{}", packet("OK"), packet("S05")));
+	}
+
+	#[test]
+	fn reads_and_writes_registers() {
+		let replies = serve(LOOP, &["P3=ab", "P10=0123", "p3", "p10", "p11", "p99"]);
+		assert_eq!(replies, ["OK", "OK", "ab", "0123", "0200", "E01"]);
+
+		let replies = serve(LOOP, &["g"]);
+		// V0 to VF, then I and PC.
+		assert!(replies[0].starts_with(&format!("{}00000200", "00".repeat(16))));
+		assert_eq!(replies[0].len(), REGISTER_COUNT * 2 + (STACK_SIZE + 2) * 2);
+	}
+
+	#[test]
+	fn reads_and_writes_memory() {
+		let replies = serve(
+			LOOP,
+			&[
+				"m200,2",
+				"M300,2:beef",
+				"m300,3",
+				"X302,2:}]*",
+				"m302,2",
+				"M300,2:be",
+				"mffffffffffffffff,2",
+				"Mffffffffffffffff,1:00",
+			],
+		);
+		assert_eq!(
+			replies,
+			["1200", "OK", "beef00", "OK", "7d2a", "E01", "E01", "E01"]
+		);
+	}
+
+	#[test]
+	fn stops_at_breakpoints() {
+		// 6001 7001 1202: the breakpoint is at the jump back.
+		let program = [0x60, 0x01, 0x70, 0x01, 0x12, 0x02];
+		let replies = serve(
+			&program,
+			&["Z0,204,2", "c", "p0", "c", "p0", "z0,204,2", "s", "p11"],
+		);
+		assert_eq!(
+			replies,
+			[
+				"OK",
+				"T05swbreak:;",
+				"02",
+				"T05swbreak:;",
+				"03",
+				"OK",
+				"S05",
+				"0202"
+			]
+		);
+	}
+
+	#[test]
+	fn steps() {
+		let program = [0x60, 0x01, 0x70, 0x01, 0x12, 0x02];
+		let replies = serve(&program, &["s", "s", "p0", "s", "p11", "s202", "p0", "p11"]);
+		assert_eq!(
+			replies,
+			["S05", "S05", "02", "S05", "0202", "S05", "03", "0204"]
+		);
+	}
+
+	#[test]
+	fn stops_at_watchpoints() {
+		// A300 6007 F055 F065 1208: the store writes 0x300, then the load reads 0x301.
+		let program = [0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0xF0, 0x65, 0x12, 0x08];
+		let replies = serve(&program, &["Z2,300,1", "Z3,300,2", "c", "p11", "c", "p11"]);
+		assert_eq!(
+			replies,
+			[
+				"OK",
+				"OK",
+				"T05watch:300;",
+				"0206",
+				"T05rwatch:301;",
+				"0208"
+			]
+		);
+
+		let replies = serve(
+			&program,
+			&["Z4,2ff,3", "s", "s", "s", "?", "s", "z4,2ff,3", "s"],
+		);
+		assert_eq!(
+			replies,
+			[
+				"OK",
+				"S05",
+				"S05",
+				"T05awatch:300;",
+				"T05awatch:300;",
+				"T05awatch:301;",
+				"OK",
+				"S05"
+			]
+		);
+	}
+
+	#[test]
+	fn reports_faults_and_exits() {
+		// 00EE returns with an empty stack.
+		assert_eq!(serve(&[0x00, 0xEE], &["c"]), ["S0b"]);
+		// 00FD exits.
+		assert_eq!(serve(&[0x00, 0xFD], &["c"]), ["W00"]);
+		assert_eq!(serve(&[0x00, 0xFD], &["s"]), ["W00"]);
+	}
+
+	#[test]
+	fn interrupts() {
+		let mut input = packet("c").into_bytes();
+		input.push(INTERRUPT);
+		let output = serve_program(LOOP, &input);
+		assert_eq!(output, format!("+{}", packet("S02")));
+	}
+
+	#[test]
+	fn keeps_bytes_received_while_running() {
+		// 6002 F015 F107 3100 1204 00FD waits two frames, then exits, while the next packet arrives.
+		let program = [
+			0x60, 0x02, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0x00, 0xFD,
+		];
+		let input = format!("{}{}", packet("c"), packet("m200,2"));
+		let output = serve_program(&program, input.as_bytes());
+		assert_eq!(output, format!("+{}+{}", packet("W00"), packet("6002")));
+	}
+
+	#[test]
+	fn describes_registers() {
+		let replies = serve(LOOP, &["qXfer:features:read:target.xml:0,ffff"]);
+		assert!(replies[0].starts_with("l<?xml"));
+		assert_eq!(replies[0].matches("<reg ").count(), REGISTER_COUNT);
+	}
+}
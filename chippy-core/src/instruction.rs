@@ -1,6 +1,7 @@
 pub mod arthimetic;
 pub mod branch;
 pub mod draw;
+pub mod format;
 pub mod load;
 pub mod logical;
 
@@ -17,16 +18,33 @@ pub type Value = u8;
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
 pub enum Instruction {
 	/// 0nnn - SYS addr
-	/// This is a stub that panics.
-	Sys,
+	/// This calls RCA 1802 machine code in COSMAC VIP mode, and faults the virtual machine otherwise.
+	#[display(fmt = "Call Machine Code at 0x{:03X}", _0)]
+	Sys(Address),
 	/// 00E0 - CLEAR
 	Clear,
 	/// 00EE - RETURN
 	Return,
+	/// 00Cn - SCD nibble (SUPER-CHIP)
+	#[display(fmt = "Scroll Down {} Rows", _0)]
+	ScrollDown(u8),
+	/// 00Dn - SCU nibble (XO-CHIP)
+	#[display(fmt = "Scroll Up {} Rows", _0)]
+	ScrollUp(u8),
+	/// 00FB - SCR (SUPER-CHIP)
+	#[display(fmt = "Scroll Right")]
+	ScrollRight,
+	/// 00FC - SCL (SUPER-CHIP)
+	#[display(fmt = "Scroll Left")]
+	ScrollLeft,
+	/// 00FD - EXIT (SUPER-CHIP)
+	/// This halts the virtual machine.
+	Exit,
 	/// Cxkk - RND Vx, byte
 	#[display(fmt = "Random into V{:X} & 0x{:X}", _0, _1)]
 	Random(Register, Value),
 	/// Dxyn - DRW Vx, Vy, nibble
+	/// In SUPER-CHIP mode, Dxy0 draws a 16 row sprite.
 	#[display(fmt = "Draw Sprite at V{:X},V{:X} with {} rows", _0, _1, _2)]
 	Draw(Register, Register, Value),
 	/// Fx0A - LD Vx, K
@@ -40,6 +58,9 @@ pub enum Instruction {
 		"if *_0 { \"Enable\" } else { \"Disable \" }"
 	)]
 	SetHighResolution(bool),
+	/// FN01 - PLANE n (XO-CHIP)
+	#[display(fmt = "Select Planes {:X}", _0)]
+	SelectPlane(u8),
 	/// A loading instruction
 	Load(load::LoadInstruction),
 	/// A branching instruction (00EE, 1NNN, 2NNN, 3XNN, 4XNN, 5XY0 and 9XY0)
@@ -51,20 +72,110 @@ pub enum Instruction {
 }
 
 impl Instruction {
-	/// Parses a CHIP-8 opcode
+	/// Decodes the instruction at an address in memory.
+	/// Unlike [`Instruction::parse`], this can decode XO-CHIP's 4-byte F000 NNNN.
+	pub fn decode(memory: &[u8], address: usize, mode: VmMode) -> Option<Self> {
+		let word = |address: usize| {
+			Some(u16::from_be_bytes([
+				*memory.get(address)?,
+				*memory.get(address + 1)?,
+			]))
+		};
+		let opcode = word(address)?;
+		if mode == VmMode::XoChip && opcode == 0xF000 {
+			return Some(Instruction::Load(load::LoadInstruction {
+				from: load::LoadTarget::LongAddress(word(address + 2)?),
+				into: load::LoadTarget::I,
+			}));
+		}
+		Self::parse(opcode, mode)
+	}
+
+	/// Encodes the instruction into the bytes of its opcode.
+	/// Returns `None` if the instruction has no encoding in the mode, so that
+	/// decoding the bytes always gives back the same instruction.
+	pub fn encode(&self, mode: VmMode) -> Option<Vec<u8>> {
+		let x = |register: Register| (register as u16) << 8;
+		let opcode = match *self {
+			Instruction::Load(load::LoadInstruction {
+				from: load::LoadTarget::LongAddress(address),
+				into: load::LoadTarget::I,
+			}) => {
+				let [high, low] = address.to_be_bytes();
+				return self.verify(vec![0xF0, 0x00, high, low], mode);
+			}
+			Instruction::Sys(address) => address,
+			Instruction::Clear => 0x00E0,
+			Instruction::Return => 0x00EE,
+			Instruction::ScrollDown(rows) => 0x00C0 | rows as u16,
+			Instruction::ScrollUp(rows) => 0x00D0 | rows as u16,
+			Instruction::ScrollRight => 0x00FB,
+			Instruction::ScrollLeft => 0x00FC,
+			Instruction::Exit => 0x00FD,
+			Instruction::SetHighResolution(false) => 0x00FE,
+			Instruction::SetHighResolution(true) => 0x00FF,
+			Instruction::Random(register, value) => 0xC000 | x(register) | value as u16,
+			Instruction::Draw(register_x, register_y, rows) => {
+				0xD000 | x(register_x) | (register_y as u16) << 4 | rows as u16
+			}
+			Instruction::LoadKey(register) => 0xF00A | x(register),
+			Instruction::AddI(register) => 0xF01E | x(register),
+			Instruction::SelectPlane(planes) => 0xF001 | x(planes),
+			Instruction::Load(load) => load.encode()?,
+			Instruction::Branch(branch) => branch.encode()?,
+			Instruction::Logical(logical) => logical.encode(),
+			Instruction::Arthimetic(arthimetic) => arthimetic.encode()?,
+		};
+		self.verify(opcode.to_be_bytes().to_vec(), mode)
+	}
+
+	/// Checks that the bytes decode back into the instruction.
+	/// Fields that are out of range would otherwise spill into other parts of the opcode.
+	fn verify(&self, bytes: Vec<u8>, mode: VmMode) -> Option<Vec<u8>> {
+		(Self::decode(&bytes, 0, mode) == Some(*self)).then_some(bytes)
+	}
+
+	/// The size of the instruction in bytes.
+	pub fn size(&self) -> u16 {
+		match self {
+			Instruction::Load(load::LoadInstruction {
+				from: load::LoadTarget::LongAddress(_),
+				..
+			}) => 4,
+			_ => 2,
+		}
+	}
+
+	/// Parses a CHIP-8 opcode.
+	/// The mode decides which instructions exist, while the differences in how
+	/// they behave are decided by the quirks when they are executed.
 	pub fn parse(opcode: u16, mode: VmMode) -> Option<Self> {
 		match opcode & 0xF000 {
-			0x0000 => match opcode & 0x00FF {
+			0x0000 => match opcode & 0x0FFF {
+				// 00Cn - SCD nibble
+				0x00C0..=0x00CF if mode.is_super_chip() => {
+					Some(Instruction::ScrollDown((opcode & 0x000F) as u8))
+				}
+				// 00Dn - SCU nibble (XO-CHIP)
+				0x00D0..=0x00DF if mode == VmMode::XoChip => {
+					Some(Instruction::ScrollUp((opcode & 0x000F) as u8))
+				}
 				// 00E0 - CLS
 				0x00E0 => Some(Instruction::Clear),
 				// 00EE - RET
 				0x00EE => Some(Instruction::Return),
+				// 00FB - SCR
+				0x00FB if mode.is_super_chip() => Some(Instruction::ScrollRight),
+				// 00FC - SCL
+				0x00FC if mode.is_super_chip() => Some(Instruction::ScrollLeft),
+				// 00FD - EXIT
+				0x00FD if mode.is_super_chip() => Some(Instruction::Exit),
 				// 00FE - Disable High Resolution Mode
-				0x00FE if mode == VmMode::SuperChip => Some(Instruction::SetHighResolution(false)),
+				0x00FE if mode.is_super_chip() => Some(Instruction::SetHighResolution(false)),
 				// 00FF - Enable High Resolution Mode
-				0x00FF if mode == VmMode::SuperChip => Some(Instruction::SetHighResolution(true)),
+				0x00FF if mode.is_super_chip() => Some(Instruction::SetHighResolution(true)),
 				// 0nnn - SYS addr
-				_ => Some(Instruction::Sys),
+				_ => Some(Instruction::Sys(opcode & 0x0FFF)),
 			},
 			// 1nnn - JP addr
 			0x1000 => Some(Instruction::Branch(branch::BranchInstruction {
@@ -96,15 +207,38 @@ impl Instruction {
 				branch_target: branch::BranchTarget::Skip,
 				inverted: true,
 			})),
-			// 5xy0 - SE Vx, Vy
-			0x5000 => Some(Instruction::Branch(branch::BranchInstruction {
-				branch_type: branch::BranchType::EqualRegister {
-					register_a: ((opcode & 0x0F00) >> 8) as Register,
-					register_b: ((opcode & 0x00F0) >> 4) as Register,
-				},
-				branch_target: branch::BranchTarget::Skip,
-				inverted: false,
-			})),
+			0x5000 => match opcode & 0x000F {
+				// 5xy0 - SE Vx, Vy
+				0x0000 => Some(Instruction::Branch(branch::BranchInstruction {
+					branch_type: branch::BranchType::EqualRegister {
+						register_a: ((opcode & 0x0F00) >> 8) as Register,
+						register_b: ((opcode & 0x00F0) >> 4) as Register,
+					},
+					branch_target: branch::BranchTarget::Skip,
+					inverted: false,
+				})),
+				// 5xy2 - SAVE Vx - Vy (XO-CHIP)
+				0x0002 if mode == VmMode::XoChip => {
+					Some(Instruction::Load(load::LoadInstruction {
+						from: load::LoadTarget::RegisterRange(
+							((opcode & 0x0F00) >> 8) as Register,
+							((opcode & 0x00F0) >> 4) as Register,
+						),
+						into: load::LoadTarget::I,
+					}))
+				}
+				// 5xy3 - LOAD Vx - Vy (XO-CHIP)
+				0x0003 if mode == VmMode::XoChip => {
+					Some(Instruction::Load(load::LoadInstruction {
+						from: load::LoadTarget::I,
+						into: load::LoadTarget::RegisterRange(
+							((opcode & 0x0F00) >> 8) as Register,
+							((opcode & 0x00F0) >> 4) as Register,
+						),
+					}))
+				}
+				_ => None,
+			},
 			// 6xkk - LD Vx, byte
 			0x6000 => Some(Instruction::Load(load::LoadInstruction {
 				from: load::LoadTarget::Value((opcode & 0x00FF) as Value),
@@ -123,8 +257,8 @@ impl Instruction {
 			0x8000 => match opcode & 0x000F {
 				// 8xy0 - LD Vx, Vy
 				0x0000 => Some(Instruction::Load(load::LoadInstruction {
-					from: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
-					into: load::LoadTarget::Register(((opcode & 0x00F0) >> 4) as Register),
+					from: load::LoadTarget::Register(((opcode & 0x00F0) >> 4) as Register),
+					into: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
 				})),
 				// 8xy1 - OR Vx, Vy
 				0x0001 => Some(Instruction::Logical(logical::LogicalInstruction {
@@ -165,22 +299,11 @@ impl Instruction {
 					inverted: false,
 				})),
 				// 8xy6 - SHR Vx {, Vy}
-				0x0006 if mode == VmMode::Chip8 => {
-					Some(Instruction::Arthimetic(arthimetic::ArthimeticInstruction {
-						op: arthimetic::ArthimeticOp::ShrOld,
-						values: arthimetic::ArthimeticValue::RegisterRegister(
-							((opcode & 0x0F00) >> 8) as Register,
-							((opcode & 0x00F0) >> 4) as Register,
-						),
-						carry_flag: false,
-						inverted: false,
-					}))
-				}
-				// 8xy6 - SHR Vx , Vy
 				0x0006 => Some(Instruction::Arthimetic(arthimetic::ArthimeticInstruction {
 					op: arthimetic::ArthimeticOp::Shr,
-					values: arthimetic::ArthimeticValue::Register(
+					values: arthimetic::ArthimeticValue::RegisterRegister(
 						((opcode & 0x0F00) >> 8) as Register,
+						((opcode & 0x00F0) >> 4) as Register,
 					),
 					carry_flag: false,
 					inverted: false,
@@ -195,23 +318,12 @@ impl Instruction {
 					carry_flag: true,
 					inverted: true,
 				})),
-				// 8xyE - SHL Vx, Vy
-				0x000E if mode == VmMode::Chip8 => {
-					Some(Instruction::Arthimetic(arthimetic::ArthimeticInstruction {
-						op: arthimetic::ArthimeticOp::ShlOld,
-						values: arthimetic::ArthimeticValue::RegisterRegister(
-							((opcode & 0x0F00) >> 8) as Register,
-							((opcode & 0x00F0) >> 4) as Register,
-						),
-						carry_flag: false,
-						inverted: false,
-					}))
-				}
-				// 8xyE - SHL Vx
+				// 8xyE - SHL Vx {, Vy}
 				0x000E => Some(Instruction::Arthimetic(arthimetic::ArthimeticInstruction {
 					op: arthimetic::ArthimeticOp::Shl,
-					values: arthimetic::ArthimeticValue::Register(
+					values: arthimetic::ArthimeticValue::RegisterRegister(
 						((opcode & 0x0F00) >> 8) as Register,
+						((opcode & 0x00F0) >> 4) as Register,
 					),
 					carry_flag: false,
 					inverted: false,
@@ -232,21 +344,13 @@ impl Instruction {
 				from: load::LoadTarget::Address(opcode & 0x0FFF),
 				into: load::LoadTarget::I,
 			})),
-			// Bxnn - JP V0, addr (CHIP-48)
-			0xB000 if mode != VmMode::Chip8 => {
-				Some(Instruction::Branch(branch::BranchInstruction {
-					branch_type: branch::BranchType::Unconditional,
-					branch_target: branch::BranchTarget::AddressOffset(
-						opcode & 0x0FFF,
-						((opcode & 0x0F00) >> 8) as Register,
-					),
-					inverted: false,
-				}))
-			}
-			// Bnnn - JP V0, addr
+			// Bnnn - JP V0, addr (or Bxnn - JP Vx, addr, depending on quirks)
 			0xB000 => Some(Instruction::Branch(branch::BranchInstruction {
 				branch_type: branch::BranchType::Unconditional,
-				branch_target: branch::BranchTarget::AddressOffset(opcode & 0x0FFF, 0),
+				branch_target: branch::BranchTarget::AddressOffset(
+					opcode & 0x0FFF,
+					((opcode & 0x0F00) >> 8) as Register,
+				),
 				inverted: false,
 			})),
 			// Cxkk - RND Vx, byte
@@ -280,6 +384,20 @@ impl Instruction {
 				_ => None,
 			},
 			0xF000 => match opcode & 0x00FF {
+				// F000 NNNN - LD I, long addr (XO-CHIP)
+				// This is twice as long as other instructions, so it's handled by `decode`.
+				0x0000 => None,
+				// FN01 - PLANE n (XO-CHIP)
+				0x0001 if mode == VmMode::XoChip && opcode & 0x0F00 <= 0x0300 => {
+					Some(Instruction::SelectPlane(((opcode & 0x0F00) >> 8) as u8))
+				}
+				// F002 - AUDIO (XO-CHIP)
+				0x0002 if mode == VmMode::XoChip && opcode & 0x0F00 == 0 => {
+					Some(Instruction::Load(load::LoadInstruction {
+						from: load::LoadTarget::I,
+						into: load::LoadTarget::AudioPattern,
+					}))
+				}
 				// Fx07 - LD Vx, DT
 				0x0007 => Some(Instruction::Load(load::LoadInstruction {
 					from: load::LoadTarget::DelayTimer,
@@ -301,7 +419,12 @@ impl Instruction {
 				0x001E => Some(Instruction::AddI(((opcode & 0x0F00) >> 8) as Register)),
 				// Fx29 - LD F, Vx
 				0x0029 => Some(Instruction::Load(load::LoadInstruction {
-					from: load::LoadTarget::Font(((opcode & 0x0F00) >> 8) as u8),
+					from: load::LoadTarget::Font(((opcode & 0x0F00) >> 8) as Register),
+					into: load::LoadTarget::I,
+				})),
+				// Fx30 - LD HF, Vx (SUPER-CHIP)
+				0x0030 if mode.is_super_chip() => Some(Instruction::Load(load::LoadInstruction {
+					from: load::LoadTarget::BigFont(((opcode & 0x0F00) >> 8) as Register),
 					into: load::LoadTarget::I,
 				})),
 				// Fx33 - LD B, Vx
@@ -312,30 +435,36 @@ impl Instruction {
 				// Fx55 - LD [I], Vx
 				0x0055 => Some(Instruction::Load(load::LoadInstruction {
 					from: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
-					into: if mode != VmMode::Chip8 {
-						load::LoadTarget::IChip48
-					} else {
-						load::LoadTarget::I
-					},
+					into: load::LoadTarget::I,
 				})),
 				// Fx65 - LD Vx, [I]
 				0x0065 => Some(Instruction::Load(load::LoadInstruction {
-					from: if mode != VmMode::Chip8 {
-						load::LoadTarget::IChip48
-					} else {
-						load::LoadTarget::I
-					},
+					from: load::LoadTarget::I,
 					into: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
 				})),
+				// Fx3A - PITCH Vx (XO-CHIP)
+				0x003A if mode == VmMode::XoChip => {
+					Some(Instruction::Load(load::LoadInstruction {
+						from: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
+						into: load::LoadTarget::Pitch,
+					}))
+				}
 				// FX75 - store V0..VX in RPL user flags
-				0x0075 if mode == VmMode::SuperChip => {
+				// SUPER-CHIP only has 8 flags, while XO-CHIP has 16.
+				0x0075
+					if mode == VmMode::XoChip
+						|| (mode.is_super_chip() && opcode & 0x0F00 < 0x0800) =>
+				{
 					Some(Instruction::Load(load::LoadInstruction {
 						from: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
 						into: load::LoadTarget::Rpl,
 					}))
 				}
 				// FX85 - load V0..VX from RPL user flags
-				0x0085 if mode == VmMode::SuperChip => {
+				0x0085
+					if mode == VmMode::XoChip
+						|| (mode.is_super_chip() && opcode & 0x0F00 < 0x0800) =>
+				{
 					Some(Instruction::Load(load::LoadInstruction {
 						from: load::LoadTarget::Rpl,
 						into: load::LoadTarget::Register(((opcode & 0x0F00) >> 8) as Register),
@@ -347,3 +476,33 @@ impl Instruction {
 		}
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encode_round_trips_every_opcode() {
+		for mode in VmMode::ALL {
+			for opcode in 0..=u16::MAX {
+				// The long load of I is followed by its address.
+				let bytes = [opcode.to_be_bytes(), [0x12, 0x34]].concat();
+				let instruction = match Instruction::decode(&bytes, 0, mode) {
+					Some(instruction) => instruction,
+					None => continue,
+				};
+				let encoded = instruction
+					.encode(mode)
+					.unwrap_or_else(|| panic!("{:04X} can't be encoded in {} mode", opcode, mode));
+				// Opcodes that ignore some of their bits are encoded with them clear.
+				assert_eq!(
+					Instruction::decode(&encoded, 0, mode),
+					Some(instruction),
+					"{:04X} doesn't round trip in {} mode",
+					opcode,
+					mode
+				);
+			}
+		}
+	}
+}
@@ -1,4 +1,5 @@
 use crate::{
+	error::VmError,
 	instruction::{Register, Value},
 	vm::Vm,
 };
@@ -10,15 +11,13 @@ pub enum ArthimeticOp {
 	Add,
 	/// Subtraction.
 	Sub,
-	/// Shift left (original CHIP-8 version)
-	#[display(fmt = "Shl")]
-	ShlOld,
-	/// Shift right (original CHIP-8 version)
-	#[display(fmt = "Shr")]
-	ShrOld,
-	/// Shift left (modern CHIP-48 version)
+	/// Shift left.
+	/// With two registers, the second register is shifted into the first
+	/// if the shift quirk is enabled.
 	Shl,
-	/// Shift right (modern CHIP-48 version)
+	/// Shift right.
+	/// With two registers, the second register is shifted into the first
+	/// if the shift quirk is enabled.
 	Shr,
 }
 
@@ -58,26 +57,53 @@ pub struct ArthimeticInstruction {
 }
 
 impl ArthimeticInstruction {
+	/// Encodes the instruction into its opcode, if it has one.
+	pub fn encode(&self) -> Option<u16> {
+		match (self.op, self.values, self.inverted) {
+			(ArthimeticOp::Add, ArthimeticValue::RegisterValue(x, value), false) => {
+				Some(0x7000 | (x as u16) << 8 | value as u16)
+			}
+			(op, ArthimeticValue::RegisterRegister(x, y), inverted) => {
+				let op = match (op, inverted) {
+					(ArthimeticOp::Add, false) => 0x4,
+					(ArthimeticOp::Sub, false) => 0x5,
+					(ArthimeticOp::Shr, false) => 0x6,
+					(ArthimeticOp::Sub, true) => 0x7,
+					(ArthimeticOp::Shl, false) => 0xE,
+					_ => return None,
+				};
+				Some(0x8000 | (x as u16) << 8 | (y as u16) << 4 | op)
+			}
+			_ => None,
+		}
+	}
+
 	/// Execute a single-register arhimetic instructiln.
-	fn execute_register(&self, vm: &mut Vm, register: Register) {
+	fn execute_register(&self, vm: &mut Vm, register: Register) -> Result<(), VmError> {
 		let register = register as usize;
 		assert!(register < vm.registers.len());
 		let value = vm.registers[register];
 		match self.op {
 			ArthimeticOp::Shl => {
+				vm.registers[register] = value << 1;
 				vm.registers[0xF] = (value >> 7) & 1;
-				vm.registers[register] <<= 1;
 			}
 			ArthimeticOp::Shr => {
+				vm.registers[register] = value >> 1;
 				vm.registers[0xF] = value & 1;
-				vm.registers[register] >>= 1;
 			}
-			_ => unreachable!(),
+			_ => return Err(vm.invalid_opcode()),
 		}
+		Ok(())
 	}
 
 	/// Execute a register-register arthimetic instruction.
-	fn execute_register_register(&self, vm: &mut Vm, register_a: Register, register_b: Register) {
+	fn execute_register_register(
+		&self,
+		vm: &mut Vm,
+		register_a: Register,
+		register_b: Register,
+	) -> Result<(), VmError> {
 		let register_a = register_a as usize;
 		let register_b = register_b as usize;
 		assert!(register_a < vm.registers.len() && register_b < vm.registers.len());
@@ -102,21 +128,26 @@ impl ArthimeticInstruction {
 					vm.registers[0xF] = !overflow as u8;
 				}
 			}
-			ArthimeticOp::ShlOld => {
-				vm.registers[register_a] = vm.registers[register_b];
-				vm.registers[0xF] = (vm.registers[register_a] >> 7) & 1;
-				vm.registers[register_a] <<= 1;
+			ArthimeticOp::Shl => {
+				let value = if vm.quirks.shift_vy { value_b } else { value_a };
+				vm.registers[register_a] = value << 1;
+				vm.registers[0xF] = (value >> 7) & 1;
 			}
-			ArthimeticOp::ShrOld => {
-				vm.registers[register_a] = vm.registers[register_b];
-				vm.registers[0xF] = vm.registers[register_a] & 1;
-				vm.registers[register_a] >>= 1;
+			ArthimeticOp::Shr => {
+				let value = if vm.quirks.shift_vy { value_b } else { value_a };
+				vm.registers[register_a] = value >> 1;
+				vm.registers[0xF] = value & 1;
 			}
-			_ => unreachable!(),
 		}
+		Ok(())
 	}
 
-	fn execute_register_value(&self, vm: &mut Vm, register: Register, value: Value) {
+	fn execute_register_value(
+		&self,
+		vm: &mut Vm,
+		register: Register,
+		value: Value,
+	) -> Result<(), VmError> {
 		let register = register as usize;
 		assert!(register < vm.registers.len());
 		let register_value = vm.registers[register];
@@ -139,19 +170,20 @@ impl ArthimeticInstruction {
 					vm.registers[0xF] = !overflow as u8;
 				}
 			}
-			_ => unreachable!(),
+			_ => return Err(vm.invalid_opcode()),
 		}
+		Ok(())
 	}
 
 	/// Execute the instruction on the CHIP-8 VM.
-	pub fn execute(self, vm: &mut Vm) {
+	pub fn execute(self, vm: &mut Vm) -> Result<(), VmError> {
 		match self.values {
 			ArthimeticValue::Register(register) => self.execute_register(vm, register),
 			ArthimeticValue::RegisterValue(register, value) => {
-				self.execute_register_value(vm, register, value);
+				self.execute_register_value(vm, register, value)
 			}
 			ArthimeticValue::RegisterRegister(register_a, register_b) => {
-				self.execute_register_register(vm, register_a, register_b);
+				self.execute_register_register(vm, register_a, register_b)
 			}
 		}
 	}
@@ -1,6 +1,7 @@
 use crate::{
+	error::VmError,
 	instruction::{Address, Register, Value},
-	vm::{ProgramCounter, Vm},
+	vm::{ProgramCounter, Vm, STACK_SIZE},
 };
 
 /// The type of branch/jump that will be taken by the instruction.
@@ -31,8 +32,9 @@ pub enum BranchTarget {
 	/// The instruction will jump to the target address.
 	#[display(fmt = "Jump to 0x{:X}", _0)]
 	Address(Address),
-	/// The instruction will jump to the target address + offset.
-	#[display(fmt = "Jump to 0x{:X} + V{:X}", _0, _1)]
+	/// The instruction will jump to the target address + V0,
+	/// or + the register if the jump-with-Vx quirk is enabled.
+	#[display(fmt = "Jump to 0x{:X} + V0/V{:X}", _0, _1)]
 	AddressOffset(Address, Register),
 	/// The instruction will skip the next instruction.
 	#[display(fmt = "Skip Next Instruction")]
@@ -61,11 +63,49 @@ impl BranchInstruction {
 		}
 	}
 
+	/// Encodes the instruction into its opcode, if it has one.
+	pub fn encode(&self) -> Option<u16> {
+		let opcode = match (self.branch_type, self.branch_target, self.inverted) {
+			(BranchType::Unconditional, BranchTarget::Address(address), false) => 0x1000 | address,
+			(BranchType::Call, BranchTarget::Address(address), false) => 0x2000 | address,
+			(BranchType::Unconditional, BranchTarget::AddressOffset(address, _), false) => {
+				0xB000 | address
+			}
+			(BranchType::Equal { register, value }, BranchTarget::Skip, inverted) => {
+				let base = if inverted { 0x4000 } else { 0x3000 };
+				base | (register as u16) << 8 | value as u16
+			}
+			(
+				BranchType::EqualRegister {
+					register_a,
+					register_b,
+				},
+				BranchTarget::Skip,
+				inverted,
+			) => {
+				let base = if inverted { 0x9000 } else { 0x5000 };
+				base | (register_a as u16) << 8 | (register_b as u16) << 4
+			}
+			(BranchType::KeyPressed { register }, BranchTarget::Skip, inverted) => {
+				let base = if inverted { 0xE0A1 } else { 0xE09E };
+				base | (register as u16) << 8
+			}
+			_ => return None,
+		};
+		Some(opcode)
+	}
+
 	/// Execute the branch instruction.
-	pub fn execute(self, vm: &mut Vm) -> ProgramCounter {
+	pub fn execute(self, vm: &mut Vm) -> Result<ProgramCounter, VmError> {
 		let should_branch = match self.branch_type {
 			BranchType::Unconditional => true,
 			BranchType::Call => {
+				if vm.stack.len() >= STACK_SIZE {
+					return Err(VmError::StackOverflow {
+						pc: vm.program_counter,
+						opcode: vm.opcode(),
+					});
+				}
 				vm.stack.push(vm.program_counter);
 				true
 			}
@@ -85,15 +125,19 @@ impl BranchInstruction {
 			}
 			BranchType::KeyPressed { register } => {
 				let register = register as usize;
-				assert!(register < vm.keypad.len());
-				vm.keypad[vm.registers[register] as usize]
+				assert!(register < vm.registers.len());
+				vm.keypad[(vm.registers[register] & 0xF) as usize]
 			}
 		} ^ self.inverted;
-		if should_branch {
+		Ok(if should_branch {
 			match self.branch_target {
 				BranchTarget::Address(address) => ProgramCounter::Jump(address),
 				BranchTarget::AddressOffset(address, offset_register) => {
-					let offset_register = offset_register as usize;
+					let offset_register = if vm.quirks.jump_vx {
+						offset_register as usize
+					} else {
+						0
+					};
 					assert!(offset_register < vm.registers.len());
 					let offset = vm.registers[offset_register] as u16;
 					ProgramCounter::Jump(address + offset)
@@ -102,6 +146,6 @@ impl BranchInstruction {
 			}
 		} else {
 			ProgramCounter::Next
-		}
+		})
 	}
 }
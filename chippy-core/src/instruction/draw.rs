@@ -0,0 +1,130 @@
+use crate::{
+	display::PLANES,
+	error::VmError,
+	instruction::{Register, Value},
+	vm::Vm,
+};
+
+/// The display planes selected for drawing.
+fn selected_planes(vm: &Vm) -> impl Iterator<Item = usize> {
+	let mask = vm.plane;
+	(0..PLANES).filter(move |plane| mask & (1 << plane) != 0)
+}
+
+pub fn clear(vm: &mut Vm) {
+	for plane in selected_planes(vm).collect::<Vec<_>>() {
+		vm.display.clear(plane);
+	}
+}
+
+/// Scrolls the selected planes by `dx` columns and `dy` rows.
+/// Pixels scrolled off the screen are lost, and blank pixels are scrolled in.
+pub fn scroll(vm: &mut Vm, dx: isize, dy: isize) {
+	let (width, height) = (vm.display.width(), vm.display.height());
+	for plane in selected_planes(vm).collect::<Vec<_>>() {
+		let old = vm.display.plane(plane).to_bitvec();
+		for y in 0..height {
+			for x in 0..width {
+				let old_x = x as isize - dx;
+				let old_y = y as isize - dy;
+				let pixel = (0..width as isize).contains(&old_x)
+					&& (0..height as isize).contains(&old_y)
+					&& old[old_y as usize * width + old_x as usize];
+				vm.display.set(plane, x, y, pixel);
+			}
+		}
+	}
+}
+
+/// The width and height of the sprite that Dxyn draws with `rows` as n.
+fn sprite_shape(vm: &Vm, rows: Value) -> (usize, usize) {
+	// SUPER-CHIP's Dxy0 draws a 16x16 sprite, or an 8x16 sprite in low resolution mode.
+	if rows == 0 && vm.mode.is_super_chip() {
+		if vm.high_resolution || vm.quirks.lores_16x16 {
+			(16, 16)
+		} else {
+			(8, 16)
+		}
+	} else {
+		(8, rows as usize)
+	}
+}
+
+/// How many bytes Dxyn reads from I with `rows` as n, which is a sprite for each selected plane.
+pub fn sprite_bytes(vm: &Vm, rows: Value) -> usize {
+	let (sprite_width, rows) = sprite_shape(vm, rows);
+	rows * sprite_width / 8 * selected_planes(vm).count()
+}
+
+pub fn draw(vm: &mut Vm, x_reg: Register, y_reg: Register, rows: Value) -> Result<(), VmError> {
+	let x_reg = x_reg as usize;
+	let y_reg = y_reg as usize;
+	assert!(x_reg < vm.registers.len() && y_reg < vm.registers.len());
+	let (sprite_width, rows) = sprite_shape(vm, rows);
+	let row_size = sprite_width / 8;
+	let sprite_size = rows * row_size;
+	let planes = selected_planes(vm).collect::<Vec<_>>();
+	let mut memory_location = vm.index_register as usize;
+	// Each selected plane is drawn with its own sprite, one after the other.
+	let memory_range = vm.memory_range(memory_location, sprite_size * planes.len())?;
+	vm.watch_read(memory_range);
+	let (width, height) = (vm.display.width(), vm.display.height());
+	let x = vm.registers[x_reg] as usize % width;
+	let y = vm.registers[y_reg] as usize % height;
+	vm.registers[0xF] = 0;
+	for plane in planes {
+		for row in 0..rows {
+			let y = y + row;
+			if y >= height && vm.quirks.clip_sprites {
+				break;
+			}
+			let y = y % height;
+			let offset = memory_location + row * row_size;
+			let pixels = if row_size == 2 {
+				u16::from_be_bytes([vm.memory[offset], vm.memory[offset + 1]])
+			} else {
+				(vm.memory[offset] as u16) << 8
+			};
+			for col in 0..sprite_width {
+				let x = x + col;
+				if x >= width && vm.quirks.clip_sprites {
+					break;
+				}
+				let x = x % width;
+				let bit = (pixels >> (15 - col)) & 1 != 0;
+				let set_pixel = vm.display.get(plane, x, y);
+				if bit && set_pixel {
+					vm.registers[0xF] = 1;
+				}
+				vm.display.set(plane, x, y, bit ^ set_pixel)
+			}
+		}
+		memory_location += sprite_size;
+	}
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vm::VmMode;
+
+	#[test]
+	fn sprite_bytes_depend_on_the_mode_and_planes() {
+		let mut vm = Vm::new();
+		assert_eq!(sprite_bytes(&vm, 5), 5);
+		assert_eq!(sprite_bytes(&vm, 0), 0);
+
+		vm.set_mode(VmMode::SuperChip);
+		assert_eq!(sprite_bytes(&vm, 0), 16);
+		vm.set_high_resolution(true);
+		assert_eq!(sprite_bytes(&vm, 0), 32);
+
+		vm.set_mode(VmMode::XoChip);
+		vm.set_high_resolution(false);
+		assert_eq!(sprite_bytes(&vm, 0), 32);
+		vm.plane = 0b11;
+		assert_eq!(sprite_bytes(&vm, 0), 64);
+		assert_eq!(sprite_bytes(&vm, 3), 6);
+	}
+}
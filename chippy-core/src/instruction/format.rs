@@ -0,0 +1,393 @@
+use crate::{
+	instruction::{
+		arthimetic::{ArthimeticInstruction, ArthimeticOp, ArthimeticValue},
+		branch::{BranchInstruction, BranchTarget, BranchType},
+		load::{LoadInstruction, LoadTarget},
+		logical::{LogicalInstruction, LogicalOp},
+		Address, Instruction,
+	},
+	quirks::Quirks,
+	vm::VmMode,
+};
+use std::str::FromStr;
+
+/// A syntax that instructions and data can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Syntax {
+	/// Cowgod's mnemonics, as read by the assembler.
+	Cowgod,
+	/// Octo, as read by the Octo compiler.
+	Octo,
+	/// The raw bytes in hexadecimal.
+	Hex,
+}
+
+impl Syntax {
+	/// Every syntax.
+	pub const ALL: [Syntax; 3] = [Syntax::Cowgod, Syntax::Octo, Syntax::Hex];
+
+	/// Writes an instruction, using `label` to look up the names of addresses.
+	/// The quirks decide which register Bnnn jumps with.
+	pub fn instruction(
+		self,
+		instruction: &Instruction,
+		mode: VmMode,
+		quirks: &Quirks,
+		label: impl Fn(Address) -> Option<String>,
+	) -> String {
+		let text = match self {
+			Syntax::Cowgod => cowgod(instruction, quirks, &label),
+			Syntax::Octo => octo(instruction, quirks, &label),
+			Syntax::Hex => None,
+		};
+		// Instructions without a mnemonic are written as data.
+		text.unwrap_or_else(|| self.bytes(&instruction.encode(mode).unwrap_or_default()))
+	}
+
+	/// Writes bytes of data.
+	pub fn bytes(self, data: &[u8]) -> String {
+		let bytes = data.iter().map(|byte| format!("0x{:02X}", byte));
+		match self {
+			Syntax::Cowgod => format!("db {}", bytes.collect::<Vec<_>>().join(", ")),
+			Syntax::Octo => bytes.collect::<Vec<_>>().join(" "),
+			Syntax::Hex => data.iter().map(|byte| format!("{:02X}", byte)).collect(),
+		}
+	}
+
+	/// Writes a sprite with a line for each row, which is two bytes when it's wide.
+	pub fn sprite(self, wide: bool, data: &[u8]) -> String {
+		let row_size = if wide { 2 } else { 1 };
+		data.chunks(row_size)
+			.map(|row| match (self, row) {
+				(Syntax::Cowgod, [byte]) => format!("db %{:08b}", byte),
+				(Syntax::Cowgod, [high, low]) => format!("dw %{:08b}{:08b}", high, low),
+				(Syntax::Octo, _) => row
+					.iter()
+					.map(|byte| format!("0b{:08b}", byte))
+					.collect::<Vec<_>>()
+					.join(" "),
+				_ => self.bytes(row),
+			})
+			.collect::<Vec<_>>()
+			.join("\n")
+	}
+
+	/// Writes the definition of a label at the current address.
+	pub fn label(self, name: &str) -> String {
+		match self {
+			Syntax::Octo => format!(": {}", name),
+			_ => format!("{}:", name),
+		}
+	}
+}
+
+impl FromStr for Syntax {
+	type Err = String;
+
+	/// Parses a syntax from its name, ignoring case.
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		Syntax::ALL
+			.into_iter()
+			.find(|syntax| syntax.to_string().eq_ignore_ascii_case(name))
+			.ok_or_else(|| format!("unknown syntax `{}`", name))
+	}
+}
+
+/// Writes an address as its label, or in hexadecimal if it doesn't have one.
+fn address(label: &impl Fn(Address) -> Option<String>, address: Address) -> String {
+	label(address).unwrap_or_else(|| format!("0x{:03X}", address))
+}
+
+/// Writes an instruction as Cowgod's mnemonics, if it has one.
+fn cowgod(
+	instruction: &Instruction,
+	quirks: &Quirks,
+	label: &impl Fn(Address) -> Option<String>,
+) -> Option<String> {
+	let address = |target| address(label, target);
+	let text = match *instruction {
+		Instruction::Sys(target) => format!("SYS {}", address(target)),
+		Instruction::Clear => "CLS".to_string(),
+		Instruction::Return => "RET".to_string(),
+		Instruction::ScrollDown(rows) => format!("SCD {}", rows),
+		Instruction::ScrollUp(rows) => format!("SCU {}", rows),
+		Instruction::ScrollRight => "SCR".to_string(),
+		Instruction::ScrollLeft => "SCL".to_string(),
+		Instruction::Exit => "EXIT".to_string(),
+		Instruction::Random(x, mask) => format!("RND V{:X}, 0x{:02X}", x, mask),
+		Instruction::Draw(x, y, rows) => format!("DRW V{:X}, V{:X}, {}", x, y, rows),
+		Instruction::LoadKey(x) => format!("LD V{:X}, K", x),
+		Instruction::AddI(x) => format!("ADD I, V{:X}", x),
+		Instruction::SetHighResolution(true) => "HIGH".to_string(),
+		Instruction::SetHighResolution(false) => "LOW".to_string(),
+		Instruction::SelectPlane(planes) => format!("PLANE {}", planes),
+		Instruction::Load(LoadInstruction { from, into }) => match (from, into) {
+			(LoadTarget::Value(value), LoadTarget::Register(x)) => {
+				format!("LD V{:X}, 0x{:02X}", x, value)
+			}
+			(LoadTarget::Register(y), LoadTarget::Register(x)) => format!("LD V{:X}, V{:X}", x, y),
+			(LoadTarget::Address(target), LoadTarget::I) => format!("LD I, {}", address(target)),
+			(LoadTarget::LongAddress(target), LoadTarget::I) => {
+				format!("LD I, LONG {}", address(target))
+			}
+			(LoadTarget::DelayTimer, LoadTarget::Register(x)) => format!("LD V{:X}, DT", x),
+			(LoadTarget::Register(x), LoadTarget::DelayTimer) => format!("LD DT, V{:X}", x),
+			(LoadTarget::Register(x), LoadTarget::SoundTimer) => format!("LD ST, V{:X}", x),
+			(LoadTarget::Font(x), LoadTarget::I) => format!("LD F, V{:X}", x),
+			(LoadTarget::BigFont(x), LoadTarget::I) => format!("LD HF, V{:X}", x),
+			(LoadTarget::Register(x), LoadTarget::Bcd) => format!("LD B, V{:X}", x),
+			(LoadTarget::Register(x), LoadTarget::I) => format!("LD [I], V{:X}", x),
+			(LoadTarget::I, LoadTarget::Register(x)) => format!("LD V{:X}, [I]", x),
+			(LoadTarget::RegisterRange(x, y), LoadTarget::I) => {
+				format!("LD [I], V{:X}-V{:X}", x, y)
+			}
+			(LoadTarget::I, LoadTarget::RegisterRange(x, y)) => {
+				format!("LD V{:X}-V{:X}, [I]", x, y)
+			}
+			(LoadTarget::Register(x), LoadTarget::Rpl) => format!("LD R, V{:X}", x),
+			(LoadTarget::Rpl, LoadTarget::Register(x)) => format!("LD V{:X}, R", x),
+			(LoadTarget::Register(x), LoadTarget::Pitch) => format!("LD PITCH, V{:X}", x),
+			(LoadTarget::I, LoadTarget::AudioPattern) => "AUDIO".to_string(),
+			_ => return None,
+		},
+		Instruction::Branch(BranchInstruction {
+			branch_type,
+			branch_target,
+			inverted,
+		}) => {
+			let skip = if inverted { "SNE" } else { "SE" };
+			match (branch_type, branch_target) {
+				(BranchType::Unconditional, BranchTarget::Address(target)) => {
+					format!("JP {}", address(target))
+				}
+				(BranchType::Unconditional, BranchTarget::AddressOffset(target, x)) => {
+					let x = if quirks.jump_vx { x } else { 0 };
+					format!("JP V{:X}, {}", x, address(target))
+				}
+				(BranchType::Call, BranchTarget::Address(target)) => {
+					format!("CALL {}", address(target))
+				}
+				(BranchType::Equal { register, value }, BranchTarget::Skip) => {
+					format!("{} V{:X}, 0x{:02X}", skip, register, value)
+				}
+				(
+					BranchType::EqualRegister {
+						register_a,
+						register_b,
+					},
+					BranchTarget::Skip,
+				) => format!("{} V{:X}, V{:X}", skip, register_a, register_b),
+				(BranchType::KeyPressed { register }, BranchTarget::Skip) => {
+					let mnemonic = if inverted { "SKNP" } else { "SKP" };
+					format!("{} V{:X}", mnemonic, register)
+				}
+				_ => return None,
+			}
+		}
+		Instruction::Logical(LogicalInstruction {
+			op,
+			register_a,
+			register_b,
+		}) => {
+			let mnemonic = match op {
+				LogicalOp::And => "AND",
+				LogicalOp::Or => "OR",
+				LogicalOp::Xor => "XOR",
+			};
+			format!("{} V{:X}, V{:X}", mnemonic, register_a, register_b)
+		}
+		Instruction::Arthimetic(ArthimeticInstruction {
+			op,
+			values,
+			inverted,
+			..
+		}) => match (op, values, inverted) {
+			(ArthimeticOp::Add, ArthimeticValue::RegisterValue(x, value), false) => {
+				format!("ADD V{:X}, 0x{:02X}", x, value)
+			}
+			(ArthimeticOp::Add, ArthimeticValue::RegisterRegister(x, y), false) => {
+				format!("ADD V{:X}, V{:X}", x, y)
+			}
+			(ArthimeticOp::Sub, ArthimeticValue::RegisterRegister(x, y), inverted) => {
+				let mnemonic = if inverted { "SUBN" } else { "SUB" };
+				format!("{} V{:X}, V{:X}", mnemonic, x, y)
+			}
+			(ArthimeticOp::Shr, ArthimeticValue::RegisterRegister(x, y), false) => {
+				format!("SHR V{:X}, V{:X}", x, y)
+			}
+			(ArthimeticOp::Shl, ArthimeticValue::RegisterRegister(x, y), false) => {
+				format!("SHL V{:X}, V{:X}", x, y)
+			}
+			_ => return None,
+		},
+	};
+	Some(text)
+}
+
+/// Writes an instruction as Octo, if it can be. Calls to addresses without a label
+/// are written with `:call`.
+fn octo(
+	instruction: &Instruction,
+	quirks: &Quirks,
+	label: &impl Fn(Address) -> Option<String>,
+) -> Option<String> {
+	let address = |target| address(label, target);
+	let text = match *instruction {
+		Instruction::Sys(target) => format!("native {}", address(target)),
+		Instruction::Clear => "clear".to_string(),
+		Instruction::Return => "return".to_string(),
+		Instruction::ScrollDown(rows) => format!("scroll-down {}", rows),
+		Instruction::ScrollUp(rows) => format!("scroll-up {}", rows),
+		Instruction::ScrollRight => "scroll-right".to_string(),
+		Instruction::ScrollLeft => "scroll-left".to_string(),
+		Instruction::Exit => "exit".to_string(),
+		Instruction::Random(x, mask) => format!("v{:x} := random {}", x, mask),
+		Instruction::Draw(x, y, rows) => format!("sprite v{:x} v{:x} {}", x, y, rows),
+		Instruction::LoadKey(x) => format!("v{:x} := key", x),
+		Instruction::AddI(x) => format!("i += v{:x}", x),
+		Instruction::SetHighResolution(true) => "hires".to_string(),
+		Instruction::SetHighResolution(false) => "lores".to_string(),
+		Instruction::SelectPlane(planes) => format!("plane {}", planes),
+		Instruction::Load(LoadInstruction { from, into }) => match (from, into) {
+			(LoadTarget::Value(value), LoadTarget::Register(x)) => format!("v{:x} := {}", x, value),
+			(LoadTarget::Register(y), LoadTarget::Register(x)) => format!("v{:x} := v{:x}", x, y),
+			(LoadTarget::Address(target), LoadTarget::I) => format!("i := {}", address(target)),
+			(LoadTarget::LongAddress(target), LoadTarget::I) => {
+				format!("i := long {}", address(target))
+			}
+			(LoadTarget::DelayTimer, LoadTarget::Register(x)) => format!("v{:x} := delay", x),
+			(LoadTarget::Register(x), LoadTarget::DelayTimer) => format!("delay := v{:x}", x),
+			(LoadTarget::Register(x), LoadTarget::SoundTimer) => format!("buzzer := v{:x}", x),
+			(LoadTarget::Font(x), LoadTarget::I) => format!("i := hex v{:x}", x),
+			(LoadTarget::BigFont(x), LoadTarget::I) => format!("i := bighex v{:x}", x),
+			(LoadTarget::Register(x), LoadTarget::Bcd) => format!("bcd v{:x}", x),
+			(LoadTarget::Register(x), LoadTarget::I) => format!("save v{:x}", x),
+			(LoadTarget::I, LoadTarget::Register(x)) => format!("load v{:x}", x),
+			(LoadTarget::RegisterRange(x, y), LoadTarget::I) => format!("save v{:x} - v{:x}", x, y),
+			(LoadTarget::I, LoadTarget::RegisterRange(x, y)) => format!("load v{:x} - v{:x}", x, y),
+			(LoadTarget::Register(x), LoadTarget::Rpl) => format!("saveflags v{:x}", x),
+			(LoadTarget::Rpl, LoadTarget::Register(x)) => format!("loadflags v{:x}", x),
+			(LoadTarget::Register(x), LoadTarget::Pitch) => format!("pitch := v{:x}", x),
+			(LoadTarget::I, LoadTarget::AudioPattern) => "audio".to_string(),
+			_ => return None,
+		},
+		// `if ... then` skips the next instruction when the condition is false,
+		// so the conditions are the opposite of the skips.
+		Instruction::Branch(BranchInstruction {
+			branch_type,
+			branch_target,
+			inverted,
+		}) => {
+			let op = if inverted { "==" } else { "!=" };
+			match (branch_type, branch_target) {
+				(BranchType::Unconditional, BranchTarget::Address(target)) => {
+					format!("jump {}", address(target))
+				}
+				// Octo has no way to write Bxnn, so the register is left in a comment.
+				(BranchType::Unconditional, BranchTarget::AddressOffset(target, x))
+					if quirks.jump_vx =>
+				{
+					format!("jump0 {} # with v{:x}", address(target), x)
+				}
+				(BranchType::Unconditional, BranchTarget::AddressOffset(target, _)) => {
+					format!("jump0 {}", address(target))
+				}
+				(BranchType::Call, BranchTarget::Address(target)) => match label(target) {
+					Some(label) => label,
+					None => format!(":call {}", address(target)),
+				},
+				(BranchType::Equal { register, value }, BranchTarget::Skip) => {
+					format!("if v{:x} {} {} then", register, op, value)
+				}
+				(
+					BranchType::EqualRegister {
+						register_a,
+						register_b,
+					},
+					BranchTarget::Skip,
+				) => format!("if v{:x} {} v{:x} then", register_a, op, register_b),
+				(BranchType::KeyPressed { register }, BranchTarget::Skip) => {
+					let key = if inverted { "key" } else { "-key" };
+					format!("if v{:x} {} then", register, key)
+				}
+				_ => return None,
+			}
+		}
+		Instruction::Logical(LogicalInstruction {
+			op,
+			register_a,
+			register_b,
+		}) => {
+			let op = match op {
+				LogicalOp::And => "&=",
+				LogicalOp::Or => "|=",
+				LogicalOp::Xor => "^=",
+			};
+			format!("v{:x} {} v{:x}", register_a, op, register_b)
+		}
+		Instruction::Arthimetic(ArthimeticInstruction {
+			op,
+			values,
+			inverted,
+			..
+		}) => match (op, values, inverted) {
+			(ArthimeticOp::Add, ArthimeticValue::RegisterValue(x, value), false) => {
+				format!("v{:x} += {}", x, value)
+			}
+			(ArthimeticOp::Add, ArthimeticValue::RegisterRegister(x, y), false) => {
+				format!("v{:x} += v{:x}", x, y)
+			}
+			(ArthimeticOp::Sub, ArthimeticValue::RegisterRegister(x, y), inverted) => {
+				let op = if inverted { "=-" } else { "-=" };
+				format!("v{:x} {} v{:x}", x, op, y)
+			}
+			(ArthimeticOp::Shr, ArthimeticValue::RegisterRegister(x, y), false) => {
+				format!("v{:x} >>= v{:x}", x, y)
+			}
+			(ArthimeticOp::Shl, ArthimeticValue::RegisterRegister(x, y), false) => {
+				format!("v{:x} <<= v{:x}", x, y)
+			}
+			_ => return None,
+		},
+	};
+	Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn offset_jumps_show_their_register() {
+		let jump = Instruction::decode(&[0xB2, 0x34], 0, VmMode::SuperChip).unwrap();
+		let text = |syntax: Syntax, quirks: &Quirks| {
+			syntax.instruction(&jump, VmMode::SuperChip, quirks, |_| None)
+		};
+		assert_eq!(text(Syntax::Cowgod, &Quirks::CHIP8), "JP V0, 0x234");
+		assert_eq!(text(Syntax::Cowgod, &Quirks::SUPER_CHIP), "JP V2, 0x234");
+		assert_eq!(text(Syntax::Octo, &Quirks::CHIP8), "jump0 0x234");
+		assert_eq!(
+			text(Syntax::Octo, &Quirks::SUPER_CHIP),
+			"jump0 0x234 # with v2"
+		);
+		assert_eq!(text(Syntax::Hex, &Quirks::SUPER_CHIP), "B234");
+	}
+
+	#[test]
+	fn labels() {
+		let call = Instruction::decode(&[0x23, 0x00], 0, VmMode::Chip8).unwrap();
+		let label = |address: Address| (address == 0x300).then(|| "draw".to_string());
+		assert_eq!(
+			Syntax::Cowgod.instruction(&call, VmMode::Chip8, &Quirks::CHIP8, label),
+			"CALL draw"
+		);
+		assert_eq!(
+			Syntax::Octo.instruction(&call, VmMode::Chip8, &Quirks::CHIP8, label),
+			"draw"
+		);
+		assert_eq!(
+			Syntax::Octo.instruction(&call, VmMode::Chip8, &Quirks::CHIP8, |_| None),
+			":call 0x300"
+		);
+	}
+}
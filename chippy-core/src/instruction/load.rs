@@ -0,0 +1,230 @@
+use crate::{
+	error::VmError,
+	instruction::{Address, Register, Value},
+	quirks::IndexIncrement,
+	vm::{Vm, BIG_FONT_ADDRESS, FONT_ADDRESS},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+/// The target to load the value into.
+pub enum LoadTarget {
+	/// Load a register.
+	#[display(fmt = "V{:X}", _0)]
+	Register(Register),
+	/// Load an address.
+	#[display(fmt = "Memory(0x{:X})", _0)]
+	Address(Address),
+	/// Load a value.
+	#[display(fmt = "0x{:X}", _0)]
+	Value(Value),
+	/// Load a 16-bit address (XO-CHIP).
+	#[display(fmt = "Memory(0x{:04X})", _0)]
+	LongAddress(Address),
+	/// Load I.
+	#[display(fmt = "I")]
+	I,
+	/// Load a range of registers, in either order (XO-CHIP).
+	#[display(fmt = "V{:X}-V{:X}", _0, _1)]
+	RegisterRange(Register, Register),
+	/// Load the font character for the digit in a register.
+	#[display(fmt = "Font(V{:X})", _0)]
+	Font(Register),
+	/// Load the large font character for the digit in a register (SUPER-CHIP).
+	#[display(fmt = "Big Font(V{:X})", _0)]
+	BigFont(Register),
+	/// Load the delay timer.
+	#[display(fmt = "Delay Timer")]
+	DelayTimer,
+	/// Load the sound timer.
+	#[display(fmt = "Sound Timer")]
+	SoundTimer,
+	/// Load the BCD representation of a register
+	#[display(fmt = "BCD")]
+	Bcd,
+	/// SUPER-CHIP persistent RPL user flags.
+	Rpl,
+	/// The XO-CHIP audio pattern buffer.
+	#[display(fmt = "Audio Pattern")]
+	AudioPattern,
+	/// The XO-CHIP audio pitch register.
+	Pitch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+#[display(fmt = "Load from {} into {}", from, into)]
+pub struct LoadInstruction {
+	pub from: LoadTarget,
+	pub into: LoadTarget,
+}
+
+/// Changes I after Fx55 or Fx65 accessed registers V0 to `last_register`.
+fn increment_index(vm: &mut Vm, last_register: usize) {
+	let increment = match vm.quirks.index_increment {
+		IndexIncrement::XPlusOne => last_register as u16 + 1,
+		IndexIncrement::X => last_register as u16,
+		IndexIncrement::Unchanged => 0,
+	};
+	vm.index_register = vm.index_register.wrapping_add(increment);
+}
+
+/// The registers from `first` to `last`, counting down if `last` is lower.
+fn register_range(first: Register, last: Register) -> Vec<usize> {
+	let (first, last) = (first as usize, last as usize);
+	if first <= last {
+		(first..=last).collect()
+	} else {
+		(last..=first).rev().collect()
+	}
+}
+
+impl LoadInstruction {
+	/// Encodes the instruction into its opcode, if it has a 2-byte opcode.
+	pub fn encode(&self) -> Option<u16> {
+		let x = |register: Register| (register as u16) << 8;
+		let opcode = match (self.from, self.into) {
+			(LoadTarget::Value(value), LoadTarget::Register(into)) => {
+				0x6000 | x(into) | value as u16
+			}
+			(LoadTarget::Register(from), LoadTarget::Register(into)) => {
+				0x8000 | x(into) | (from as u16) << 4
+			}
+			(LoadTarget::Address(address), LoadTarget::I) => 0xA000 | address,
+			(LoadTarget::RegisterRange(first, last), LoadTarget::I) => {
+				0x5002 | x(first) | (last as u16) << 4
+			}
+			(LoadTarget::I, LoadTarget::RegisterRange(first, last)) => {
+				0x5003 | x(first) | (last as u16) << 4
+			}
+			(LoadTarget::I, LoadTarget::AudioPattern) => 0xF002,
+			(LoadTarget::DelayTimer, LoadTarget::Register(into)) => 0xF007 | x(into),
+			(LoadTarget::Register(from), LoadTarget::DelayTimer) => 0xF015 | x(from),
+			(LoadTarget::Register(from), LoadTarget::SoundTimer) => 0xF018 | x(from),
+			(LoadTarget::Font(from), LoadTarget::I) => 0xF029 | x(from),
+			(LoadTarget::BigFont(from), LoadTarget::I) => 0xF030 | x(from),
+			(LoadTarget::Register(from), LoadTarget::Bcd) => 0xF033 | x(from),
+			(LoadTarget::Register(from), LoadTarget::Pitch) => 0xF03A | x(from),
+			(LoadTarget::Register(last), LoadTarget::I) => 0xF055 | x(last),
+			(LoadTarget::I, LoadTarget::Register(last)) => 0xF065 | x(last),
+			(LoadTarget::Register(last), LoadTarget::Rpl) => 0xF075 | x(last),
+			(LoadTarget::Rpl, LoadTarget::Register(last)) => 0xF085 | x(last),
+			_ => return None,
+		};
+		Some(opcode)
+	}
+
+	/// Execute the load instruction.
+	pub fn execute(self, vm: &mut Vm) -> Result<(), VmError> {
+		match (self.from, self.into) {
+			(LoadTarget::Address(address), LoadTarget::I)
+			| (LoadTarget::LongAddress(address), LoadTarget::I) => {
+				vm.index_register = address;
+			}
+			(LoadTarget::Register(from), LoadTarget::Register(into)) => {
+				let from = from as usize;
+				let into = into as usize;
+				assert!(from < vm.registers.len() && into < vm.registers.len());
+				vm.registers[into] = vm.registers[from];
+			}
+			(LoadTarget::Value(from), LoadTarget::Register(into)) => {
+				let into = into as usize;
+				assert!(into < vm.registers.len());
+				vm.registers[into] = from;
+			}
+			(LoadTarget::DelayTimer, LoadTarget::Register(into)) => {
+				let into = into as usize;
+				assert!(into < vm.registers.len());
+				vm.registers[into] = vm.delay_timer;
+			}
+			(LoadTarget::Register(from), LoadTarget::DelayTimer) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				vm.delay_timer = vm.registers[from];
+			}
+			(LoadTarget::SoundTimer, LoadTarget::Register(into)) => {
+				let into = into as usize;
+				assert!(into < vm.registers.len());
+				vm.registers[into] = vm.sound_timer;
+			}
+			(LoadTarget::Register(from), LoadTarget::SoundTimer) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				vm.sound_timer = vm.registers[from];
+			}
+			(LoadTarget::Font(from), LoadTarget::I) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				let digit = (vm.registers[from] & 0xF) as u16;
+				vm.index_register = FONT_ADDRESS + digit * 5;
+			}
+			(LoadTarget::BigFont(from), LoadTarget::I) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				let digit = (vm.registers[from] & 0xF) as u16;
+				vm.index_register = BIG_FONT_ADDRESS + digit * 10;
+			}
+			(LoadTarget::Register(from), LoadTarget::Bcd) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				let value = vm.registers[from];
+				let memory_range = vm.memory_range(vm.index_register as usize, 3)?;
+				let bcd = [value / 100, (value / 10) % 10, value % 10];
+				vm.write_memory(memory_range.start, &bcd);
+			}
+			(LoadTarget::I, LoadTarget::Register(into)) => {
+				let into = into as usize;
+				assert!(into < vm.registers.len());
+				let memory_range = vm.memory_range(vm.index_register as usize, into + 1)?;
+				vm.watch_read(memory_range.clone());
+				vm.registers[0..=into].copy_from_slice(&vm.memory[memory_range]);
+				increment_index(vm, into);
+			}
+			(LoadTarget::Register(from), LoadTarget::I) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				let memory_range = vm.memory_range(vm.index_register as usize, from + 1)?;
+				let registers = vm.registers;
+				vm.write_memory(memory_range.start, &registers[0..=from]);
+				increment_index(vm, from);
+			}
+			(LoadTarget::Register(from), LoadTarget::Rpl) => {
+				let from = from as usize;
+				assert!(from < vm.rpl.len());
+				vm.rpl[0..=from].copy_from_slice(&vm.registers[0..=from]);
+			}
+			(LoadTarget::Rpl, LoadTarget::Register(into)) => {
+				let into = into as usize;
+				assert!(into < vm.rpl.len());
+				vm.registers[0..=into].copy_from_slice(&vm.rpl[0..=into]);
+			}
+			(LoadTarget::RegisterRange(first, last), LoadTarget::I) => {
+				let registers = register_range(first, last);
+				let memory_range = vm.memory_range(vm.index_register as usize, registers.len())?;
+				let values = registers
+					.iter()
+					.map(|register| vm.registers[*register])
+					.collect::<Vec<_>>();
+				vm.write_memory(memory_range.start, &values);
+			}
+			(LoadTarget::I, LoadTarget::RegisterRange(first, last)) => {
+				let registers = register_range(first, last);
+				let memory_range = vm.memory_range(vm.index_register as usize, registers.len())?;
+				vm.watch_read(memory_range.clone());
+				for (address, register) in memory_range.zip(registers) {
+					vm.registers[register] = vm.memory[address];
+				}
+			}
+			(LoadTarget::I, LoadTarget::AudioPattern) => {
+				let memory_range = vm.memory_range(vm.index_register as usize, 16)?;
+				vm.watch_read(memory_range.clone());
+				vm.audio_pattern.copy_from_slice(&vm.memory[memory_range]);
+			}
+			(LoadTarget::Register(from), LoadTarget::Pitch) => {
+				let from = from as usize;
+				assert!(from < vm.registers.len());
+				vm.pitch = vm.registers[from];
+			}
+			_ => return Err(vm.invalid_opcode()),
+		}
+		Ok(())
+	}
+}
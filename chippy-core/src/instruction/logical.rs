@@ -1,4 +1,4 @@
-use crate::{instruction::Register, vm::Vm};
+use crate::{error::VmError, instruction::Register, vm::Vm};
 
 /// The type of logical operation to perform.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
@@ -26,7 +26,17 @@ pub struct LogicalInstruction {
 }
 
 impl LogicalInstruction {
-	pub fn execute(self, vm: &mut Vm) {
+	/// Encodes the instruction into its opcode.
+	pub fn encode(&self) -> u16 {
+		let op = match self.op {
+			LogicalOp::Or => 0x1,
+			LogicalOp::And => 0x2,
+			LogicalOp::Xor => 0x3,
+		};
+		0x8000 | (self.register_a as u16) << 8 | (self.register_b as u16) << 4 | op
+	}
+
+	pub fn execute(self, vm: &mut Vm) -> Result<(), VmError> {
 		let register_a = self.register_a as usize;
 		let register_b = self.register_b as usize;
 		assert!(register_a < vm.registers.len() && register_b < vm.registers.len());
@@ -37,5 +47,9 @@ impl LogicalInstruction {
 			LogicalOp::Or => *value_a |= value_b,
 			LogicalOp::Xor => *value_a ^= value_b,
 		}
+		if vm.quirks.logic_reset_vf {
+			vm.registers[0xF] = 0;
+		}
+		Ok(())
 	}
 }
@@ -0,0 +1,20 @@
+#[macro_use]
+extern crate derive_more;
+
+pub mod assembler;
+pub mod breakpoint;
+pub mod cache;
+pub mod disassembler;
+pub mod display;
+pub mod error;
+pub mod gdb;
+pub mod instruction;
+pub mod octo;
+pub mod quirks;
+pub mod random;
+pub mod rca1802;
+pub mod rewind;
+pub mod state;
+pub mod timing;
+pub mod vm;
+pub mod watchpoint;
@@ -0,0 +1,1211 @@
+use crate::{
+	assembler::{AsmError, SourceLine, SourceMap},
+	instruction::{
+		arthimetic::{ArthimeticInstruction, ArthimeticOp, ArthimeticValue},
+		branch::{BranchInstruction, BranchTarget, BranchType},
+		format::Syntax,
+		load::{LoadInstruction, LoadTarget},
+		logical::{LogicalInstruction, LogicalOp},
+		Instruction, Register,
+	},
+	vm::{VmMode, PROGRAM_START},
+};
+use std::{
+	collections::{HashMap, VecDeque},
+	fs,
+	path::Path,
+};
+
+/// The register used as scratch space by the comparison pseudo-ops.
+const FLAG_REGISTER: Register = 0xF;
+/// How many macros may be expanded while compiling a program.
+const MAX_EXPANSIONS: usize = 100_000;
+
+/// A whitespace-separated word of source.
+#[derive(Debug, Clone)]
+struct Token {
+	text: String,
+	line: usize,
+	column: usize,
+}
+
+/// A macro defined with `:macro`.
+#[derive(Debug, Clone)]
+struct Macro {
+	arguments: Vec<String>,
+	body: Vec<Token>,
+}
+
+/// A reference to a label that wasn't defined when it was used.
+#[derive(Debug, Clone, Copy)]
+enum Fixup {
+	/// The low 12 bits of the opcode at a position.
+	Address,
+	/// The 16 bit address following `i := long`.
+	Long,
+	/// The two `vx := nn` instructions of `:unpack`, with the nibble to put in v0,
+	/// or `None` to unpack a 16 bit address.
+	Unpack(Option<u8>),
+}
+
+/// An unfinished control structure.
+#[derive(Debug, Clone)]
+enum Block {
+	/// An `if ... begin`, with the position of the jump past it.
+	If { jump: usize },
+	/// A `loop`, with its start and the positions of the jumps out of it from `while`.
+	Loop { start: usize, breaks: Vec<usize> },
+}
+
+/// A comparison in an `if` or `while`.
+#[derive(Debug, Clone)]
+enum Condition {
+	Equal(Register, Operand, bool),
+	Key(Register, bool),
+	/// `a < b`, or `a <= b` when the flag is set.
+	Less(Operand, Operand, bool),
+}
+
+/// A register or a constant value.
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+	Register(Register),
+	Value(u8),
+}
+
+/// Compiles an Octo program into memory that is loaded at 0x200.
+pub fn compile(source: &str, mode: VmMode) -> Result<Vec<u8>, AsmError> {
+	Compiler::new(source, "<source>", mode)
+		.run()
+		.map(|(rom, _)| rom)
+}
+
+/// Compiles an Octo source file.
+pub fn compile_file(path: &Path, mode: VmMode) -> Result<Vec<u8>, AsmError> {
+	compile_file_with_map(path, mode).map(|(rom, _)| rom)
+}
+
+/// Compiles an Octo source file, along with the source line of each instruction.
+pub fn compile_file_with_map(path: &Path, mode: VmMode) -> Result<(Vec<u8>, SourceMap), AsmError> {
+	let file = path.display().to_string();
+	let source = fs::read_to_string(path).map_err(|error| AsmError {
+		file: file.clone(),
+		line: 0,
+		column: 0,
+		message: error.to_string(),
+	})?;
+	Compiler::new(&source, &file, mode).run()
+}
+
+struct Compiler {
+	file: String,
+	mode: VmMode,
+	tokens: VecDeque<Token>,
+	rom: Vec<u8>,
+	source_map: SourceMap,
+	position: usize,
+	started: bool,
+	labels: HashMap<String, usize>,
+	constants: HashMap<String, i64>,
+	aliases: HashMap<String, Register>,
+	macros: HashMap<String, Macro>,
+	fixups: Vec<(usize, Fixup, Token)>,
+	blocks: Vec<(Block, Token)>,
+	expansions: usize,
+	last: Token,
+}
+
+impl Compiler {
+	fn new(source: &str, file: &str, mode: VmMode) -> Self {
+		Compiler {
+			file: file.to_string(),
+			mode,
+			tokens: tokenize(source),
+			rom: Vec::new(),
+			source_map: SourceMap::default(),
+			position: PROGRAM_START,
+			started: false,
+			labels: HashMap::new(),
+			constants: HashMap::new(),
+			aliases: HashMap::new(),
+			macros: HashMap::new(),
+			fixups: Vec::new(),
+			blocks: Vec::new(),
+			expansions: 0,
+			last: Token {
+				text: String::new(),
+				line: 1,
+				column: 1,
+			},
+		}
+	}
+
+	fn run(mut self) -> Result<(Vec<u8>, SourceMap), AsmError> {
+		while let Some(token) = self.next_token() {
+			self.statement(token)?;
+		}
+		if let Some((block, token)) = self.blocks.pop() {
+			let expected = match block {
+				Block::If { .. } => "end",
+				Block::Loop { .. } => "again",
+			};
+			return Err(self.error(&token, format!("`{}` without `{}`", token.text, expected)));
+		}
+		for (position, fixup, token) in std::mem::take(&mut self.fixups) {
+			let address = match self.labels.get(&token.text) {
+				Some(address) => *address,
+				None => return Err(self.error(&token, format!("undefined label `{}`", token.text))),
+			};
+			self.patch(position, fixup, address, &token)?;
+		}
+		Ok((self.rom, self.source_map))
+	}
+
+	fn error(&self, token: &Token, message: impl Into<String>) -> AsmError {
+		AsmError {
+			file: self.file.clone(),
+			line: token.line,
+			column: token.column,
+			message: message.into(),
+		}
+	}
+
+	fn next_token(&mut self) -> Option<Token> {
+		let token = self.tokens.pop_front()?;
+		self.last = token.clone();
+		Some(token)
+	}
+
+	/// The next token, which must exist.
+	fn expect_token(&mut self) -> Result<Token, AsmError> {
+		self.next_token()
+			.ok_or_else(|| self.error(&self.last, "unexpected end of program"))
+	}
+
+	/// Consumes the next token, which must be `text`.
+	fn expect(&mut self, text: &str) -> Result<Token, AsmError> {
+		let token = self.expect_token()?;
+		if token.text != text {
+			return Err(self.error(
+				&token,
+				format!("expected `{}`, found `{}`", text, token.text),
+			));
+		}
+		Ok(token)
+	}
+
+	/// Consumes the next token if it is `text`.
+	fn eat(&mut self, text: &str) -> bool {
+		if self.tokens.front().is_some_and(|token| token.text == text) {
+			self.next_token();
+			true
+		} else {
+			false
+		}
+	}
+
+	fn statement(&mut self, token: Token) -> Result<(), AsmError> {
+		if let Some(name) = self.macros.get(&token.text).cloned() {
+			return self.expand(&token, name);
+		}
+		if let Some(x) = self.register(&token) {
+			return self.register_statement(x);
+		}
+		match token.text.as_str() {
+			":" => {
+				let name = self.name()?;
+				self.start(name.text == "main");
+				self.define_label(&name, self.position)
+			}
+			":next" => {
+				let name = self.name()?;
+				self.start(false);
+				self.define_label(&name, self.position + 1)
+			}
+			":const" => {
+				let name = self.name()?;
+				let value = self.expect_token()?;
+				let value = self.value(&value)?;
+				self.define(&name)?;
+				self.constants.insert(name.text, value);
+				Ok(())
+			}
+			":alias" => {
+				let name = self.name()?;
+				let register = self.expect_register()?;
+				self.define(&name)?;
+				self.aliases.insert(name.text, register);
+				Ok(())
+			}
+			":macro" => self.define_macro(),
+			":unpack" => {
+				let nibble = self.expect_token()?;
+				let label = self.expect_token()?;
+				if nibble.text == "long" {
+					// XO-CHIP style, with the whole address in v0 and v1.
+					self.unpack(None, &label)
+				} else {
+					let nibble = self.ranged(&nibble, 0, 0xF)? as u8;
+					self.unpack(Some(nibble), &label)
+				}
+			}
+			":org" => {
+				let address = self.expect_token()?;
+				let address = self.ranged(&address, PROGRAM_START as i64, 0xFFFF)?;
+				self.start(false);
+				self.position = address as usize;
+				Ok(())
+			}
+			":byte" => {
+				let value = self.expect_token()?;
+				let value = self.ranged(&value, -0x80, 0xFF)? as u8;
+				self.write(&[value], &token)
+			}
+			":call" => {
+				let target = self.expect_token()?;
+				self.call(&target, &token)
+			}
+			":breakpoint" => self.name().map(|_| ()),
+			":monitor" => {
+				self.expect_token()?;
+				self.expect_token()?;
+				Ok(())
+			}
+			"clear" => self.emit(Instruction::Clear, &token),
+			"return" | ";" => self.emit(Instruction::Return, &token),
+			"hires" => self.emit(Instruction::SetHighResolution(true), &token),
+			"lores" => self.emit(Instruction::SetHighResolution(false), &token),
+			"exit" => self.emit(Instruction::Exit, &token),
+			"scroll-left" => self.emit(Instruction::ScrollLeft, &token),
+			"scroll-right" => self.emit(Instruction::ScrollRight, &token),
+			"scroll-down" => {
+				let rows = self.nibble()?;
+				self.emit(Instruction::ScrollDown(rows), &token)
+			}
+			"scroll-up" => {
+				let rows = self.nibble()?;
+				self.emit(Instruction::ScrollUp(rows), &token)
+			}
+			"plane" => {
+				let planes = self.nibble()?;
+				self.emit(Instruction::SelectPlane(planes), &token)
+			}
+			"audio" => self.emit(load(LoadTarget::I, LoadTarget::AudioPattern), &token),
+			"bcd" => {
+				let x = self.expect_register()?;
+				self.emit(load(LoadTarget::Register(x), LoadTarget::Bcd), &token)
+			}
+			"save" | "load" => {
+				let x = self.expect_register()?;
+				let registers = if self.eat("-") {
+					LoadTarget::RegisterRange(x, self.expect_register()?)
+				} else {
+					LoadTarget::Register(x)
+				};
+				let instruction = if token.text == "save" {
+					load(registers, LoadTarget::I)
+				} else {
+					load(LoadTarget::I, registers)
+				};
+				self.emit(instruction, &token)
+			}
+			"saveflags" => {
+				let x = self.expect_register()?;
+				self.emit(load(LoadTarget::Register(x), LoadTarget::Rpl), &token)
+			}
+			"loadflags" => {
+				let x = self.expect_register()?;
+				self.emit(load(LoadTarget::Rpl, LoadTarget::Register(x)), &token)
+			}
+			"sprite" => {
+				let x = self.expect_register()?;
+				let y = self.expect_register()?;
+				let rows = self.nibble()?;
+				self.emit(Instruction::Draw(x, y, rows), &token)
+			}
+			"jump" => self.branch(BranchType::Unconditional, false, &token),
+			"jump0" => self.branch(BranchType::Unconditional, true, &token),
+			"native" => {
+				let target = self.expect_token()?;
+				let position = self.position;
+				self.emit(Instruction::Sys(0), &token)?;
+				self.reference(position, Fixup::Address, &target)
+			}
+			"delay" | "buzzer" | "pitch" => {
+				self.expect(":=")?;
+				let x = self.expect_register()?;
+				let into = match token.text.as_str() {
+					"delay" => LoadTarget::DelayTimer,
+					"buzzer" => LoadTarget::SoundTimer,
+					_ => LoadTarget::Pitch,
+				};
+				self.emit(load(LoadTarget::Register(x), into), &token)
+			}
+			"i" => self.index_statement(),
+			"if" => {
+				let condition = self.condition()?;
+				let keyword = self.expect_token()?;
+				match keyword.text.as_str() {
+					"then" => self.skip(&condition, false, &token),
+					"begin" => {
+						self.skip(&condition, true, &token)?;
+						let jump = self.jump_placeholder(&keyword)?;
+						self.blocks.push((Block::If { jump }, token));
+						Ok(())
+					}
+					_ => Err(self.error(&keyword, "expected `then` or `begin`")),
+				}
+			}
+			"else" => {
+				let jump = match self.blocks.pop() {
+					Some((Block::If { jump }, _)) => jump,
+					_ => return Err(self.error(&token, "`else` without `if ... begin`")),
+				};
+				let end = self.jump_placeholder(&token)?;
+				self.patch(jump, Fixup::Address, self.position, &token)?;
+				self.blocks.push((Block::If { jump: end }, token));
+				Ok(())
+			}
+			"end" => match self.blocks.pop() {
+				Some((Block::If { jump }, _)) => {
+					self.patch(jump, Fixup::Address, self.position, &token)
+				}
+				_ => Err(self.error(&token, "`end` without `if ... begin`")),
+			},
+			"loop" => {
+				self.start(false);
+				self.blocks.push((
+					Block::Loop {
+						start: self.position,
+						breaks: Vec::new(),
+					},
+					token,
+				));
+				Ok(())
+			}
+			"while" => {
+				let condition = self.condition()?;
+				self.skip(&condition, true, &token)?;
+				let jump = self.jump_placeholder(&token)?;
+				match self
+					.blocks
+					.iter_mut()
+					.rev()
+					.find_map(|(block, _)| match block {
+						Block::Loop { breaks, .. } => Some(breaks),
+						_ => None,
+					}) {
+					Some(breaks) => {
+						breaks.push(jump);
+						Ok(())
+					}
+					None => Err(self.error(&token, "`while` outside of a loop")),
+				}
+			}
+			"again" => match self.blocks.pop() {
+				Some((Block::Loop { start, breaks }, _)) => {
+					let jump = self.jump_placeholder(&token)?;
+					self.patch(jump, Fixup::Address, start, &token)?;
+					for jump in breaks {
+						self.patch(jump, Fixup::Address, self.position, &token)?;
+					}
+					Ok(())
+				}
+				_ => Err(self.error(&token, "`again` without `loop`")),
+			},
+			text if text.starts_with(':') => {
+				Err(self.error(&token, format!("unknown directive `{}`", text)))
+			}
+			_ => {
+				// A number or constant on its own is a byte of data.
+				if let Some(value) = self.constant(&token) {
+					let value = self.check_range(&token, value, -0x80, 0xFF)? as u8;
+					return self.write(&[value], &token);
+				}
+				if !is_name(&token.text) {
+					return Err(self.error(&token, format!("unexpected `{}`", token.text)));
+				}
+				// Anything else is a call to a label.
+				self.call(&token, &token)
+			}
+		}
+	}
+
+	/// Compiles `vx op value`.
+	fn register_statement(&mut self, x: Register) -> Result<(), AsmError> {
+		let op = self.expect_token()?;
+		let source = self.expect_token()?;
+		if op.text == ":=" {
+			let instruction = match source.text.as_str() {
+				"key" => Instruction::LoadKey(x),
+				"delay" => load(LoadTarget::DelayTimer, LoadTarget::Register(x)),
+				"random" => {
+					let mask = self.expect_token()?;
+					Instruction::Random(x, self.ranged(&mask, -0x80, 0xFF)? as u8)
+				}
+				_ => match self.operand(&source)? {
+					Operand::Register(y) => load(LoadTarget::Register(y), LoadTarget::Register(x)),
+					Operand::Value(value) => {
+						load(LoadTarget::Value(value), LoadTarget::Register(x))
+					}
+				},
+			};
+			return self.emit(instruction, &op);
+		}
+		let source = self.operand(&source)?;
+		let instruction = match (op.text.as_str(), source) {
+			("+=", Operand::Value(value)) => arthimetic(
+				ArthimeticOp::Add,
+				ArthimeticValue::RegisterValue(x, value),
+				false,
+				false,
+			),
+			("-=", Operand::Value(value)) => arthimetic(
+				ArthimeticOp::Add,
+				ArthimeticValue::RegisterValue(x, value.wrapping_neg()),
+				false,
+				false,
+			),
+			("+=", Operand::Register(y)) => arthimetic(
+				ArthimeticOp::Add,
+				ArthimeticValue::RegisterRegister(x, y),
+				true,
+				false,
+			),
+			("-=", Operand::Register(y)) | ("=-", Operand::Register(y)) => arthimetic(
+				ArthimeticOp::Sub,
+				ArthimeticValue::RegisterRegister(x, y),
+				true,
+				op.text == "=-",
+			),
+			(">>=", Operand::Register(y)) => arthimetic(
+				ArthimeticOp::Shr,
+				ArthimeticValue::RegisterRegister(x, y),
+				false,
+				false,
+			),
+			("<<=", Operand::Register(y)) => arthimetic(
+				ArthimeticOp::Shl,
+				ArthimeticValue::RegisterRegister(x, y),
+				false,
+				false,
+			),
+			("|=", Operand::Register(y)) => logical(LogicalOp::Or, x, y),
+			("&=", Operand::Register(y)) => logical(LogicalOp::And, x, y),
+			("^=", Operand::Register(y)) => logical(LogicalOp::Xor, x, y),
+			_ => {
+				return Err(self.error(
+					&op,
+					format!("`{}` can't be used with these operands", op.text),
+				))
+			}
+		};
+		self.emit(instruction, &op)
+	}
+
+	/// Compiles `i := ...` and `i += vx`.
+	fn index_statement(&mut self) -> Result<(), AsmError> {
+		let op = self.expect_token()?;
+		if op.text == "+=" {
+			let x = self.expect_register()?;
+			return self.emit(Instruction::AddI(x), &op);
+		}
+		if op.text != ":=" {
+			return Err(self.error(&op, format!("expected `:=` or `+=`, found `{}`", op.text)));
+		}
+		let source = self.expect_token()?;
+		match source.text.as_str() {
+			"hex" | "bighex" => {
+				let x = self.expect_register()?;
+				let from = if source.text == "hex" {
+					LoadTarget::Font(x)
+				} else {
+					LoadTarget::BigFont(x)
+				};
+				self.emit(load(from, LoadTarget::I), &op)
+			}
+			"long" => {
+				let target = self.expect_token()?;
+				let position = self.position;
+				self.emit(load(LoadTarget::LongAddress(0), LoadTarget::I), &op)?;
+				self.reference(position + 2, Fixup::Long, &target)
+			}
+			_ => {
+				let position = self.position;
+				self.emit(load(LoadTarget::Address(0), LoadTarget::I), &op)?;
+				self.reference(position, Fixup::Address, &source)
+			}
+		}
+	}
+
+	/// Compiles `jump` or `jump0`.
+	fn branch(
+		&mut self,
+		branch_type: BranchType,
+		offset: bool,
+		token: &Token,
+	) -> Result<(), AsmError> {
+		let target = self.expect_token()?;
+		let target_type = if offset {
+			BranchTarget::AddressOffset(0, 0)
+		} else {
+			BranchTarget::Address(0)
+		};
+		let position = self.position;
+		self.emit(
+			Instruction::Branch(BranchInstruction::new(branch_type, target_type, false)),
+			token,
+		)?;
+		self.reference(position, Fixup::Address, &target)
+	}
+
+	/// Emits a call to a label or address.
+	fn call(&mut self, target: &Token, token: &Token) -> Result<(), AsmError> {
+		let position = self.position;
+		self.emit(
+			Instruction::Branch(BranchInstruction::new(
+				BranchType::Call,
+				BranchTarget::Address(0),
+				false,
+			)),
+			token,
+		)?;
+		self.reference(position, Fixup::Address, target)
+	}
+
+	/// Emits an unconditional jump to be patched later, returning its position.
+	fn jump_placeholder(&mut self, token: &Token) -> Result<usize, AsmError> {
+		let position = self.position;
+		self.emit(
+			Instruction::Branch(BranchInstruction::new(
+				BranchType::Unconditional,
+				BranchTarget::Address(0),
+				false,
+			)),
+			token,
+		)?;
+		Ok(position)
+	}
+
+	/// Compiles `:unpack`, loading a label's address into v0 and v1.
+	fn unpack(&mut self, nibble: Option<u8>, label: &Token) -> Result<(), AsmError> {
+		let position = self.position;
+		for register in 0..2 {
+			self.emit(
+				load(LoadTarget::Value(0), LoadTarget::Register(register)),
+				label,
+			)?;
+		}
+		self.reference(position, Fixup::Unpack(nibble), label)
+	}
+
+	/// Parses the comparison of an `if` or `while`.
+	fn condition(&mut self) -> Result<Condition, AsmError> {
+		let left = self.expect_token()?;
+		let op = self.expect_token()?;
+		match op.text.as_str() {
+			"key" | "-key" => {
+				let x = self.register_or_error(&left)?;
+				return Ok(Condition::Key(x, op.text == "key"));
+			}
+			"==" | "!=" => {
+				let x = self.register_or_error(&left)?;
+				let right = self.expect_token()?;
+				let right = self.operand(&right)?;
+				return Ok(Condition::Equal(x, right, op.text == "=="));
+			}
+			_ => {}
+		}
+		let right = self.expect_token()?;
+		let left = self.operand(&left)?;
+		let right = self.operand(&right)?;
+		match op.text.as_str() {
+			"<" => Ok(Condition::Less(left, right, false)),
+			"<=" => Ok(Condition::Less(left, right, true)),
+			">" => Ok(Condition::Less(right, left, false)),
+			">=" => Ok(Condition::Less(right, left, true)),
+			_ => Err(self.error(&op, format!("unknown comparison `{}`", op.text))),
+		}
+	}
+
+	/// Emits instructions that skip the next instruction when the condition is `skip_when`.
+	fn skip(
+		&mut self,
+		condition: &Condition,
+		skip_when: bool,
+		token: &Token,
+	) -> Result<(), AsmError> {
+		let skip = |branch_type, inverted| {
+			Instruction::Branch(BranchInstruction::new(
+				branch_type,
+				BranchTarget::Skip,
+				inverted,
+			))
+		};
+		let instruction = match *condition {
+			Condition::Equal(x, Operand::Value(value), equal) => {
+				skip(BranchType::Equal { register: x, value }, equal != skip_when)
+			}
+			Condition::Equal(x, Operand::Register(y), equal) => skip(
+				BranchType::EqualRegister {
+					register_a: x,
+					register_b: y,
+				},
+				equal != skip_when,
+			),
+			Condition::Key(x, pressed) => {
+				skip(BranchType::KeyPressed { register: x }, pressed != skip_when)
+			}
+			Condition::Less(a, b, or_equal) => {
+				// vf is set when first >= second, which is the opposite of a < b,
+				// and the same as a <= b with the operands swapped.
+				let (first, second) = if or_equal { (b, a) } else { (a, b) };
+				let (from, register, inverted) = match (first, second) {
+					// vf := first, vf -= second
+					(first, Operand::Register(second)) => (first, second, false),
+					// vf := second, vf =- first
+					(Operand::Register(first), second) => (second, first, true),
+					_ => {
+						return Err(self.error(token, "one side of a comparison must be a register"))
+					}
+				};
+				let from = match from {
+					Operand::Register(register) => LoadTarget::Register(register),
+					Operand::Value(value) => LoadTarget::Value(value),
+				};
+				self.emit(load(from, LoadTarget::Register(FLAG_REGISTER)), token)?;
+				self.emit(
+					arthimetic(
+						ArthimeticOp::Sub,
+						ArthimeticValue::RegisterRegister(FLAG_REGISTER, register),
+						true,
+						inverted,
+					),
+					token,
+				)?;
+				// The comparison holds when vf is clear, or set when it's `<=`.
+				let flag = u8::from(or_equal);
+				skip(
+					BranchType::Equal {
+						register: FLAG_REGISTER,
+						value: flag,
+					},
+					!skip_when,
+				)
+			}
+		};
+		self.emit(instruction, token)
+	}
+
+	/// Expands a macro invocation into the token stream.
+	fn expand(&mut self, token: &Token, definition: Macro) -> Result<(), AsmError> {
+		self.expansions += 1;
+		if self.expansions > MAX_EXPANSIONS {
+			return Err(self.error(token, "too many macro expansions"));
+		}
+		let mut arguments = HashMap::new();
+		for name in &definition.arguments {
+			let argument = self.expect_token()?;
+			arguments.insert(name.clone(), argument.text);
+		}
+		for body_token in definition.body.into_iter().rev() {
+			let text = arguments
+				.get(&body_token.text)
+				.cloned()
+				.unwrap_or(body_token.text);
+			self.tokens.push_front(Token { text, ..body_token });
+		}
+		Ok(())
+	}
+
+	/// Parses `:macro name arguments { body }`.
+	fn define_macro(&mut self) -> Result<(), AsmError> {
+		let name = self.name()?;
+		let mut arguments = Vec::new();
+		loop {
+			let token = self.expect_token()?;
+			if token.text == "{" {
+				break;
+			}
+			arguments.push(token.text);
+		}
+		let mut body = Vec::new();
+		let mut depth = 1;
+		loop {
+			let token = self.expect_token()?;
+			match token.text.as_str() {
+				"{" => depth += 1,
+				"}" => {
+					depth -= 1;
+					if depth == 0 {
+						break;
+					}
+				}
+				_ => {}
+			}
+			body.push(token);
+		}
+		self.define(&name)?;
+		self.macros.insert(name.text, Macro { arguments, body });
+		Ok(())
+	}
+
+	/// Reserves the jump to `main` unless the program starts with it.
+	fn start(&mut self, is_main: bool) {
+		if self.started {
+			return;
+		}
+		self.started = true;
+		if !is_main {
+			let main = Token {
+				text: "main".to_string(),
+				..self.last.clone()
+			};
+			self.rom.extend([0x10, 0x00]);
+			self.position += 2;
+			self.fixups.push((PROGRAM_START, Fixup::Address, main));
+		}
+	}
+
+	/// Encodes an instruction at the current position.
+	fn emit(&mut self, instruction: Instruction, token: &Token) -> Result<(), AsmError> {
+		// The jump to main comes first, and has no line of its own.
+		self.start(false);
+		self.source_map.lines.insert(
+			self.position,
+			SourceLine {
+				file: self.file.clone(),
+				line: token.line,
+			},
+		);
+		let bytes = instruction.encode(self.mode).ok_or_else(|| {
+			let text =
+				Syntax::Octo.instruction(&instruction, self.mode, &self.mode.quirks(), |_| None);
+			self.error(
+				token,
+				format!("`{}` is not supported in {} mode", text, self.mode),
+			)
+		})?;
+		self.write(&bytes, token)
+	}
+
+	/// Writes bytes at the current position.
+	fn write(&mut self, bytes: &[u8], token: &Token) -> Result<(), AsmError> {
+		self.start(false);
+		let start = self.position - PROGRAM_START;
+		let end = start + bytes.len();
+		if PROGRAM_START + end > self.mode.memory_size() {
+			return Err(self.error(token, "the program doesn't fit in memory"));
+		}
+		if self.rom.len() < end {
+			self.rom.resize(end, 0);
+		}
+		self.rom[start..end].copy_from_slice(bytes);
+		self.position += bytes.len();
+		Ok(())
+	}
+
+	/// Fills in a label's address now if it's defined, or once the program is compiled.
+	fn reference(&mut self, position: usize, fixup: Fixup, label: &Token) -> Result<(), AsmError> {
+		if let Some(address) = self.labels.get(&label.text) {
+			return self.patch(position, fixup, *address, label);
+		}
+		if let Some(value) = self.constant(label) {
+			let address = self.check_range(label, value, 0, 0xFFFF)?;
+			return self.patch(position, fixup, address as usize, label);
+		}
+		self.fixups.push((position, fixup, label.clone()));
+		Ok(())
+	}
+
+	fn patch(
+		&mut self,
+		position: usize,
+		fixup: Fixup,
+		address: usize,
+		token: &Token,
+	) -> Result<(), AsmError> {
+		let index = position - PROGRAM_START;
+		match fixup {
+			Fixup::Address => {
+				if address > 0xFFF {
+					return Err(self.error(
+						token,
+						format!("0x{:X} is out of range of a 12 bit address", address),
+					));
+				}
+				self.rom[index] = (self.rom[index] & 0xF0) | (address >> 8) as u8;
+				self.rom[index + 1] = address as u8;
+			}
+			Fixup::Long => {
+				self.rom[index..index + 2].copy_from_slice(&(address as u16).to_be_bytes());
+			}
+			Fixup::Unpack(nibble) => {
+				let high = match nibble {
+					None => (address >> 8) as u8,
+					Some(_) if address > 0xFFF => {
+						return Err(self.error(
+							token,
+							format!("0x{:X} is out of range of a 12 bit address", address),
+						))
+					}
+					Some(nibble) => (nibble << 4) | (address >> 8) as u8,
+				};
+				self.rom[index + 1] = high;
+				self.rom[index + 3] = address as u8;
+			}
+		}
+		Ok(())
+	}
+
+	fn define_label(&mut self, name: &Token, address: usize) -> Result<(), AsmError> {
+		self.define(name)?;
+		self.labels.insert(name.text.clone(), address);
+		Ok(())
+	}
+
+	/// Checks that a name isn't already in use.
+	fn define(&self, name: &Token) -> Result<(), AsmError> {
+		if self.labels.contains_key(&name.text)
+			|| self.constants.contains_key(&name.text)
+			|| self.aliases.contains_key(&name.text)
+			|| self.macros.contains_key(&name.text)
+		{
+			return Err(self.error(name, format!("`{}` is already defined", name.text)));
+		}
+		Ok(())
+	}
+
+	/// The next token, which must be a name that can be defined.
+	fn name(&mut self) -> Result<Token, AsmError> {
+		let name = self.expect_token()?;
+		if !is_name(&name.text) || self.register(&name).is_some() {
+			return Err(self.error(&name, format!("`{}` can't be used as a name", name.text)));
+		}
+		Ok(name)
+	}
+
+	/// The register named by a token, such as `v3` or an alias.
+	fn register(&self, token: &Token) -> Option<Register> {
+		if let Some(register) = self.aliases.get(&token.text) {
+			return Some(*register);
+		}
+		let digit = token.text.strip_prefix(['v', 'V'])?;
+		(digit.len() == 1)
+			.then(|| u8::from_str_radix(digit, 16).ok())
+			.flatten()
+	}
+
+	fn register_or_error(&self, token: &Token) -> Result<Register, AsmError> {
+		self.register(token).ok_or_else(|| {
+			self.error(
+				token,
+				format!("expected a register, found `{}`", token.text),
+			)
+		})
+	}
+
+	fn expect_register(&mut self) -> Result<Register, AsmError> {
+		let token = self.expect_token()?;
+		self.register_or_error(&token)
+	}
+
+	/// A register or a byte value.
+	fn operand(&self, token: &Token) -> Result<Operand, AsmError> {
+		match self.register(token) {
+			Some(register) => Ok(Operand::Register(register)),
+			None => Ok(Operand::Value(self.ranged(token, -0x80, 0xFF)? as u8)),
+		}
+	}
+
+	fn nibble(&mut self) -> Result<u8, AsmError> {
+		let token = self.expect_token()?;
+		Ok(self.ranged(&token, 0, 0xF)? as u8)
+	}
+
+	/// The value of a number, constant or defined label.
+	fn value(&self, token: &Token) -> Result<i64, AsmError> {
+		self.constant(token)
+			.or_else(|| self.labels.get(&token.text).map(|address| *address as i64))
+			.ok_or_else(|| self.error(token, format!("expected a value, found `{}`", token.text)))
+	}
+
+	/// The value of a number or constant.
+	fn constant(&self, token: &Token) -> Option<i64> {
+		parse_number(&token.text).or_else(|| self.constants.get(&token.text).copied())
+	}
+
+	fn ranged(&self, token: &Token, min: i64, max: i64) -> Result<i64, AsmError> {
+		let value = self.value(token)?;
+		self.check_range(token, value, min, max)
+	}
+
+	fn check_range(&self, token: &Token, value: i64, min: i64, max: i64) -> Result<i64, AsmError> {
+		if value < min || value > max {
+			return Err(self.error(
+				token,
+				format!("{} is out of range ({} to 0x{:X})", value, min, max),
+			));
+		}
+		Ok(value)
+	}
+}
+
+fn load(from: LoadTarget, into: LoadTarget) -> Instruction {
+	Instruction::Load(LoadInstruction { from, into })
+}
+
+fn arthimetic(
+	op: ArthimeticOp,
+	values: ArthimeticValue,
+	carry_flag: bool,
+	inverted: bool,
+) -> Instruction {
+	Instruction::Arthimetic(ArthimeticInstruction {
+		op,
+		values,
+		carry_flag,
+		inverted,
+	})
+}
+
+fn logical(op: LogicalOp, register_a: Register, register_b: Register) -> Instruction {
+	Instruction::Logical(LogicalInstruction {
+		op,
+		register_a,
+		register_b,
+	})
+}
+
+/// Splits source into whitespace-separated tokens, dropping `#` comments.
+fn tokenize(source: &str) -> VecDeque<Token> {
+	let mut tokens = VecDeque::new();
+	for (index, line) in source.lines().enumerate() {
+		let line = line.split('#').next().unwrap_or_default();
+		let mut start = None;
+		for (column, c) in line.char_indices().chain([(line.len(), ' ')]) {
+			match (c.is_whitespace(), start) {
+				(false, None) => start = Some(column),
+				(true, Some(first)) => {
+					tokens.push_back(Token {
+						text: line[first..column].to_string(),
+						line: index + 1,
+						column: first + 1,
+					});
+					start = None;
+				}
+				_ => {}
+			}
+		}
+	}
+	tokens
+}
+
+/// Parses a decimal, hexadecimal (`0x`) or binary (`0b`) number, which may be negative.
+fn parse_number(text: &str) -> Option<i64> {
+	let (negative, digits) = match text.strip_prefix('-') {
+		Some(digits) => (true, digits),
+		None => (false, text),
+	};
+	let value = if let Some(hex) = digits
+		.strip_prefix("0x")
+		.or_else(|| digits.strip_prefix("0X"))
+	{
+		i64::from_str_radix(hex, 16).ok()?
+	} else if let Some(binary) = digits
+		.strip_prefix("0b")
+		.or_else(|| digits.strip_prefix("0B"))
+	{
+		i64::from_str_radix(binary, 2).ok()?
+	} else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+		digits.parse().ok()?
+	} else {
+		return None;
+	};
+	Some(if negative { -value } else { value })
+}
+
+fn is_name(text: &str) -> bool {
+	text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+		&& text
+			.chars()
+			.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn compile_chip8(source: &str) -> Vec<u8> {
+		compile(source, VmMode::Chip8).unwrap()
+	}
+
+	/// Compiles source that should fail, returning where the error is and what it says.
+	fn error(source: &str) -> (usize, usize, String) {
+		let error = compile(source, VmMode::Chip8).unwrap_err();
+		(error.line, error.column, error.message)
+	}
+
+	#[test]
+	fn instructions_and_data() {
+		let source = "
+			: main
+				v0 := 5
+				v0 += 1
+				i := data
+				sprite v0 v1 3
+			: data 0xFF 0x81 0b11111111
+		";
+		assert_eq!(
+			compile_chip8(source),
+			[0x60, 0x05, 0x70, 0x01, 0xA2, 0x08, 0xD0, 0x13, 0xFF, 0x81, 0xFF]
+		);
+	}
+
+	#[test]
+	fn jumps_to_main() {
+		assert_eq!(
+			compile_chip8(": sub return : main sub"),
+			[0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+		);
+	}
+
+	#[test]
+	fn if_then() {
+		assert_eq!(
+			compile_chip8(": main if v0 == 1 then v1 := 2"),
+			[0x40, 0x01, 0x61, 0x02]
+		);
+		assert_eq!(
+			compile_chip8(": main if v0 key then v1 := 2"),
+			[0xE0, 0xA1, 0x61, 0x02]
+		);
+	}
+
+	#[test]
+	fn if_begin_else_end() {
+		let source = ": main if v0 != 2 begin v1 := 1 else v1 := 2 end";
+		assert_eq!(
+			compile_chip8(source),
+			[0x40, 0x02, 0x12, 0x08, 0x61, 0x01, 0x12, 0x0A, 0x61, 0x02]
+		);
+	}
+
+	#[test]
+	fn loop_while_again() {
+		let source = ": main loop v0 += 1 while v0 != 5 again";
+		assert_eq!(
+			compile_chip8(source),
+			[0x70, 0x01, 0x40, 0x05, 0x12, 0x08, 0x12, 0x00]
+		);
+	}
+
+	#[test]
+	fn comparisons() {
+		// vf := v0, vf -= v1, and v2 := 1 is skipped unless vf is clear.
+		assert_eq!(
+			compile_chip8(": main if v0 < v1 then v2 := 1"),
+			[0x8F, 0x00, 0x8F, 0x15, 0x4F, 0x00, 0x62, 0x01]
+		);
+		// v0 > 3 is 3 < v0, so vf := 3, vf -= v0
+		assert_eq!(
+			compile_chip8(": main if v0 > 3 then v2 := 1"),
+			[0x6F, 0x03, 0x8F, 0x05, 0x4F, 0x00, 0x62, 0x01]
+		);
+	}
+
+	#[test]
+	fn macros() {
+		let source = "
+			:macro twice register { register += 1 register += 1 }
+			:alias counter v3
+			: main twice counter
+		";
+		assert_eq!(compile_chip8(source), [0x73, 0x01, 0x73, 0x01]);
+	}
+
+	#[test]
+	fn unpack() {
+		assert_eq!(
+			compile_chip8(": main :unpack 0xA data : data 0x12"),
+			[0x60, 0xA2, 0x61, 0x04, 0x12]
+		);
+		assert_eq!(
+			compile(": main :unpack long data : data 0x12", VmMode::XoChip).unwrap(),
+			[0x60, 0x02, 0x61, 0x04, 0x12]
+		);
+	}
+
+	#[test]
+	fn org_and_next() {
+		let rom = compile_chip8(": main jump target :org 0x300 : target v0 := 1");
+		assert_eq!(rom.len(), 0x102);
+		assert_eq!(rom[..2], [0x13, 0x00]);
+		assert_eq!(rom[0x100..], [0x60, 0x01]);
+		// :next labels the byte after the opcode, which is the value of `v0 := 3`.
+		assert_eq!(
+			compile_chip8(": main :next value v0 := 3 i := value"),
+			[0x60, 0x03, 0xA2, 0x01]
+		);
+	}
+
+	#[test]
+	fn constants() {
+		assert_eq!(
+			compile_chip8(":const SPEED 4 : main v0 := SPEED SPEED"),
+			[0x60, 0x04, 0x04]
+		);
+	}
+
+	#[test]
+	fn errors() {
+		let error = |source, line, column, message: &str| {
+			assert_eq!(
+				self::error(source),
+				(line, column, message.to_string()),
+				"{}",
+				source
+			)
+		};
+		error(": main end", 1, 8, "`end` without `if ... begin`");
+		error(
+			": main hires",
+			1,
+			8,
+			"`hires` is not supported in Chip8 mode",
+		);
+		error(": main else", 1, 8, "`else` without `if ... begin`");
+		error(": main\n  loop", 2, 3, "`loop` without `again`");
+		error(": main again", 1, 8, "`again` without `loop`");
+		error(": main while v0 == 1", 1, 8, "`while` outside of a loop");
+		error(": main jump nowhere", 1, 13, "undefined label `nowhere`");
+		error(": main :foo", 1, 8, "unknown directive `:foo`");
+		error(
+			": main sprite 1 2 3",
+			1,
+			15,
+			"expected a register, found `1`",
+		);
+		error(": main v0 :=", 1, 11, "unexpected end of program");
+		error(
+			": main v0 := 256",
+			1,
+			14,
+			"256 is out of range (-128 to 0xFF)",
+		);
+		error(": main : main", 1, 10, "`main` is already defined");
+		error(
+			": main if 1 < 2 then",
+			1,
+			8,
+			"one side of a comparison must be a register",
+		);
+		error(": main if v0 ~ 2 then", 1, 14, "unknown comparison `~`");
+		error(
+			":macro forever { forever } : main forever",
+			1,
+			18,
+			"too many macro expansions",
+		);
+	}
+}
@@ -0,0 +1,84 @@
+/// How Fx55 and Fx65 change I after accessing memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Display)]
+pub enum IndexIncrement {
+	/// I is incremented by X + 1, as on the original CHIP-8 interpreter.
+	#[display(fmt = "X + 1")]
+	XPlusOne,
+	/// I is incremented by X, as on CHIP-48.
+	#[display(fmt = "X")]
+	X,
+	/// I is left unchanged, as on SUPER-CHIP.
+	#[display(fmt = "Unchanged")]
+	Unchanged,
+}
+
+/// Behaviors that differ between CHIP-8 interpreters.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Quirks {
+	/// Whether 8xy6 and 8xyE shift Vy into Vx, instead of shifting Vx in place.
+	pub shift_vy: bool,
+	/// How Fx55 and Fx65 change I.
+	pub index_increment: IndexIncrement,
+	/// Whether Bxnn jumps to xnn + Vx, instead of Bnnn jumping to nnn + V0.
+	pub jump_vx: bool,
+	/// Whether sprites are clipped at the edges of the screen, instead of wrapping around.
+	pub clip_sprites: bool,
+	/// Whether 8xy1, 8xy2 and 8xy3 reset VF to 0.
+	pub logic_reset_vf: bool,
+	/// Whether Dxyn waits for the next frame, limiting drawing to once per frame.
+	pub display_wait: bool,
+	/// Whether Dxy0 draws a 16x16 sprite in low resolution mode, instead of an 8x16 sprite.
+	pub lores_16x16: bool,
+}
+
+impl Quirks {
+	/// The behavior of the original CHIP-8 interpreter on the COSMAC VIP.
+	pub const CHIP8: Quirks = Quirks {
+		shift_vy: true,
+		index_increment: IndexIncrement::XPlusOne,
+		jump_vx: false,
+		clip_sprites: true,
+		logic_reset_vf: true,
+		display_wait: true,
+		lores_16x16: false,
+	};
+
+	/// The behavior of CHIP-48 on the HP-48.
+	pub const CHIP48: Quirks = Quirks {
+		shift_vy: false,
+		index_increment: IndexIncrement::X,
+		jump_vx: true,
+		clip_sprites: true,
+		logic_reset_vf: false,
+		display_wait: false,
+		lores_16x16: false,
+	};
+
+	/// The behavior of SUPER-CHIP 1.1 on the HP-48.
+	pub const SUPER_CHIP: Quirks = Quirks {
+		shift_vy: false,
+		index_increment: IndexIncrement::Unchanged,
+		jump_vx: true,
+		clip_sprites: true,
+		logic_reset_vf: false,
+		display_wait: false,
+		lores_16x16: false,
+	};
+
+	/// The behavior of XO-CHIP, as implemented by Octo.
+	pub const XO_CHIP: Quirks = Quirks {
+		shift_vy: true,
+		index_increment: IndexIncrement::XPlusOne,
+		jump_vx: false,
+		clip_sprites: false,
+		logic_reset_vf: false,
+		display_wait: false,
+		lores_16x16: true,
+	};
+}
+
+impl Default for Quirks {
+	fn default() -> Self {
+		Quirks::CHIP8
+	}
+}
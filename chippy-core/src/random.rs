@@ -0,0 +1,92 @@
+use nanorand::Rng;
+
+/// The algorithm used to generate random numbers for Cxkk.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Display)]
+pub enum RandomMode {
+	/// The wyrand generator.
+	#[display(fmt = "WyRand")]
+	WyRand,
+	/// The generator used by the COSMAC VIP interpreter.
+	///
+	/// The interpreter keeps a 16-bit counter. Every random number increments it,
+	/// then adds the byte at the counter's low byte in a page of memory to its high byte,
+	/// which is the result. The VIP reads from the interpreter's own code, which isn't
+	/// in memory here, so the page at 0x200 is used instead.
+	#[display(fmt = "VIP")]
+	Vip,
+}
+
+/// A seedable source of random numbers, owned by the virtual machine so that
+/// runs can be reproduced.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Random {
+	/// The algorithm used to generate random numbers.
+	pub mode: RandomMode,
+	/// The seed the generator was last seeded with.
+	seed: u64,
+	/// The current state of the generator.
+	state: u64,
+}
+
+impl Random {
+	/// Creates a new wyrand generator from a seed.
+	pub fn new(seed: u64) -> Self {
+		Random {
+			mode: RandomMode::WyRand,
+			seed,
+			state: seed,
+		}
+	}
+
+	/// Creates a new wyrand generator seeded from system entropy.
+	pub fn from_entropy() -> Self {
+		Self::new(nanorand::tls_rng().generate())
+	}
+
+	/// The seed the generator was last seeded with.
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
+	/// Restarts the generator from a seed.
+	pub fn reseed(&mut self, seed: u64) {
+		self.seed = seed;
+		self.state = seed;
+	}
+
+	/// The current state of the generator.
+	pub fn state(&self) -> u64 {
+		self.state
+	}
+
+	/// Restores the generator to a seed and a state returned by [`Random::state`].
+	pub fn set_state(&mut self, seed: u64, state: u64) {
+		self.seed = seed;
+		self.state = state;
+	}
+
+	/// Generates a random byte.
+	/// The VIP generator reads from the given memory.
+	pub fn generate(&mut self, memory: &[u8]) -> u8 {
+		match self.mode {
+			RandomMode::WyRand => {
+				self.state = self.state.wrapping_add(0xA076_1D64_78BD_642F);
+				let t =
+					(self.state as u128).wrapping_mul((self.state ^ 0xE703_7ED1_A0B4_28DB) as u128);
+				((t >> 64) ^ t) as u8
+			}
+			RandomMode::Vip => {
+				let [high, low] = (self.state as u16).wrapping_add(1).to_be_bytes();
+				let high = high.wrapping_add(memory[0x200 | low as usize]);
+				self.state = u16::from_be_bytes([high, low]) as u64;
+				high
+			}
+		}
+	}
+}
+
+impl Default for Random {
+	fn default() -> Self {
+		Self::from_entropy()
+	}
+}
@@ -0,0 +1,598 @@
+//! The RCA CDP1802 that COSMAC VIP machine code subroutines run on.
+//!
+//! Only 0nnn subroutines run as 1802 code. The CHIP-8 interpreter itself is
+//! still emulated directly rather than by running the original VIP interpreter
+//! on the 1802, since that would need the VIP's ROM and interpreter, which
+//! chippy doesn't ship, and the display interrupt and DMA timing of the 1861.
+
+use crate::{
+	display::LOW_RESOLUTION,
+	error::VmError,
+	instruction::Address,
+	vm::{Vm, VmMode},
+};
+
+/// The start of the memory the COSMAC VIP interpreter reserves at the top of 4 KiB,
+/// for its stack, work area, registers and display.
+pub const VIP_RESERVED: usize = 0xEA0;
+/// The top of the stack that R2 points to in COSMAC VIP machine code.
+const VIP_STACK: u16 = 0x0ECF;
+/// The address of V0 to VF in COSMAC VIP memory.
+const VIP_REGISTERS: u16 = 0x0EF0;
+/// The address of the 64x32 display buffer in COSMAC VIP memory.
+const VIP_DISPLAY: u16 = 0x0F00;
+/// The output port that selects which key EF3 reports on the COSMAC VIP.
+const VIP_KEY_PORT: usize = 2;
+/// How many 1802 instructions a machine code subroutine may run before it's
+/// considered stuck.
+const MAX_SUBROUTINE_STEPS: usize = 1_000_000;
+
+/// The RCA CDP1802 microprocessor, as used in the COSMAC VIP.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rca1802 {
+	/// The 16 scratchpad registers, R0 to RF.
+	pub r: [u16; 16],
+	/// The register designator that selects the program counter.
+	pub p: u8,
+	/// The register designator that selects the data pointer.
+	pub x: u8,
+	/// The data register, or accumulator.
+	pub d: u8,
+	/// The data flag, set on carries and shifts.
+	pub df: bool,
+	/// The temporary register, holding X and P after an interrupt or MARK.
+	pub t: u8,
+	/// Whether interrupts are enabled.
+	pub ie: bool,
+	/// The Q output flip-flop.
+	pub q: bool,
+	/// The external flag inputs EF1 to EF4.
+	pub ef: [bool; 4],
+	/// The last byte written to each output port by OUT 1 to OUT 7.
+	pub output: [u8; 8],
+	/// The byte read from each input port by INP 1 to INP 7.
+	pub input: [u8; 8],
+}
+
+impl Rca1802 {
+	/// Creates an 1802 in its reset state.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// The current program counter, R(P).
+	pub fn pc(&self) -> u16 {
+		self.r[self.p as usize]
+	}
+
+	fn read(memory: &[u8], address: u16) -> u8 {
+		memory[address as usize % memory.len()]
+	}
+
+	fn write(memory: &mut [u8], address: u16, value: u8) {
+		let len = memory.len();
+		memory[address as usize % len] = value;
+	}
+
+	/// Reads the byte at R(P) and increments R(P).
+	fn immediate(&mut self, memory: &[u8]) -> u8 {
+		let p = self.p as usize;
+		let value = Self::read(memory, self.r[p]);
+		self.r[p] = self.r[p].wrapping_add(1);
+		value
+	}
+
+	/// Adds with carry, setting DF to the carry.
+	fn add(&mut self, a: u8, b: u8, carry: bool) {
+		let sum = a as u16 + b as u16 + carry as u16;
+		self.d = sum as u8;
+		self.df = sum > 0xFF;
+	}
+
+	/// Handles an interrupt, if interrupts are enabled.
+	/// X and P are saved in T, and the interrupt routine runs with R1 as its
+	/// program counter and R2 as its stack pointer.
+	/// Returns whether the interrupt was taken.
+	pub fn interrupt(&mut self) -> bool {
+		if !self.ie {
+			return false;
+		}
+		self.t = (self.x << 4) | self.p;
+		self.p = 1;
+		self.x = 2;
+		self.ie = false;
+		true
+	}
+
+	/// Performs a DMA output cycle, reading the byte at R0 and incrementing R0,
+	/// as the 1861 does to fetch each byte of the display.
+	pub fn dma_out(&mut self, memory: &[u8]) -> u8 {
+		let value = Self::read(memory, self.r[0]);
+		self.r[0] = self.r[0].wrapping_add(1);
+		value
+	}
+
+	/// Executes a single instruction from memory.
+	/// Returns the number of machine cycles it took, or `None` if the opcode is undefined.
+	pub fn step(&mut self, memory: &mut [u8]) -> Option<u32> {
+		let opcode = self.immediate(memory);
+		let n = (opcode & 0xF) as usize;
+		let x = self.x as usize;
+		let p = self.p as usize;
+		match opcode >> 4 {
+			// IDL - wait for an interrupt or DMA, which happens every frame on the VIP.
+			0x0 if n == 0 => {}
+			// LDN
+			0x0 => self.d = Self::read(memory, self.r[n]),
+			// INC
+			0x1 => self.r[n] = self.r[n].wrapping_add(1),
+			// DEC
+			0x2 => self.r[n] = self.r[n].wrapping_sub(1),
+			// Short branches, with the upper half of the opcodes inverting the condition.
+			0x3 => {
+				let condition = match n & 0x7 {
+					0 => true,
+					1 => self.q,
+					2 => self.d == 0,
+					3 => self.df,
+					flag => self.ef[flag - 4],
+				};
+				if condition != (n & 0x8 != 0) {
+					let low = Self::read(memory, self.r[p]);
+					self.r[p] = (self.r[p] & 0xFF00) | low as u16;
+				} else {
+					self.r[p] = self.r[p].wrapping_add(1);
+				}
+			}
+			// LDA
+			0x4 => {
+				self.d = Self::read(memory, self.r[n]);
+				self.r[n] = self.r[n].wrapping_add(1);
+			}
+			// STR
+			0x5 => Self::write(memory, self.r[n], self.d),
+			0x6 => match n {
+				// IRX
+				0x0 => self.r[x] = self.r[x].wrapping_add(1),
+				// OUT 1-7
+				0x1..=0x7 => {
+					self.output[n] = Self::read(memory, self.r[x]);
+					self.r[x] = self.r[x].wrapping_add(1);
+				}
+				// 68 is only defined on the 1804 and later.
+				0x8 => return None,
+				// INP 1-7
+				_ => {
+					self.d = self.input[n - 8];
+					Self::write(memory, self.r[x], self.d);
+				}
+			},
+			0x7 => match n {
+				// RET and DIS
+				0x0 | 0x1 => {
+					let value = Self::read(memory, self.r[x]);
+					self.r[x] = self.r[x].wrapping_add(1);
+					self.x = value >> 4;
+					self.p = value & 0xF;
+					self.ie = n == 0;
+				}
+				// LDXA
+				0x2 => {
+					self.d = Self::read(memory, self.r[x]);
+					self.r[x] = self.r[x].wrapping_add(1);
+				}
+				// STXD
+				0x3 => {
+					Self::write(memory, self.r[x], self.d);
+					self.r[x] = self.r[x].wrapping_sub(1);
+				}
+				// ADC
+				0x4 => self.add(Self::read(memory, self.r[x]), self.d, self.df),
+				// SDB
+				0x5 => self.add(Self::read(memory, self.r[x]), !self.d, self.df),
+				// SHRC
+				0x6 => {
+					let carry = self.d & 1 != 0;
+					self.d = (self.d >> 1) | ((self.df as u8) << 7);
+					self.df = carry;
+				}
+				// SMB
+				0x7 => self.add(self.d, !Self::read(memory, self.r[x]), self.df),
+				// SAV
+				0x8 => Self::write(memory, self.r[x], self.t),
+				// MARK
+				0x9 => {
+					self.t = (self.x << 4) | self.p;
+					Self::write(memory, self.r[2], self.t);
+					self.x = self.p;
+					self.r[2] = self.r[2].wrapping_sub(1);
+				}
+				// REQ and SEQ
+				0xA | 0xB => self.q = n == 0xB,
+				// ADCI
+				0xC => {
+					let value = self.immediate(memory);
+					self.add(value, self.d, self.df);
+				}
+				// SDBI
+				0xD => {
+					let value = self.immediate(memory);
+					self.add(value, !self.d, self.df);
+				}
+				// SHLC
+				0xE => {
+					let carry = self.d & 0x80 != 0;
+					self.d = (self.d << 1) | self.df as u8;
+					self.df = carry;
+				}
+				// SMBI
+				_ => {
+					let value = self.immediate(memory);
+					self.add(self.d, !value, self.df);
+				}
+			},
+			// GLO
+			0x8 => self.d = self.r[n] as u8,
+			// GHI
+			0x9 => self.d = (self.r[n] >> 8) as u8,
+			// PLO
+			0xA => self.r[n] = (self.r[n] & 0xFF00) | self.d as u16,
+			// PHI
+			0xB => self.r[n] = (self.r[n] & 0x00FF) | (self.d as u16) << 8,
+			// Long branches and skips, which take an extra machine cycle.
+			0xC => {
+				let base = match n & 0x3 {
+					0 => true,
+					1 => self.q,
+					2 => self.d == 0,
+					_ => self.df,
+				};
+				match n {
+					// LBR, LBQ, LBZ, LBDF, LSKP, LBNQ, LBNZ and LBNF
+					0x0..=0x3 | 0x8..=0xB => {
+						if base != (n & 0x8 != 0) {
+							let high = Self::read(memory, self.r[p]);
+							let low = Self::read(memory, self.r[p].wrapping_add(1));
+							self.r[p] = u16::from_be_bytes([high, low]);
+						} else {
+							self.r[p] = self.r[p].wrapping_add(2);
+						}
+					}
+					// NOP
+					0x4 => {}
+					// LSIE
+					0xC => {
+						if self.ie {
+							self.r[p] = self.r[p].wrapping_add(2);
+						}
+					}
+					// LSNQ, LSNZ, LSNF, LSQ, LSZ and LSDF
+					_ => {
+						if base == (n & 0x8 != 0) {
+							self.r[p] = self.r[p].wrapping_add(2);
+						}
+					}
+				}
+				return Some(3);
+			}
+			// SEP
+			0xD => self.p = n as u8,
+			// SEX
+			0xE => self.x = n as u8,
+			_ => {
+				// The immediate forms (F8-FF) read from R(P) instead of R(X).
+				let value = match n {
+					0x0..=0x5 | 0x7 => Self::read(memory, self.r[x]),
+					0x8..=0xD | 0xF => self.immediate(memory),
+					_ => 0,
+				};
+				match n & 0x7 {
+					// LDX and LDI
+					0x0 => self.d = value,
+					// OR and ORI
+					0x1 => self.d |= value,
+					// AND and ANI
+					0x2 => self.d &= value,
+					// XOR and XRI
+					0x3 => self.d ^= value,
+					// ADD and ADI
+					0x4 => self.add(value, self.d, false),
+					// SD and SDI
+					0x5 => self.add(value, !self.d, true),
+					// SHR and SHL
+					0x6 if n == 0x6 => {
+						self.df = self.d & 1 != 0;
+						self.d >>= 1;
+					}
+					0x6 => {
+						self.df = self.d & 0x80 != 0;
+						self.d <<= 1;
+					}
+					// SM and SMI
+					_ => self.add(self.d, !value, true),
+				}
+			}
+		}
+		Some(2)
+	}
+}
+
+impl Default for Rca1802 {
+	fn default() -> Self {
+		Rca1802 {
+			r: [0; 16],
+			p: 0,
+			x: 0,
+			d: 0,
+			df: false,
+			t: 0,
+			ie: true,
+			q: false,
+			ef: [false; 4],
+			output: [0; 8],
+			input: [0; 8],
+		}
+	}
+}
+
+/// Calls a 0nnn machine code subroutine, as the COSMAC VIP interpreter does.
+///
+/// The subroutine runs with R3 as its program counter and R2 as its stack
+/// pointer, and returns with SEP R4. The interpreter's registers, I and display
+/// are copied to where they are in VIP memory for the subroutine, and back afterwards.
+/// Returns the number of machine cycles the subroutine took.
+pub fn call_subroutine(vm: &mut Vm, address: Address) -> Result<u64, VmError> {
+	debug_assert_eq!(vm.mode, VmMode::CosmacVip);
+	let opcode = vm.opcode();
+	let registers = VIP_REGISTERS as usize;
+	vm.memory[registers..registers + 16].copy_from_slice(&vm.registers);
+	store_display(vm);
+	let cpu = &mut vm.cpu;
+	cpu.r[2] = VIP_STACK;
+	cpu.r[3] = address;
+	cpu.r[5] = vm.program_counter.wrapping_add(2);
+	cpu.r[6] = VIP_REGISTERS | (opcode >> 8) & 0xF;
+	cpu.r[7] = VIP_REGISTERS | (opcode >> 4) & 0xF;
+	cpu.r[8] = u16::from_be_bytes([vm.delay_timer, vm.sound_timer]);
+	cpu.r[0xA] = vm.index_register;
+	cpu.r[0xB] = VIP_DISPLAY;
+	cpu.p = 3;
+	cpu.x = 2;
+	let mut cycles = 0;
+	for _ in 0..MAX_SUBROUTINE_STEPS {
+		// EF3 reports whether the key last selected with OUT 2 is held down.
+		let key = (vm.cpu.output[VIP_KEY_PORT] & 0xF) as usize;
+		vm.cpu.ef[2] = vm.keypad.get(key).is_some_and(|key| *key);
+		let machine_address = vm.cpu.pc();
+		cycles += vm
+			.cpu
+			.step(&mut vm.memory)
+			.ok_or(VmError::InvalidMachineCode {
+				pc: vm.program_counter,
+				opcode,
+				address: machine_address,
+			})? as u64;
+		if vm.cpu.p == 4 {
+			vm.registers
+				.copy_from_slice(&vm.memory[registers..registers + 16]);
+			let [delay_timer, sound_timer] = vm.cpu.r[8].to_be_bytes();
+			vm.delay_timer = delay_timer;
+			vm.sound_timer = sound_timer;
+			vm.index_register = vm.cpu.r[0xA];
+			load_display(vm);
+			// The whole of memory may have been written to.
+			vm.instruction_cache.clear();
+			return Ok(cycles);
+		}
+	}
+	Err(VmError::MachineCodeTimeout {
+		pc: vm.program_counter,
+		opcode,
+	})
+}
+
+/// Copies the display into the VIP display buffer.
+fn store_display(vm: &mut Vm) {
+	let (width, height) = LOW_RESOLUTION;
+	for y in 0..height {
+		for byte in 0..width / 8 {
+			let value = (0..8).fold(0u8, |value, bit| {
+				value | (vm.display.get(0, byte * 8 + bit, y) as u8) << (7 - bit)
+			});
+			vm.memory[VIP_DISPLAY as usize + y * width / 8 + byte] = value;
+		}
+	}
+}
+
+/// Copies the VIP display buffer back into the display.
+fn load_display(vm: &mut Vm) {
+	let (width, height) = LOW_RESOLUTION;
+	for y in 0..height {
+		for x in 0..width {
+			let value = vm.memory[VIP_DISPLAY as usize + y * width / 8 + x / 8];
+			vm.display.set(0, x, y, (value >> (7 - x % 8)) & 1 != 0);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Runs the program at 0 until it reaches an IDL, returning the CPU and memory.
+	fn run(program: &[u8]) -> (Rca1802, Vec<u8>) {
+		let mut memory = vec![0; 0x100];
+		memory[..program.len()].copy_from_slice(program);
+		let mut cpu = Rca1802::new();
+		while memory[cpu.pc() as usize] != 0x00 {
+			cpu.step(&mut memory).unwrap();
+		}
+		(cpu, memory)
+	}
+
+	#[test]
+	fn loads_and_stores() {
+		// LDI 40, PLO R5, LDI 00, PHI R5, SEX R5, LDI AB, STXD, LDI 00, INC R5, LDX
+		let (cpu, memory) = run(&[
+			0xF8, 0x40, 0xA5, 0xF8, 0x00, 0xB5, 0xE5, 0xF8, 0xAB, 0x73, 0xF8, 0x00, 0x15, 0xF0,
+		]);
+		assert_eq!(memory[0x40], 0xAB);
+		assert_eq!(cpu.r[5], 0x40);
+		assert_eq!(cpu.x, 5);
+		assert_eq!(cpu.d, 0xAB);
+	}
+
+	#[test]
+	fn register_operations() {
+		// LDI 34, PLO R7, LDI 12, PHI R7, INC R7, DEC R8, GHI R7
+		let (cpu, _) = run(&[0xF8, 0x34, 0xA7, 0xF8, 0x12, 0xB7, 0x17, 0x28, 0x97]);
+		assert_eq!(cpu.r[7], 0x1235);
+		assert_eq!(cpu.r[8], 0xFFFF);
+		assert_eq!(cpu.d, 0x12);
+	}
+
+	#[test]
+	fn logic() {
+		// LDI F0, ORI 0F, ANI 3C, XRI FF
+		let (cpu, _) = run(&[0xF8, 0xF0, 0xF9, 0x0F, 0xFA, 0x3C, 0xFB, 0xFF]);
+		assert_eq!(cpu.d, 0xC3);
+	}
+
+	#[test]
+	fn addition_sets_df_on_carry() {
+		// LDI F0, ADI 20
+		let (cpu, _) = run(&[0xF8, 0xF0, 0xFC, 0x20]);
+		assert_eq!((cpu.d, cpu.df), (0x10, true));
+		// LDI 10, ADI 20, ADCI 01 adds without a carry in.
+		let (cpu, _) = run(&[0xF8, 0x10, 0xFC, 0x20, 0x7C, 0x01]);
+		assert_eq!((cpu.d, cpu.df), (0x31, false));
+		// LDI FF, ADI 01, ADCI 00 carries into the next byte.
+		let (cpu, _) = run(&[0xF8, 0xFF, 0xFC, 0x01, 0x7C, 0x00]);
+		assert_eq!((cpu.d, cpu.df), (0x01, false));
+	}
+
+	#[test]
+	fn subtraction_clears_df_on_borrow() {
+		// LDI 10, SMI 20
+		let (cpu, _) = run(&[0xF8, 0x10, 0xFF, 0x20]);
+		assert_eq!((cpu.d, cpu.df), (0xF0, false));
+		// LDI 30, SMI 20
+		let (cpu, _) = run(&[0xF8, 0x30, 0xFF, 0x20]);
+		assert_eq!((cpu.d, cpu.df), (0x10, true));
+		// LDI 10, SDI 30 subtracts D from the immediate byte.
+		let (cpu, _) = run(&[0xF8, 0x10, 0xFD, 0x30]);
+		assert_eq!((cpu.d, cpu.df), (0x20, true));
+		// LDI 10, SMI 20, SMBI 00 borrows from the next byte.
+		let (cpu, _) = run(&[0xF8, 0x10, 0xFF, 0x20, 0x7F, 0x00]);
+		assert_eq!((cpu.d, cpu.df), (0xEF, true));
+	}
+
+	#[test]
+	fn shifts_through_df() {
+		// LDI 81, SHR
+		let (cpu, _) = run(&[0xF8, 0x81, 0xF6]);
+		assert_eq!((cpu.d, cpu.df), (0x40, true));
+		// LDI 81, SHL
+		let (cpu, _) = run(&[0xF8, 0x81, 0xFE]);
+		assert_eq!((cpu.d, cpu.df), (0x02, true));
+		// LDI 81, SHR, SHRC rotates the carry back in at the top.
+		let (cpu, _) = run(&[0xF8, 0x81, 0xF6, 0x76]);
+		assert_eq!((cpu.d, cpu.df), (0xA0, false));
+		// LDI 81, SHL, SHLC rotates the carry back in at the bottom.
+		let (cpu, _) = run(&[0xF8, 0x81, 0xFE, 0x7E]);
+		assert_eq!((cpu.d, cpu.df), (0x05, false));
+	}
+
+	#[test]
+	fn short_branches() {
+		// LDI 00, BZ 06, LDI 11, LDI 22
+		let (cpu, _) = run(&[0xF8, 0x00, 0x32, 0x06, 0xF8, 0x11, 0xF8, 0x22]);
+		assert_eq!(cpu.d, 0x22);
+		// LDI 01, BZ 07, LDI 11, IDL
+		let (cpu, _) = run(&[0xF8, 0x01, 0x32, 0x07, 0xF8, 0x11]);
+		assert_eq!(cpu.d, 0x11);
+		// SEQ, BNQ 07, LDI 11, IDL, IDL, LDI 22
+		let (cpu, _) = run(&[0x7B, 0x39, 0x07, 0xF8, 0x11, 0x00, 0x00, 0xF8, 0x22]);
+		assert_eq!((cpu.d, cpu.q), (0x11, true));
+	}
+
+	#[test]
+	fn long_branches_and_skips() {
+		let mut memory = vec![0; 0x100];
+		// LBR 0080
+		memory[..3].copy_from_slice(&[0xC0, 0x00, 0x80]);
+		// LDI 00, LSZ, LDI 11, IDL
+		memory[0x80..0x86].copy_from_slice(&[0xF8, 0x00, 0xCE, 0xF8, 0x11, 0x00]);
+		let mut cpu = Rca1802::new();
+		assert_eq!(cpu.step(&mut memory), Some(3));
+		assert_eq!(cpu.pc(), 0x80);
+		cpu.step(&mut memory);
+		assert_eq!(cpu.step(&mut memory), Some(3));
+		assert_eq!(cpu.pc(), 0x85);
+		assert_eq!(cpu.d, 0);
+	}
+
+	#[test]
+	fn mark_and_return() {
+		// LDI 80, PLO R2, SEX R3, MARK, SEX R2, IRX, RET
+		let (cpu, memory) = run(&[0xF8, 0x80, 0xA2, 0xE3, 0x79, 0xE2, 0x60, 0x70]);
+		assert_eq!(memory[0x80], 0x30);
+		assert_eq!(cpu.t, 0x30);
+		assert_eq!((cpu.x, cpu.p), (3, 0));
+		assert!(cpu.ie);
+	}
+
+	#[test]
+	fn undefined_opcode() {
+		let mut memory = vec![0x68, 0x00];
+		assert_eq!(Rca1802::new().step(&mut memory), None);
+	}
+
+	#[test]
+	fn interrupts() {
+		let mut cpu = Rca1802::new();
+		cpu.x = 5;
+		cpu.p = 3;
+		assert!(cpu.interrupt());
+		assert_eq!((cpu.t, cpu.x, cpu.p, cpu.ie), (0x53, 2, 1, false));
+		// Interrupts are disabled until the routine returns.
+		assert!(!cpu.interrupt());
+		// SAV, DEC R2, RET returns to where the interrupt happened.
+		let mut memory = vec![0; 0x100];
+		cpu.r[1] = 0x10;
+		cpu.r[2] = 0x80;
+		memory[0x10..0x14].copy_from_slice(&[0x78, 0x22, 0x60, 0x70]);
+		for _ in 0..4 {
+			cpu.step(&mut memory);
+		}
+		assert_eq!(memory[0x80], 0x53);
+		assert_eq!((cpu.x, cpu.p, cpu.ie), (5, 3, true));
+		assert!(cpu.interrupt());
+	}
+
+	#[test]
+	fn dma() {
+		let memory = (0..=0xFF).collect::<Vec<u8>>();
+		let mut cpu = Rca1802::new();
+		cpu.r[0] = 0x40;
+		let bytes = (0..8).map(|_| cpu.dma_out(&memory)).collect::<Vec<_>>();
+		assert_eq!(bytes, (0x40..0x48).collect::<Vec<_>>());
+		assert_eq!(cpu.r[0], 0x48);
+	}
+
+	#[test]
+	fn input_and_output() {
+		// LDI 40, PLO R4, SEX R4, INP 3, OUT 5
+		let mut cpu = Rca1802::new();
+		cpu.input[3] = 0x5A;
+		let mut memory = vec![0; 0x100];
+		memory[..6].copy_from_slice(&[0xF8, 0x40, 0xA4, 0xE4, 0x6B, 0x65]);
+		for _ in 0..5 {
+			cpu.step(&mut memory);
+		}
+		assert_eq!((cpu.d, memory[0x40]), (0x5A, 0x5A));
+		cpu.step(&mut memory);
+		assert_eq!(cpu.output[5], 0x5A);
+		assert_eq!(cpu.r[4], 0x41);
+	}
+}
@@ -0,0 +1,262 @@
+use crate::{state::StateError, vm::Vm};
+use std::collections::VecDeque;
+
+/// Changed bytes closer together than this are merged into a single run,
+/// as a run header costs more than the bytes in between.
+const MERGE_DISTANCE: usize = 6;
+
+/// How to get from a snapshot to the one taken the frame before it.
+enum Delta {
+	/// The complete previous snapshot, used when the snapshots differ in size.
+	Full(Vec<u8>),
+	/// Runs of bytes that differ from the newer snapshot,
+	/// encoded as a big-endian `u32` offset, a big-endian `u16` length, and the bytes.
+	Patch(Vec<u8>),
+}
+
+impl Delta {
+	/// Creates a delta that turns `newer` back into `older`.
+	fn new(newer: &[u8], older: &[u8]) -> Self {
+		if newer.len() != older.len() {
+			return Delta::Full(older.to_vec());
+		}
+		let mut patch = Vec::new();
+		let mut offset = 0;
+		while offset < older.len() {
+			if newer[offset] == older[offset] {
+				offset += 1;
+				continue;
+			}
+			let start = offset;
+			let mut end = offset + 1;
+			let mut same = 0;
+			while end < older.len() && end - start < u16::MAX as usize && same < MERGE_DISTANCE {
+				if newer[end] == older[end] {
+					same += 1;
+				} else {
+					same = 0;
+				}
+				end += 1;
+			}
+			let end = end - same;
+			patch.extend_from_slice(&(start as u32).to_be_bytes());
+			patch.extend_from_slice(&((end - start) as u16).to_be_bytes());
+			patch.extend_from_slice(&older[start..end]);
+			offset = end;
+		}
+		Delta::Patch(patch)
+	}
+
+	/// Turns `snapshot` into the snapshot taken the frame before it.
+	fn apply(&self, snapshot: &mut Vec<u8>) {
+		match self {
+			Delta::Full(older) => snapshot.clone_from(older),
+			Delta::Patch(patch) => {
+				let mut patch = patch.as_slice();
+				while !patch.is_empty() {
+					let start =
+						u32::from_be_bytes([patch[0], patch[1], patch[2], patch[3]]) as usize;
+					let len = u16::from_be_bytes([patch[4], patch[5]]) as usize;
+					snapshot[start..start + len].copy_from_slice(&patch[6..6 + len]);
+					patch = &patch[6 + len..];
+				}
+			}
+		}
+	}
+
+	/// The number of bytes used by the delta.
+	fn size(&self) -> usize {
+		match self {
+			Delta::Full(older) => older.len(),
+			Delta::Patch(patch) => patch.len(),
+		}
+	}
+}
+
+/// A ring buffer of per-frame snapshots of the virtual machine, used to run
+/// the game backwards.
+///
+/// Only the most recent snapshot is stored in full; every older frame is stored
+/// as a delta against the frame after it. Once the buffer holds more than
+/// `max_frames` frames or `max_bytes` bytes, the oldest frames are dropped.
+pub struct RewindBuffer {
+	/// The maximum number of frames that can be rewound.
+	pub max_frames: usize,
+	/// The maximum number of bytes the buffer may use.
+	pub max_bytes: usize,
+	/// The most recent snapshot.
+	latest: Option<Vec<u8>>,
+	/// The deltas to previous frames, oldest first.
+	deltas: VecDeque<Delta>,
+	/// The number of bytes used by the deltas.
+	delta_bytes: usize,
+}
+
+impl RewindBuffer {
+	/// Creates an empty rewind buffer.
+	pub fn new(max_frames: usize, max_bytes: usize) -> Self {
+		RewindBuffer {
+			max_frames,
+			max_bytes,
+			latest: None,
+			deltas: VecDeque::new(),
+			delta_bytes: 0,
+		}
+	}
+
+	/// Records the current state of the virtual machine as a new frame.
+	pub fn push(&mut self, vm: &Vm) {
+		let snapshot = vm.save_state();
+		if let Some(latest) = self.latest.take() {
+			let delta = Delta::new(&snapshot, &latest);
+			self.delta_bytes += delta.size();
+			self.deltas.push_back(delta);
+		}
+		self.latest = Some(snapshot);
+		self.trim();
+	}
+
+	/// Restores the virtual machine to the previous frame.
+	/// Returns `false` if there are no frames left to rewind to.
+	pub fn rewind(&mut self, vm: &mut Vm) -> Result<bool, StateError> {
+		let (latest, delta) = match (self.latest.as_mut(), self.deltas.pop_back()) {
+			(Some(latest), Some(delta)) => (latest, delta),
+			_ => return Ok(false),
+		};
+		self.delta_bytes -= delta.size();
+		delta.apply(latest);
+		// Loading a snapshot also resets the instruction cache.
+		vm.load_state(latest)?;
+		Ok(true)
+	}
+
+	/// Changes how many frames and bytes the buffer may hold, dropping the oldest
+	/// frames if it's over the new limits.
+	pub fn set_limits(&mut self, max_frames: usize, max_bytes: usize) {
+		self.max_frames = max_frames;
+		self.max_bytes = max_bytes;
+		self.trim();
+	}
+
+	/// Discards every recorded frame.
+	pub fn clear(&mut self) {
+		self.latest = None;
+		self.deltas.clear();
+		self.delta_bytes = 0;
+	}
+
+	/// The number of frames that can currently be rewound.
+	pub fn len(&self) -> usize {
+		self.deltas.len()
+	}
+
+	/// Whether there are no frames that can be rewound.
+	pub fn is_empty(&self) -> bool {
+		self.deltas.is_empty()
+	}
+
+	/// The number of bytes currently used by the buffer.
+	pub fn memory_usage(&self) -> usize {
+		self.latest.as_ref().map_or(0, Vec::len) + self.delta_bytes
+	}
+
+	/// Drops the oldest frames until the buffer is within its limits.
+	fn trim(&mut self) {
+		while self.deltas.len() > self.max_frames || self.memory_usage() > self.max_bytes {
+			match self.deltas.pop_front() {
+				Some(delta) => self.delta_bytes -= delta.size(),
+				None => break,
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::RewindBuffer;
+	use crate::vm::Vm;
+
+	/// A virtual machine that counts frames in V0 and stores the count at 0x300.
+	fn counting_vm() -> Vm {
+		// Drawing waits for the next frame, so V0 goes up by one each frame.
+		let program = [
+			0x70, 0x01, // ADD V0, 1
+			0xA3, 0x00, // LD I, 0x300
+			0xF0, 0x55, // LD [I], V0
+			0xD0, 0x00, // DRW V0, V0, 0
+			0x12, 0x00, // JP 0x200
+		];
+		let mut vm = Vm::new();
+		vm.quirks.display_wait = true;
+		vm.load_program(&program).unwrap();
+		vm
+	}
+
+	#[test]
+	fn rewinds_frame_by_frame() {
+		let mut vm = counting_vm();
+		let mut rewind = RewindBuffer::new(100, usize::MAX);
+		let mut counts = Vec::new();
+		for _ in 0..10 {
+			vm.run_frame().unwrap();
+			rewind.push(&vm);
+			counts.push(vm.registers[0]);
+		}
+		assert_eq!(rewind.len(), 9);
+		counts.pop();
+		while let Some(count) = counts.pop() {
+			assert!(rewind.rewind(&mut vm).unwrap());
+			assert_eq!(vm.registers[0], count);
+			assert_eq!(vm.memory[0x300], count);
+		}
+		assert!(rewind.is_empty());
+		assert!(!rewind.rewind(&mut vm).unwrap());
+	}
+
+	#[test]
+	fn drops_the_oldest_frames() {
+		let mut vm = counting_vm();
+		let mut rewind = RewindBuffer::new(3, usize::MAX);
+		for _ in 0..10 {
+			vm.run_frame().unwrap();
+			rewind.push(&vm);
+		}
+		assert_eq!(rewind.len(), 3);
+		while rewind.rewind(&mut vm).unwrap() {}
+		// Frames 7 to 10 were kept, so the oldest one left is frame 7.
+		assert_eq!(vm.registers[0], 7);
+
+		rewind.set_limits(1, usize::MAX);
+		assert!(rewind.len() <= 1);
+	}
+
+	#[test]
+	fn stays_within_its_budget() {
+		let mut vm = counting_vm();
+		let snapshot = vm.save_state().len();
+		// Room for the latest snapshot and a few small deltas.
+		let budget = snapshot + 64;
+		let mut rewind = RewindBuffer::new(1000, budget);
+		for _ in 0..100 {
+			vm.run_frame().unwrap();
+			rewind.push(&vm);
+			assert!(rewind.memory_usage() <= budget);
+		}
+		assert!(!rewind.is_empty());
+		assert!(rewind.len() < 100);
+	}
+
+	#[test]
+	fn clear_forgets_everything() {
+		let mut vm = counting_vm();
+		let mut rewind = RewindBuffer::new(100, usize::MAX);
+		for _ in 0..5 {
+			vm.run_frame().unwrap();
+			rewind.push(&vm);
+		}
+		rewind.clear();
+		assert!(rewind.is_empty());
+		assert_eq!(rewind.memory_usage(), 0);
+		assert!(!rewind.rewind(&mut vm).unwrap());
+	}
+}
@@ -0,0 +1,387 @@
+use crate::{
+	display::{HIGH_RESOLUTION, LOW_RESOLUTION, PLANES},
+	quirks::{IndexIncrement, Quirks},
+	random::RandomMode,
+	rca1802::Rca1802,
+	vm::{Vm, VmMode, PROGRAM_START, STACK_SIZE},
+};
+
+/// The magic bytes at the start of every save state.
+const MAGIC: [u8; 4] = *b"CHPY";
+/// The current version of the save state format.
+pub const VERSION: u8 = 7;
+
+/// An error encountered while loading a save state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum StateError {
+	/// The data is not a save state.
+	#[display(fmt = "not a save state")]
+	BadMagic,
+	/// The save state was written by an unsupported version of the format.
+	#[display(fmt = "unsupported save state version {}", _0)]
+	UnsupportedVersion(u8),
+	/// The save state ended unexpectedly.
+	#[display(fmt = "save state is truncated")]
+	Truncated,
+	/// The save state contains a value that the virtual machine can't hold.
+	#[display(fmt = "save state has an invalid {}", _0)]
+	Invalid(&'static str),
+}
+
+impl std::error::Error for StateError {}
+
+/// Reads values out of a save state.
+struct Reader<'a> {
+	data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+	fn bytes(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+		if self.data.len() < len {
+			return Err(StateError::Truncated);
+		}
+		let (bytes, rest) = self.data.split_at(len);
+		self.data = rest;
+		Ok(bytes)
+	}
+
+	fn u8(&mut self) -> Result<u8, StateError> {
+		Ok(self.bytes(1)?[0])
+	}
+
+	fn u16(&mut self) -> Result<u16, StateError> {
+		let bytes = self.bytes(2)?;
+		Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+	}
+
+	fn u32(&mut self) -> Result<u32, StateError> {
+		let bytes = self.bytes(4)?;
+		Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+	}
+
+	fn u64(&mut self) -> Result<u64, StateError> {
+		let mut bytes = [0; 8];
+		bytes.copy_from_slice(self.bytes(8)?);
+		Ok(u64::from_be_bytes(bytes))
+	}
+
+	fn bool(&mut self) -> Result<bool, StateError> {
+		match self.u8()? {
+			0 => Ok(false),
+			1 => Ok(true),
+			_ => Err(StateError::Invalid("flag")),
+		}
+	}
+}
+
+fn mode_to_u8(mode: VmMode) -> u8 {
+	match mode {
+		VmMode::Chip8 => 0,
+		VmMode::Chip48 => 1,
+		VmMode::SuperChip => 2,
+		VmMode::XoChip => 3,
+		VmMode::CosmacVip => 4,
+	}
+}
+
+fn mode_from_u8(mode: u8) -> Result<VmMode, StateError> {
+	match mode {
+		0 => Ok(VmMode::Chip8),
+		1 => Ok(VmMode::Chip48),
+		2 => Ok(VmMode::SuperChip),
+		3 => Ok(VmMode::XoChip),
+		4 => Ok(VmMode::CosmacVip),
+		_ => Err(StateError::Invalid("mode")),
+	}
+}
+
+fn index_increment_to_u8(increment: IndexIncrement) -> u8 {
+	match increment {
+		IndexIncrement::XPlusOne => 0,
+		IndexIncrement::X => 1,
+		IndexIncrement::Unchanged => 2,
+	}
+}
+
+fn index_increment_from_u8(increment: u8) -> Result<IndexIncrement, StateError> {
+	match increment {
+		0 => Ok(IndexIncrement::XPlusOne),
+		1 => Ok(IndexIncrement::X),
+		2 => Ok(IndexIncrement::Unchanged),
+		_ => Err(StateError::Invalid("index increment quirk")),
+	}
+}
+
+fn random_mode_to_u8(mode: RandomMode) -> u8 {
+	match mode {
+		RandomMode::WyRand => 0,
+		RandomMode::Vip => 1,
+	}
+}
+
+fn random_mode_from_u8(mode: u8) -> Result<RandomMode, StateError> {
+	match mode {
+		0 => Ok(RandomMode::WyRand),
+		1 => Ok(RandomMode::Vip),
+		_ => Err(StateError::Invalid("random mode")),
+	}
+}
+
+/// Serializes the state of the virtual machine.
+pub fn save(vm: &Vm) -> Vec<u8> {
+	let mut state = Vec::with_capacity(vm.memory.len() + vm.display.plane(0).len() / 4 + 64);
+	state.extend_from_slice(&MAGIC);
+	state.push(VERSION);
+	state.push(mode_to_u8(vm.mode));
+	state.push(vm.quirks.shift_vy as u8);
+	state.push(index_increment_to_u8(vm.quirks.index_increment));
+	state.push(vm.quirks.jump_vx as u8);
+	state.push(vm.quirks.clip_sprites as u8);
+	state.push(vm.quirks.logic_reset_vf as u8);
+	state.push(vm.quirks.display_wait as u8);
+	state.push(vm.quirks.lores_16x16 as u8);
+	state.push(vm.high_resolution as u8);
+	state.extend_from_slice(&vm.registers);
+	state.extend_from_slice(&vm.index_register.to_be_bytes());
+	state.extend_from_slice(&vm.program_counter.to_be_bytes());
+	state.push(vm.stack.len() as u8);
+	for address in &vm.stack {
+		state.extend_from_slice(&address.to_be_bytes());
+	}
+	state.push(vm.delay_timer);
+	state.push(vm.sound_timer);
+	state.extend_from_slice(&vm.rpl);
+	state.push(random_mode_to_u8(vm.random.mode));
+	state.extend_from_slice(&vm.random.seed().to_be_bytes());
+	state.extend_from_slice(&vm.random.state().to_be_bytes());
+	state.extend_from_slice(&(vm.memory.len() as u32).to_be_bytes());
+	state.extend_from_slice(&vm.memory);
+	state.extend_from_slice(&(vm.program_size as u32).to_be_bytes());
+	state.push(vm.plane);
+	state.extend_from_slice(&(vm.display.plane(0).len() as u32).to_be_bytes());
+	for plane in 0..PLANES {
+		for pixels in vm.display.plane(plane).chunks(8) {
+			let byte = pixels.iter().enumerate().fold(0u8, |byte, (bit, pixel)| {
+				byte | ((*pixel as u8) << (7 - bit))
+			});
+			state.push(byte);
+		}
+	}
+	state.extend_from_slice(&vm.audio_pattern);
+	state.push(vm.pitch);
+	for register in &vm.cpu.r {
+		state.extend_from_slice(&register.to_be_bytes());
+	}
+	state.push(vm.cpu.p);
+	state.push(vm.cpu.x);
+	state.push(vm.cpu.d);
+	state.push(vm.cpu.df as u8);
+	state.push(vm.cpu.t);
+	state.push(vm.cpu.ie as u8);
+	state.push(vm.cpu.q as u8);
+	state.extend_from_slice(&vm.cpu.output);
+	state.extend_from_slice(&vm.machine_cycles.to_be_bytes());
+	state.extend_from_slice(&vm.next_interrupt.to_be_bytes());
+	state
+}
+
+/// Restores the state of the virtual machine.
+/// The virtual machine is left untouched if the state can't be loaded.
+pub fn load(vm: &mut Vm, state: &[u8]) -> Result<(), StateError> {
+	let mut reader = Reader { data: state };
+	if reader.bytes(MAGIC.len())? != MAGIC {
+		return Err(StateError::BadMagic);
+	}
+	let version = reader.u8()?;
+	if version != VERSION {
+		return Err(StateError::UnsupportedVersion(version));
+	}
+	let mode = mode_from_u8(reader.u8()?)?;
+	let quirks = Quirks {
+		shift_vy: reader.bool()?,
+		index_increment: index_increment_from_u8(reader.u8()?)?,
+		jump_vx: reader.bool()?,
+		clip_sprites: reader.bool()?,
+		logic_reset_vf: reader.bool()?,
+		display_wait: reader.bool()?,
+		lores_16x16: reader.bool()?,
+	};
+	let high_resolution = reader.bool()?;
+	let mut registers = [0; 16];
+	registers.copy_from_slice(reader.bytes(16)?);
+	let index_register = reader.u16()?;
+	let program_counter = reader.u16()?;
+	let stack_len = reader.u8()? as usize;
+	if stack_len > STACK_SIZE {
+		return Err(StateError::Invalid("stack"));
+	}
+	let stack = (0..stack_len)
+		.map(|_| reader.u16())
+		.collect::<Result<Vec<_>, _>>()?;
+	let delay_timer = reader.u8()?;
+	let sound_timer = reader.u8()?;
+	let mut rpl = [0; 16];
+	rpl.copy_from_slice(reader.bytes(16)?);
+	let random_mode = random_mode_from_u8(reader.u8()?)?;
+	let random_seed = reader.u64()?;
+	let random_state = reader.u64()?;
+	if reader.u32()? as usize != mode.memory_size() {
+		return Err(StateError::Invalid("memory size"));
+	}
+	let memory = reader.bytes(mode.memory_size())?;
+	let program_size = reader.u32()? as usize;
+	if program_size > mode.memory_size() - PROGRAM_START {
+		return Err(StateError::Invalid("program size"));
+	}
+	let plane = reader.u8()?;
+	let display_len = reader.u32()? as usize;
+	let (width, height) = if high_resolution {
+		HIGH_RESOLUTION
+	} else {
+		LOW_RESOLUTION
+	};
+	if display_len != width * height {
+		return Err(StateError::Invalid("display size"));
+	}
+	let display = reader.bytes(display_len.div_ceil(8) * PLANES)?;
+	let mut audio_pattern = [0; 16];
+	audio_pattern.copy_from_slice(reader.bytes(16)?);
+	let pitch = reader.u8()?;
+	let mut cpu = Rca1802::new();
+	for register in cpu.r.iter_mut() {
+		*register = reader.u16()?;
+	}
+	cpu.p = reader.u8()? & 0xF;
+	cpu.x = reader.u8()? & 0xF;
+	cpu.d = reader.u8()?;
+	cpu.df = reader.bool()?;
+	cpu.t = reader.u8()?;
+	cpu.ie = reader.bool()?;
+	cpu.q = reader.bool()?;
+	cpu.output.copy_from_slice(reader.bytes(8)?);
+	let machine_cycles = reader.u64()?;
+	let next_interrupt = reader.u64()?;
+
+	vm.mode = mode;
+	vm.quirks = quirks;
+	vm.set_high_resolution(high_resolution);
+	for (plane, display) in display.chunks(display_len.div_ceil(8)).enumerate() {
+		for (index, mut pixel) in vm.display.plane_mut(plane).iter_mut().enumerate() {
+			*pixel = (display[index / 8] >> (7 - index % 8)) & 1 != 0;
+		}
+	}
+	vm.plane = plane;
+	vm.audio_pattern = audio_pattern;
+	vm.pitch = pitch;
+	vm.cpu = cpu;
+	vm.machine_cycles = machine_cycles;
+	vm.next_interrupt = next_interrupt;
+	vm.registers = registers;
+	vm.index_register = index_register;
+	vm.program_counter = program_counter;
+	vm.stack = stack;
+	vm.delay_timer = delay_timer;
+	vm.sound_timer = sound_timer;
+	vm.rpl = rpl;
+	vm.random.mode = random_mode;
+	vm.random.set_state(random_seed, random_state);
+	vm.memory.clear();
+	vm.memory.extend_from_slice(memory);
+	vm.program_size = program_size;
+	vm.keypad.set_all(false);
+	vm.fault = None;
+	// Any cached instructions are stale now that memory has been replaced.
+	vm.instruction_cache.reset(vm.memory.len());
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use crate::{
+		assembler,
+		quirks::IndexIncrement,
+		random::RandomMode,
+		vm::{Vm, VmMode},
+	};
+
+	/// Runs a SUPER-CHIP program that leaves something in every part of the state.
+	fn running_vm() -> Vm {
+		let source = r#"
+			HIGH
+			LD V0, 40
+			LD DT, V0
+			LD ST, V0
+			RND V1, 0xFF
+			LD V2, 8
+			LD HF, V2
+			DRW V2, V2, 10
+			LD R, V2
+			CALL wait
+		wait:
+			RND V3, 0xFF
+			JP wait
+		"#;
+		let program = assembler::assemble(source, VmMode::SuperChip).unwrap();
+		let mut vm = Vm::new();
+		vm.set_mode(VmMode::SuperChip);
+		vm.quirks.shift_vy = true;
+		vm.quirks.index_increment = IndexIncrement::X;
+		vm.random.mode = RandomMode::Vip;
+		vm.set_seed(0x1234_5678);
+		vm.load_program(&program).unwrap();
+		for _ in 0..5 {
+			vm.run_frame().unwrap();
+		}
+		vm
+	}
+
+	#[test]
+	fn round_trip() {
+		let mut original = running_vm();
+		let mut restored = Vm::new();
+		restored.load_state(&original.save_state()).unwrap();
+
+		assert_eq!(restored.mode, original.mode);
+		assert_eq!(restored.quirks, original.quirks);
+		assert_eq!(restored.registers, original.registers);
+		assert_eq!(restored.index_register, original.index_register);
+		assert_eq!(restored.program_counter, original.program_counter);
+		assert_eq!(restored.stack, original.stack);
+		assert_eq!(restored.delay_timer, original.delay_timer);
+		assert_eq!(restored.sound_timer, original.sound_timer);
+		assert_eq!(restored.rpl, original.rpl);
+		assert_eq!(restored.high_resolution, original.high_resolution);
+		assert_eq!(restored.display, original.display);
+		assert_eq!(restored.memory, original.memory);
+		assert_eq!(restored.program_size, original.program_size);
+		assert_eq!(restored.random, original.random);
+		assert_eq!(restored.cpu, original.cpu);
+
+		// Both carry on identically, including the random numbers they draw.
+		for _ in 0..5 {
+			original.run_frame().unwrap();
+			restored.run_frame().unwrap();
+		}
+		assert_eq!(restored.registers, original.registers);
+		assert_eq!(restored.display, original.display);
+	}
+
+	#[test]
+	fn rejects_bad_states() {
+		let state = running_vm().save_state();
+		let mut vm = Vm::new();
+		assert_eq!(
+			vm.load_state(&state[..state.len() - 1]),
+			Err(super::StateError::Truncated)
+		);
+		assert_eq!(vm.load_state(b"nope"), Err(super::StateError::BadMagic));
+		let mut future = state.clone();
+		future[4] = super::VERSION + 1;
+		assert_eq!(
+			vm.load_state(&future),
+			Err(super::StateError::UnsupportedVersion(super::VERSION + 1))
+		);
+		// A failed load leaves the virtual machine untouched.
+		assert_eq!(vm.mode, VmMode::Chip8);
+	}
+}
@@ -0,0 +1,165 @@
+use crate::{
+	instruction::{
+		arthimetic::{ArthimeticInstruction, ArthimeticValue},
+		branch::{BranchInstruction, BranchTarget, BranchType},
+		load::{LoadInstruction, LoadTarget},
+		Instruction,
+	},
+	vm::Vm,
+};
+
+/// The number of RCA 1802 machine cycles in each 60 Hz frame of the COSMAC VIP.
+/// The 1802 is clocked at 1.76 MHz, with 8 clock pulses per machine cycle.
+pub const VIP_FRAME_CYCLES: u64 = 3668;
+/// The machine cycles taken from the interpreter in each frame by the display DMA
+/// (8 bytes for each of 128 scanlines) and the interrupt routine.
+pub const VIP_INTERRUPT_CYCLES: u64 = 1024 + 46;
+/// The extra machine cycles a conditional skip takes when the skip happens.
+pub const VIP_SKIP_CYCLES: u64 = 4;
+/// The machine cycles the interpreter spends fetching and decoding every instruction.
+const FETCH_CYCLES: u64 = 40;
+/// The extra machine cycles a sprite row takes when it collides, to set VF.
+const COLLISION_CYCLES: u64 = 4;
+/// The machine cycles a sprite row takes when it's clipped off the bottom of the display.
+const CLIPPED_ROW_CYCLES: u64 = 8;
+
+/// The machine cycles the COSMAC VIP interpreter takes to execute an instruction,
+/// before it is executed by the virtual machine.
+///
+/// This doesn't include skips, which take an extra [`VIP_SKIP_CYCLES`], or the
+/// machine code run by 0nnn. Instructions that don't exist on the VIP are charged
+/// as if they were a simple register operation.
+pub fn vip_cycles(vm: &Vm, instruction: &Instruction) -> u64 {
+	let register = |register: u8| vm.registers[register as usize & 0xF];
+	FETCH_CYCLES
+		+ match *instruction {
+			Instruction::Sys(_) => 26,
+			Instruction::Clear => 3078,
+			Instruction::Return => 10,
+			Instruction::Random(..) => 36,
+			Instruction::Draw(x, y, rows) => draw_cycles(vm, register(x), register(y), rows),
+			Instruction::LoadKey(_) => 18,
+			Instruction::AddI(_) => 16,
+			// Bnnn adds V0 to the address before jumping.
+			Instruction::Branch(BranchInstruction {
+				branch_target: BranchTarget::AddressOffset(..),
+				..
+			}) => 22,
+			Instruction::Branch(BranchInstruction { branch_type, .. }) => match branch_type {
+				BranchType::Unconditional => 12,
+				BranchType::Call => 26,
+				BranchType::Equal { .. } => 10,
+				BranchType::EqualRegister { .. } | BranchType::KeyPressed { .. } => 14,
+			},
+			Instruction::Arthimetic(ArthimeticInstruction {
+				values: ArthimeticValue::RegisterValue(..),
+				..
+			}) => 10,
+			Instruction::Arthimetic(_) | Instruction::Logical(_) => 44,
+			Instruction::Load(LoadInstruction { from, into }) => match (from, into) {
+				(LoadTarget::Value(_), LoadTarget::Register(_)) => 6,
+				(LoadTarget::Register(_), LoadTarget::Register(_)) => 44,
+				(LoadTarget::Address(_), LoadTarget::I) => 12,
+				(LoadTarget::Font(_), LoadTarget::I) => 16,
+				// BCD is converted by repeated subtraction, so larger digits take longer.
+				(LoadTarget::Register(from), LoadTarget::Bcd) => {
+					let value = register(from);
+					let digits = value / 100 + (value / 10) % 10 + value % 10;
+					80 + 16 * digits as u64
+				}
+				(LoadTarget::Register(last), LoadTarget::I)
+				| (LoadTarget::I, LoadTarget::Register(last)) => 14 + 14 * (last as u64 + 1),
+				_ => 10,
+			},
+			_ => 10,
+		}
+}
+
+/// The machine cycles taken to draw a sprite.
+///
+/// Sprites are drawn a byte at a time, taking twice as long for each row when
+/// it straddles two bytes of the display. Rows that collide take longer to set
+/// VF, and rows clipped off the bottom of the display are skipped.
+fn draw_cycles(vm: &Vm, x: u8, y: u8, rows: u8) -> u64 {
+	let (width, height) = (vm.display.width(), vm.display.height());
+	let x = x as usize % width;
+	let y = y as usize % height;
+	let row_cycles = if x.is_multiple_of(8) { 34 } else { 68 };
+	let rows = (0..rows as usize).map(|row| {
+		let y = y + row;
+		if y >= height && vm.quirks.clip_sprites {
+			return CLIPPED_ROW_CYCLES;
+		}
+		let sprite = vm
+			.memory
+			.get(vm.index_register as usize + row)
+			.copied()
+			.unwrap_or(0);
+		let collides = (0..8).any(|bit| {
+			sprite & (0x80 >> bit) != 0 && vm.display.get(0, (x + bit) % width, y % height)
+		});
+		row_cycles + if collides { COLLISION_CYCLES } else { 0 }
+	});
+	26 + rows.sum::<u64>()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vm::{Speed, VmMode};
+
+	fn vip_vm(program: &[u8]) -> Vm {
+		let mut vm = Vm::new();
+		vm.set_mode(VmMode::CosmacVip);
+		vm.speed = Speed::CosmacVip;
+		vm.load_program(program).unwrap();
+		vm
+	}
+
+	fn cycles(vm: &Vm) -> u64 {
+		let instruction =
+			Instruction::decode(&vm.memory, vm.program_counter as usize, vm.mode).unwrap();
+		vip_cycles(vm, &instruction)
+	}
+
+	#[test]
+	fn offset_jumps_take_longer() {
+		let jump = vip_vm(&[0x12, 0x00]);
+		let offset_jump = vip_vm(&[0xB2, 0x00]);
+		assert_eq!(cycles(&jump), FETCH_CYCLES + 12);
+		assert_eq!(cycles(&offset_jump), FETCH_CYCLES + 22);
+	}
+
+	#[test]
+	fn drawing_depends_on_position_and_collisions() {
+		// LD I, 0x20A; DRW V0, V1, 2; the sprite
+		let mut vm = vip_vm(&[
+			0xA2, 0x0A, 0xD0, 0x12, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF,
+		]);
+		vm.execute().unwrap();
+		let aligned = cycles(&vm);
+		assert_eq!(aligned, FETCH_CYCLES + 26 + 2 * 34);
+		vm.registers[0] = 4;
+		assert_eq!(cycles(&vm), FETCH_CYCLES + 26 + 2 * 68);
+		vm.registers[0] = 0;
+		vm.display.set(0, 3, 1, true);
+		assert_eq!(cycles(&vm), aligned + COLLISION_CYCLES);
+		vm.registers[1] = 31;
+		assert_eq!(cycles(&vm), FETCH_CYCLES + 26 + 34 + CLIPPED_ROW_CYCLES);
+		vm.quirks.clip_sprites = false;
+		assert_eq!(cycles(&vm), aligned);
+	}
+
+	#[test]
+	fn cosmac_vip_frame() {
+		// ADD V0, 1; JP 0x200
+		let mut vm = vip_vm(&[0x70, 0x01, 0x12, 0x00]);
+		vm.run_frame().unwrap();
+		// Each loop takes 102 machine cycles, out of the 3668 in a frame.
+		assert_eq!(vm.registers[0], 36);
+		vm.run_frame().unwrap();
+		// Later frames lose the time taken by the display and interrupt.
+		assert_eq!(vm.registers[0], 36 + 26);
+		assert_eq!(vm.next_interrupt, 3 * VIP_FRAME_CYCLES);
+	}
+}
@@ -0,0 +1,783 @@
+use crate::{
+	breakpoint::Breakpoint,
+	cache::InstructionCache,
+	display::{Display, HIGH_RESOLUTION, LOW_RESOLUTION},
+	error::VmError,
+	instruction::{draw, Address, Instruction},
+	quirks::Quirks,
+	random::Random,
+	rca1802::{self, Rca1802},
+	state::{self, StateError},
+	timing::{self, VIP_FRAME_CYCLES, VIP_INTERRUPT_CYCLES, VIP_SKIP_CYCLES},
+	watchpoint::{Access, Watchpoint, WatchpointHit, MAX_WATCHPOINT_HITS},
+};
+use bitvec::{array::BitArray, BitArr};
+use std::{
+	collections::{BTreeMap, VecDeque},
+	ops::{Range, RangeBounds},
+	str::FromStr,
+	time::{Duration, Instant},
+};
+
+/// The maximum depth of the call stack.
+pub const STACK_SIZE: usize = 16;
+/// The initial value of the XO-CHIP pitch register, which plays at 4000 Hz.
+pub const DEFAULT_PITCH: u8 = 64;
+/// The length of a single 60 Hz frame.
+pub const FRAME_TIME: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// How many instructions run between checks of the time at unlimited speed.
+const UNLIMITED_BATCH: usize = 256;
+/// The address programs are loaded at.
+pub const PROGRAM_START: usize = 0x200;
+/// The address of the small 5-byte hex font.
+pub const FONT_ADDRESS: u16 = 0x50;
+/// The address of the large 10-byte SUPER-CHIP hex font.
+pub const BIG_FONT_ADDRESS: u16 = 0xA0;
+
+const FONT: [u8; 80] = [
+	0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+	0x20, 0x60, 0x20, 0x20, 0x70, // 1
+	0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+	0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+	0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+	0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+	0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+	0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+	0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+	0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+	0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+	0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+	0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+	0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+	0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+	0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+const BIG_FONT: [u8; 160] = [
+	0x3C, 0x7E, 0xE7, 0xC3, 0xC3, 0xC3, 0xC3, 0xE7, 0x7E, 0x3C, // 0
+	0x18, 0x38, 0x58, 0x18, 0x18, 0x18, 0x18, 0x18, 0x18, 0x3C, // 1
+	0x3E, 0x7F, 0xC3, 0x06, 0x0C, 0x18, 0x30, 0x60, 0xFF, 0xFF, // 2
+	0x3C, 0x7E, 0xC3, 0x03, 0x0E, 0x0E, 0x03, 0xC3, 0x7E, 0x3C, // 3
+	0x06, 0x0E, 0x1E, 0x36, 0x66, 0xC6, 0xFF, 0xFF, 0x06, 0x06, // 4
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFC, 0xFE, 0x03, 0xC3, 0x7E, 0x3C, // 5
+	0x3E, 0x7C, 0xC0, 0xC0, 0xFC, 0xFE, 0xC3, 0xC3, 0x7E, 0x3C, // 6
+	0xFF, 0xFF, 0x03, 0x06, 0x0C, 0x18, 0x30, 0x60, 0x60, 0x60, // 7
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7E, 0x7E, 0xC3, 0xC3, 0x7E, 0x3C, // 8
+	0x3C, 0x7E, 0xC3, 0xC3, 0x7F, 0x3F, 0x03, 0x03, 0x3E, 0x7C, // 9
+	0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+	0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+	0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+	0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+	0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+/// The CHIP-8 virtual machine and interpreter.
+pub struct Vm {
+	/// The interpreter the CHIP-8 virtual machine emulates.
+	pub mode: VmMode,
+	/// How the CHIP-8 virtual machine behaves where interpreters differ.
+	pub quirks: Quirks,
+	/// The memory of the CHIP-8 virtual machine.
+	/// This is 4 KiB, or 64 KiB in XO-CHIP mode.
+	pub memory: Vec<u8>,
+	/// The size of the loaded program, which starts at 0x200.
+	pub program_size: usize,
+	/// The cache of decoded instructions and basic blocks.
+	pub instruction_cache: InstructionCache,
+	/// The registers of the CHIP-8 virtual machine.
+	pub registers: [u8; 16],
+	/// The index register of the CHIP-8 virtual machine.
+	pub index_register: u16,
+	/// The program counter of the CHIP-8 virtual machine.
+	pub program_counter: u16,
+	/// The stack of the CHIP-8 virtual machine.
+	pub stack: Vec<u16>,
+	/// The delay timer of the CHIP-8 virtual machine.
+	pub delay_timer: u8,
+	/// The sound timer of the CHIP-8 virtual machine.
+	pub sound_timer: u8,
+	/// The keypad of the CHIP-8 virtual machine.
+	pub keypad: BitArr!(for 0xF),
+	/// The display of the CHIP-8 virtual machine.
+	pub display: Display,
+	/// The bitmask of display planes that are drawn to.
+	pub plane: u8,
+	/// The XO-CHIP audio pattern buffer, played back one bit at a time.
+	pub audio_pattern: [u8; 16],
+	/// The XO-CHIP audio pitch register.
+	pub pitch: u8,
+	/// The RPL user flags of the CHIP-8 virtual machine.
+	pub rpl: [u8; 16],
+	/// The random number generator used by the CHIP-8 virtual machine.
+	pub random: Random,
+	/// The RCA 1802 that runs machine code subroutines in COSMAC VIP mode.
+	pub cpu: Rca1802,
+	/// Whether high-resolution mode is enabled or not.
+	pub high_resolution: bool,
+	/// Whether the CHIP-8 virtual machine is paused or not.
+	pub paused: bool,
+	/// Whether the CHIP-8 virtual machine is waiting for the next frame.
+	pub vblank_wait: bool,
+	/// The fault that paused the CHIP-8 virtual machine, if any.
+	pub fault: Option<VmError>,
+	/// The breakpoints that pause the CHIP-8 virtual machine before the instruction
+	/// at their address is executed.
+	pub breakpoints: BTreeMap<Address, Breakpoint>,
+	/// The breakpoint that paused the CHIP-8 virtual machine, which is passed over
+	/// when it resumes.
+	pub breakpoint: Option<Address>,
+	/// The watchpoints that report the instructions accessing ranges of memory.
+	pub watchpoints: Vec<Watchpoint>,
+	/// The latest accesses to watched memory, oldest first.
+	pub watchpoint_hits: VecDeque<WatchpointHit>,
+	/// How many instructions are executed in each frame.
+	pub speed: Speed,
+	/// The number of frames that the CHIP-8 virtual machine has run.
+	pub frames: usize,
+	/// The number of cycles that the CHIP-8 virtual machine has executed.
+	pub cycles: usize,
+	/// The number of COSMAC VIP machine cycles the executed instructions would have taken.
+	pub machine_cycles: u64,
+	/// The machine cycle of the next COSMAC VIP interrupt, which ends the frame.
+	pub next_interrupt: u64,
+	/// How long the last cycle took for the CHIP-8 virtual machine to execute.
+	pub last_cycle_time: Duration,
+	/// The average cycle time of the CHIP-8 virtual machine.
+	pub average_cycle_time: Duration,
+}
+
+impl Vm {
+	/// Creates a new CHIP-8 virtual machine.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn setup_memory(&mut self) {
+		self.memory.iter_mut().for_each(|byte| *byte = 0);
+		let font = FONT_ADDRESS as usize;
+		self.memory[font..font + FONT.len()].copy_from_slice(&FONT);
+		let big_font = BIG_FONT_ADDRESS as usize;
+		self.memory[big_font..big_font + BIG_FONT.len()].copy_from_slice(&BIG_FONT);
+	}
+
+	/// Loads a CHIP-8 program into the virtual machine.
+	pub fn load_program(&mut self, program: &[u8]) -> Result<(), VmError> {
+		// Ensure the program is not too large (0x1000 - 0x200)
+		// The COSMAC VIP interpreter reserves the top of memory for itself.
+		let end = match self.mode {
+			VmMode::CosmacVip => rca1802::VIP_RESERVED,
+			_ => self.memory.len(),
+		};
+		let max = end - PROGRAM_START;
+		if program.len() > max {
+			return Err(VmError::ProgramTooLarge {
+				size: program.len(),
+				max,
+			});
+		}
+		// Clean up the VM's state.
+		self.registers.iter_mut().for_each(|byte| *byte = 0);
+		self.index_register = 0;
+		self.program_counter = PROGRAM_START as Address;
+		self.stack.clear();
+		self.frames = 0;
+		self.cycles = 0;
+		self.machine_cycles = 0;
+		self.next_interrupt = VIP_FRAME_CYCLES;
+		self.delay_timer = 0;
+		self.sound_timer = 0;
+		self.fault = None;
+		self.breakpoint = None;
+		self.keypad.set_all(false);
+		self.set_high_resolution(false);
+		self.plane = 1;
+		self.audio_pattern = [0; 16];
+		self.pitch = DEFAULT_PITCH;
+		self.cpu = Rca1802::new();
+		self.instruction_cache.clear();
+		self.setup_memory();
+		// Copy the program to memory.
+		self.memory[PROGRAM_START..PROGRAM_START + program.len()].copy_from_slice(program);
+		self.program_size = program.len();
+		// Unpause the VM.
+		self.paused = false;
+		Ok(())
+	}
+
+	/// Serializes the complete state of the virtual machine into a save state.
+	pub fn save_state(&self) -> Vec<u8> {
+		state::save(self)
+	}
+
+	/// Restores the virtual machine from a save state created by [`Vm::save_state`].
+	pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+		state::load(self, state)
+	}
+
+	/// Reseeds the random number generator of the CHIP-8 virtual machine.
+	pub fn set_seed(&mut self, seed: u64) {
+		self.random.reseed(seed);
+	}
+
+	/// Sets the interperter mode of the CHIP-8 virtual machine,
+	/// along with the quirks of that interpreter.
+	pub fn set_mode(&mut self, mode: VmMode) {
+		self.mode = mode;
+		self.quirks = mode.quirks();
+		self.memory.resize(mode.memory_size(), 0);
+		// Instructions may not exist in the new mode.
+		self.instruction_cache.reset(mode.memory_size());
+	}
+
+	pub fn set_high_resolution(&mut self, high_resolution: bool) {
+		self.high_resolution = high_resolution;
+		let (width, height) = if self.high_resolution {
+			HIGH_RESOLUTION
+		} else {
+			LOW_RESOLUTION
+		};
+		self.display.resize(width, height);
+	}
+
+	/// Invalidate the instruction cache for a memory range.
+	/// This allows for self-modifying code, as we invalidate the memory range
+	/// wherever memory is written.
+	pub fn invalidate_cache<R>(&mut self, memory_range: R)
+	where
+		R: RangeBounds<usize>,
+	{
+		self.instruction_cache.invalidate(memory_range);
+	}
+
+	/// Reads the opcode at an address.
+	pub fn opcode_at(&self, address: u16) -> u16 {
+		let address = address as usize;
+		u16::from_be_bytes([
+			self.memory.get(address).copied().unwrap_or(0),
+			self.memory.get(address + 1).copied().unwrap_or(0),
+		])
+	}
+
+	/// Reports a read of memory by the current instruction to the watchpoints on it.
+	pub(crate) fn watch_read(&mut self, memory_range: Range<usize>) {
+		if !self.watchpoints.is_empty() {
+			let bytes = self.memory[memory_range.clone()].to_vec();
+			self.watch(Access::Read, memory_range.start, &bytes, &bytes);
+		}
+	}
+
+	/// Writes bytes to memory for the current instruction, reporting them to the
+	/// watchpoints on it and invalidating the instructions cached there.
+	pub(crate) fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+		let memory_range = address..address + bytes.len();
+		if !self.watchpoints.is_empty() {
+			let old = self.memory[memory_range.clone()].to_vec();
+			self.watch(Access::Write, address, &old, bytes);
+		}
+		self.memory[memory_range.clone()].copy_from_slice(bytes);
+		self.invalidate_cache(memory_range);
+	}
+
+	/// Records an access to each watchpoint it overlaps, pausing if one asks to.
+	fn watch(&mut self, access: Access, address: usize, old: &[u8], new: &[u8]) {
+		// The instruction is decoded before it's written, in case it overwrites itself.
+		let instruction =
+			Instruction::decode(&self.memory, self.program_counter as usize, self.mode);
+		let end = address + old.len();
+		for watchpoint in &self.watchpoints {
+			if !watchpoint.enabled || !watchpoint.access.includes(access) {
+				continue;
+			}
+			let start = address.max(*watchpoint.range.start() as usize);
+			let stop = end.min(*watchpoint.range.end() as usize + 1);
+			if start >= stop {
+				continue;
+			}
+			if self.watchpoint_hits.len() == MAX_WATCHPOINT_HITS {
+				self.watchpoint_hits.pop_front();
+			}
+			self.watchpoint_hits.push_back(WatchpointHit {
+				program_counter: self.program_counter,
+				instruction,
+				access,
+				address: start as Address,
+				old: old[start - address..stop - address].to_vec(),
+				new: new[start - address..stop - address].to_vec(),
+			});
+			self.paused |= watchpoint.pause;
+		}
+	}
+
+	/// Reads the opcode at the program counter.
+	pub fn opcode(&self) -> u16 {
+		self.opcode_at(self.program_counter)
+	}
+
+	/// Creates an [`VmError::InvalidOpcode`] for the current instruction.
+	pub(crate) fn invalid_opcode(&self) -> VmError {
+		VmError::InvalidOpcode {
+			pc: self.program_counter,
+			opcode: self.opcode(),
+		}
+	}
+
+	/// Checks that `len` bytes starting at `address` are within memory,
+	/// returning the memory range.
+	pub(crate) fn memory_range(&self, address: usize, len: usize) -> Result<Range<usize>, VmError> {
+		if address + len > self.memory.len() {
+			return Err(VmError::MemoryOutOfBounds {
+				pc: self.program_counter,
+				opcode: self.opcode(),
+				address: address + len - 1,
+			});
+		}
+		Ok(address..address + len)
+	}
+
+	/// Executes a single instruction, even if there is a breakpoint at it.
+	/// If the instruction faults, the virtual machine is paused and the fault is
+	/// stored in [`Vm::fault`].
+	pub fn execute(&mut self) -> Result<(), VmError> {
+		if self.paused {
+			return Ok(());
+		}
+		self.breakpoint = None;
+		let result = self.execute_instruction();
+		self.fault_on_error(result)
+	}
+
+	/// Pauses the virtual machine if a breakpoint at the program counter is hit,
+	/// unless it has just paused there.
+	fn at_breakpoint(&mut self) -> bool {
+		let resumed = self.breakpoint.take() == Some(self.program_counter);
+		let hit = self
+			.breakpoints
+			.get(&self.program_counter)
+			.is_some_and(|breakpoint| breakpoint.is_hit(self));
+		if resumed || !hit {
+			return false;
+		}
+		self.breakpoint = Some(self.program_counter);
+		self.paused = true;
+		true
+	}
+
+	/// Executes up to `limit` instructions, a basic block at a time, stopping early
+	/// if the virtual machine pauses or waits for the next frame.
+	/// Faults are handled as in [`Vm::execute`]. Returns the number of instructions executed.
+	pub fn execute_many(&mut self, limit: usize) -> Result<usize, VmError> {
+		let mut executed = 0;
+		while executed < limit && !self.paused && !self.vblank_wait {
+			let result = self.execute_block(limit - executed);
+			executed += self.fault_on_error(result)?;
+		}
+		Ok(executed)
+	}
+
+	/// Pauses the virtual machine and stores the fault if an instruction faulted.
+	fn fault_on_error<T>(&mut self, result: Result<T, VmError>) -> Result<T, VmError> {
+		if let Err(error) = result {
+			self.fault = Some(error);
+			self.paused = true;
+		}
+		result
+	}
+
+	/// Runs a single 60 Hz frame, executing as many instructions as the speed
+	/// allows and then decrementing the timers.
+	pub fn run_frame(&mut self) -> Result<(), VmError> {
+		if self.paused {
+			return Ok(());
+		}
+		self.vblank_wait = false;
+		match self.speed {
+			Speed::InstructionsPerFrame(instructions) => {
+				self.execute_many(instructions as usize)?;
+			}
+			Speed::Unlimited => {
+				let start = Instant::now();
+				while !self.paused && !self.vblank_wait && start.elapsed() < FRAME_TIME {
+					self.execute_many(UNLIMITED_BATCH)?;
+				}
+			}
+			Speed::CosmacVip => {
+				while !self.paused && self.machine_cycles < self.next_interrupt {
+					if self.at_breakpoint() {
+						break;
+					}
+					self.execute()?;
+					// The rest of the frame is spent idle, waiting for the interrupt.
+					if self.vblank_wait {
+						self.machine_cycles = self.next_interrupt;
+					}
+				}
+			}
+		}
+		match self.speed {
+			Speed::CosmacVip => {
+				// Slow instructions can run past several interrupts, each of which
+				// decrements the timers and takes cycles away from the interpreter.
+				while self.machine_cycles >= self.next_interrupt {
+					self.delay_timer = self.delay_timer.saturating_sub(1);
+					self.sound_timer = self.sound_timer.saturating_sub(1);
+					self.machine_cycles += VIP_INTERRUPT_CYCLES;
+					self.next_interrupt += VIP_FRAME_CYCLES;
+				}
+			}
+			_ => {
+				self.delay_timer = self.delay_timer.saturating_sub(1);
+				self.sound_timer = self.sound_timer.saturating_sub(1);
+				self.next_interrupt = self.machine_cycles + VIP_FRAME_CYCLES;
+			}
+		}
+		self.frames += 1;
+		Ok(())
+	}
+
+	fn execute_instruction(&mut self) -> Result<(), VmError> {
+		self.memory_range(self.program_counter as usize, 2)?;
+		let instruction = self
+			.instruction_cache
+			.instruction(&self.memory, self.program_counter, self.mode)
+			.ok_or_else(|| self.invalid_opcode())?;
+		let start = Instant::now();
+		self.execute_decoded(instruction)?;
+		self.record_cycle_time(start.elapsed());
+		Ok(())
+	}
+
+	/// Executes the basic block at the program counter, stopping when control
+	/// leaves the block, memory is written to, the virtual machine pauses or waits
+	/// for the next frame, a breakpoint is reached, or `limit` instructions have
+	/// been executed.
+	fn execute_block(&mut self, limit: usize) -> Result<usize, VmError> {
+		self.memory_range(self.program_counter as usize, 2)?;
+		let block = self
+			.instruction_cache
+			.block(&self.memory, self.program_counter, self.mode)
+			.ok_or_else(|| self.invalid_opcode())?;
+		let start = Instant::now();
+		let mut executed = 0;
+		for instruction in block.instructions.iter().take(limit) {
+			if self.at_breakpoint() {
+				break;
+			}
+			let next = self.program_counter.wrapping_add(instruction.size());
+			self.execute_decoded(*instruction)?;
+			executed += 1;
+			// Self-modifying code may have changed the rest of the block.
+			if self.program_counter != next
+				|| self.paused
+				|| self.vblank_wait
+				|| self.instruction_cache.is_dirty()
+			{
+				break;
+			}
+		}
+		if executed > 0 {
+			self.record_cycle_time(start.elapsed() / executed as u32);
+		}
+		Ok(executed)
+	}
+
+	/// Records how long a cycle took to execute.
+	fn record_cycle_time(&mut self, time: Duration) {
+		self.last_cycle_time = time;
+		self.average_cycle_time = (self.average_cycle_time + self.last_cycle_time) / 2;
+	}
+
+	fn execute_decoded(&mut self, instruction: Instruction) -> Result<(), VmError> {
+		let mut machine_cycles = timing::vip_cycles(self, &instruction);
+		let next_step = match instruction {
+			Instruction::Sys(address) if self.mode == VmMode::CosmacVip => {
+				machine_cycles += rca1802::call_subroutine(self, address)?;
+				ProgramCounter::Next
+			}
+			Instruction::Sys(_) => {
+				return Err(VmError::UnsupportedSys {
+					pc: self.program_counter,
+					opcode: self.opcode(),
+				})
+			}
+			Instruction::Clear => {
+				draw::clear(self);
+				ProgramCounter::Next
+			}
+			Instruction::Return => {
+				let return_address = self.stack.pop().ok_or(VmError::StackUnderflow {
+					pc: self.program_counter,
+					opcode: self.opcode(),
+				})?;
+				ProgramCounter::Jump(return_address.wrapping_add(2))
+			}
+			Instruction::Random(register, value) => {
+				let register = register as usize;
+				assert!(register < self.registers.len());
+				self.registers[register] = self.random.generate(&self.memory) & value;
+				ProgramCounter::Next
+			}
+			Instruction::ScrollDown(rows) => {
+				draw::scroll(self, 0, rows as isize);
+				ProgramCounter::Next
+			}
+			Instruction::ScrollUp(rows) => {
+				draw::scroll(self, 0, -(rows as isize));
+				ProgramCounter::Next
+			}
+			Instruction::ScrollRight => {
+				draw::scroll(self, 4, 0);
+				ProgramCounter::Next
+			}
+			Instruction::ScrollLeft => {
+				draw::scroll(self, -4, 0);
+				ProgramCounter::Next
+			}
+			Instruction::Exit => {
+				self.paused = true;
+				ProgramCounter::Pause
+			}
+			Instruction::Draw(x, y, row) => {
+				draw::draw(self, x, y, row)?;
+				self.vblank_wait = self.quirks.display_wait;
+				ProgramCounter::Next
+			}
+			Instruction::LoadKey(register) => {
+				let register = register as usize;
+				assert!(register < self.registers.len());
+				match self.keypad.first_one() {
+					Some(key) => {
+						self.registers[register] = key as u8;
+						ProgramCounter::Next
+					}
+					None => ProgramCounter::Pause,
+				}
+			}
+			Instruction::AddI(register) => {
+				let register = register as usize;
+				assert!(register < self.registers.len());
+				self.index_register = self
+					.index_register
+					.wrapping_add(self.registers[register] as u16);
+				ProgramCounter::Next
+			}
+			Instruction::SetHighResolution(mode) => {
+				self.set_high_resolution(mode);
+				ProgramCounter::Next
+			}
+			Instruction::SelectPlane(plane) => {
+				self.plane = plane;
+				ProgramCounter::Next
+			}
+			Instruction::Load(load) => {
+				load.execute(self)?;
+				ProgramCounter::Next
+			}
+			Instruction::Branch(branch) => branch.execute(self)?,
+			Instruction::Logical(logic) => {
+				logic.execute(self)?;
+				ProgramCounter::Next
+			}
+			Instruction::Arthimetic(arthimetic) => {
+				arthimetic.execute(self)?;
+				ProgramCounter::Next
+			}
+		};
+		if let ProgramCounter::Skip = next_step {
+			machine_cycles += VIP_SKIP_CYCLES;
+		}
+		next_step.next(self, instruction.size());
+		self.cycles += 1;
+		self.machine_cycles += machine_cycles;
+		Ok(())
+	}
+}
+
+impl Default for Vm {
+	fn default() -> Self {
+		Vm {
+			mode: VmMode::Chip8,
+			quirks: VmMode::Chip8.quirks(),
+			instruction_cache: InstructionCache::new(VmMode::Chip8.memory_size()),
+			memory: vec![0; VmMode::Chip8.memory_size()],
+			program_size: 0,
+			registers: [0; 16],
+			index_register: 0,
+			program_counter: PROGRAM_START as Address,
+			stack: Vec::with_capacity(16),
+			delay_timer: 0,
+			sound_timer: 0,
+			keypad: BitArray::zeroed(),
+			display: Display::default(),
+			plane: 1,
+			audio_pattern: [0; 16],
+			pitch: DEFAULT_PITCH,
+			rpl: [0; 16],
+			random: Random::default(),
+			cpu: Rca1802::default(),
+			high_resolution: false,
+			paused: true,
+			vblank_wait: false,
+			fault: None,
+			breakpoints: BTreeMap::new(),
+			watchpoints: Vec::new(),
+			watchpoint_hits: VecDeque::new(),
+			breakpoint: None,
+			speed: Speed::default(),
+			frames: 0,
+			cycles: 0,
+			machine_cycles: 0,
+			next_interrupt: VIP_FRAME_CYCLES,
+			last_cycle_time: Duration::new(0, 0),
+			average_cycle_time: Duration::new(0, 0),
+		}
+	}
+}
+
+pub enum ProgramCounter {
+	Pause,
+	Next,
+	Skip,
+	Jump(Address),
+}
+
+impl ProgramCounter {
+	/// Moves the program counter past an instruction of `size` bytes.
+	pub fn next(self, vm: &mut Vm, size: u16) {
+		match self {
+			ProgramCounter::Pause => {}
+			ProgramCounter::Next => vm.program_counter = vm.program_counter.wrapping_add(size),
+			ProgramCounter::Skip => {
+				let next = vm.program_counter.wrapping_add(size);
+				// XO-CHIP's F000 NNNN is twice as long as every other instruction.
+				let skipped = if vm.mode == VmMode::XoChip && vm.opcode_at(next) == 0xF000 {
+					4
+				} else {
+					2
+				};
+				vm.program_counter = next.wrapping_add(skipped);
+			}
+			ProgramCounter::Jump(address) => vm.program_counter = address,
+		}
+	}
+}
+
+/// How many instructions the CHIP-8 virtual machine executes in each frame.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Display)]
+pub enum Speed {
+	/// Execute a fixed number of instructions in each frame.
+	#[display(fmt = "{} IPF", _0)]
+	InstructionsPerFrame(u32),
+	/// Execute as many instructions as the host can in each frame.
+	Unlimited,
+	/// Execute instructions for as long as they would take on the COSMAC VIP,
+	/// with the timers driven by its interrupts.
+	#[display(fmt = "VIP Timing")]
+	CosmacVip,
+}
+
+impl Speed {
+	/// Commonly used speeds.
+	pub const PRESETS: [Speed; 7] = [
+		Speed::InstructionsPerFrame(7),
+		Speed::InstructionsPerFrame(15),
+		Speed::InstructionsPerFrame(30),
+		Speed::InstructionsPerFrame(100),
+		Speed::InstructionsPerFrame(1000),
+		Speed::Unlimited,
+		Speed::CosmacVip,
+	];
+}
+
+impl Default for Speed {
+	fn default() -> Self {
+		Speed::InstructionsPerFrame(15)
+	}
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Display)]
+pub enum VmMode {
+	/// Interpert as the original CHIP-8 interperter would.
+	Chip8,
+	/// Interpert as the original CHIP-8 interperter on the COSMAC VIP,
+	/// running 0nnn machine code subroutines on an emulated RCA 1802.
+	#[display(fmt = "COSMAC VIP")]
+	CosmacVip,
+	/// Interpert using CHIP-48.
+	Chip48,
+	/// Interpert using SUPER-CHIP.
+	SuperChip,
+	/// Interpert using XO-CHIP.
+	XoChip,
+}
+
+impl VmMode {
+	/// Every mode.
+	pub const ALL: [VmMode; 5] = [
+		VmMode::Chip8,
+		VmMode::CosmacVip,
+		VmMode::Chip48,
+		VmMode::SuperChip,
+		VmMode::XoChip,
+	];
+
+	/// The quirks of the interpreter.
+	pub fn quirks(self) -> Quirks {
+		match self {
+			VmMode::Chip8 | VmMode::CosmacVip => Quirks::CHIP8,
+			VmMode::Chip48 => Quirks::CHIP48,
+			VmMode::SuperChip => Quirks::SUPER_CHIP,
+			VmMode::XoChip => Quirks::XO_CHIP,
+		}
+	}
+
+	/// The size of the memory of the interpreter.
+	pub fn memory_size(self) -> usize {
+		match self {
+			VmMode::XoChip => 0x10000,
+			_ => 0x1000,
+		}
+	}
+
+	/// Whether the interpreter supports the SUPER-CHIP instructions.
+	pub fn is_super_chip(self) -> bool {
+		matches!(self, VmMode::SuperChip | VmMode::XoChip)
+	}
+}
+
+impl FromStr for VmMode {
+	type Err = String;
+
+	/// Parses a mode from a short name such as `schip`, ignoring case.
+	fn from_str(name: &str) -> Result<Self, Self::Err> {
+		match name.to_ascii_lowercase().as_str() {
+			"chip8" | "chip-8" => Ok(VmMode::Chip8),
+			"vip" | "cosmac-vip" => Ok(VmMode::CosmacVip),
+			"chip48" | "chip-48" => Ok(VmMode::Chip48),
+			"schip" | "superchip" | "super-chip" => Ok(VmMode::SuperChip),
+			"xochip" | "xo-chip" => Ok(VmMode::XoChip),
+			_ => Err(format!("unknown mode `{}`", name)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn returns_wrap_around_memory() {
+		let mut vm = Vm::new();
+		vm.set_mode(VmMode::XoChip);
+		// A call at the end of the 64 KiB of memory returns to 0x0000.
+		vm.memory[0xFFFE..].copy_from_slice(&[0x23, 0x00]);
+		vm.memory[0x300..0x302].copy_from_slice(&[0x00, 0xEE]);
+		vm.program_counter = 0xFFFE;
+		vm.paused = false;
+		vm.execute().unwrap();
+		assert_eq!(vm.program_counter, 0x300);
+		vm.execute().unwrap();
+		assert_eq!(vm.program_counter, 0x0000);
+		assert!(vm.stack.is_empty());
+	}
+}
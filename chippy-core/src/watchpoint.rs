@@ -0,0 +1,261 @@
+use crate::{
+	instruction::{format::Syntax, Address, Instruction},
+	quirks::Quirks,
+	vm::VmMode,
+};
+use std::ops::RangeInclusive;
+
+/// The most accesses to watched memory that are kept, dropping the oldest first.
+pub const MAX_WATCHPOINT_HITS: usize = 256;
+
+/// How memory is accessed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub enum Access {
+	#[display(fmt = "read")]
+	Read,
+	#[display(fmt = "write")]
+	Write,
+	#[display(fmt = "read/write")]
+	ReadWrite,
+}
+
+impl Access {
+	/// Every kind of access.
+	pub const ALL: [Access; 3] = [Access::Read, Access::Write, Access::ReadWrite];
+
+	/// Whether a watchpoint on these accesses reports `access`.
+	pub fn includes(self, access: Access) -> bool {
+		self == Access::ReadWrite || self == access
+	}
+}
+
+/// A watchpoint, which reports the instructions that access a range of memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+	/// The addresses that are watched.
+	pub range: RangeInclusive<Address>,
+	/// The accesses that are reported.
+	pub access: Access,
+	/// Whether the watchpoint reports accesses.
+	pub enabled: bool,
+	/// Whether the virtual machine pauses after an access is reported.
+	pub pause: bool,
+}
+
+impl Watchpoint {
+	/// Creates an enabled watchpoint that doesn't pause the virtual machine.
+	pub fn new(range: RangeInclusive<Address>, access: Access) -> Self {
+		Watchpoint {
+			range,
+			access,
+			enabled: true,
+			pause: false,
+		}
+	}
+}
+
+/// An access to watched memory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchpointHit {
+	/// The address of the instruction that accessed memory.
+	pub program_counter: Address,
+	/// The instruction that accessed memory, if it could be decoded.
+	pub instruction: Option<Instruction>,
+	/// Whether memory was read or written.
+	pub access: Access,
+	/// The first address accessed within the watchpoint.
+	pub address: Address,
+	/// The bytes before the access.
+	pub old: Vec<u8>,
+	/// The bytes after the access, which are the old bytes for reads.
+	pub new: Vec<u8>,
+}
+
+impl WatchpointHit {
+	/// Writes the hit on a line, with the instruction in a syntax.
+	pub fn text(&self, syntax: Syntax, mode: VmMode, quirks: &Quirks) -> String {
+		let instruction = self.instruction.map_or("???".to_string(), |instruction| {
+			syntax.instruction(&instruction, mode, quirks, |_| None)
+		});
+		let mut text = format!(
+			"0x{:03X} {:<16} {:<5} 0x{:03X} {}",
+			self.program_counter,
+			instruction,
+			// The derived Display doesn't pad, so the access is padded as a string.
+			self.access.to_string(),
+			self.address,
+			hex(&self.old)
+		);
+		if self.access == Access::Write {
+			text += &format!(" -> {}", hex(&self.new));
+		}
+		text
+	}
+}
+
+/// Writes bytes in hexadecimal, separated by spaces.
+fn hex(bytes: &[u8]) -> String {
+	bytes
+		.iter()
+		.map(|byte| format!("{:02X}", byte))
+		.collect::<Vec<_>>()
+		.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::vm::Vm;
+
+	/// Stores V0 to V3 at 0x300, reads them back, draws a row from 0x302 and stores their BCD.
+	const PROGRAM: [u8; 22] = [
+		0x60, 0x11, 0x61, 0x22, 0x62, 0x33, 0x63, 0xFF, // LD V0-V3
+		0xA3, 0x00, 0xF3, 0x55, // LD I, 0x300; LD [I], V3
+		0xA3, 0x00, 0xF3, 0x65, // LD I, 0x300; LD V3, [I]
+		0xA3, 0x02, 0xD0, 0x01, // LD I, 0x302; DRW V0, V0, 1
+		0xF3, 0x33, // LD B, V3
+	];
+
+	/// Runs the program with watchpoints, returning the virtual machine.
+	fn run(watchpoints: Vec<Watchpoint>, instructions: usize) -> Vm {
+		let mut vm = Vm::new();
+		vm.load_program(&PROGRAM).unwrap();
+		vm.watchpoints = watchpoints;
+		for _ in 0..instructions {
+			vm.execute().unwrap();
+		}
+		vm
+	}
+
+	/// An access by the instruction at an address in the program.
+	fn hit(
+		program_counter: Address,
+		access: Access,
+		address: Address,
+		old: &[u8],
+		new: &[u8],
+	) -> WatchpointHit {
+		let offset = program_counter as usize - 0x200;
+		WatchpointHit {
+			program_counter,
+			instruction: Instruction::decode(&PROGRAM, offset, VmMode::Chip8),
+			access,
+			address,
+			old: old.to_vec(),
+			new: new.to_vec(),
+		}
+	}
+
+	#[test]
+	fn access_includes() {
+		assert!(Access::Read.includes(Access::Read));
+		assert!(!Access::Read.includes(Access::Write));
+		assert!(Access::Write.includes(Access::Write));
+		assert!(!Access::Write.includes(Access::Read));
+		assert!(Access::ReadWrite.includes(Access::Read));
+		assert!(Access::ReadWrite.includes(Access::Write));
+	}
+
+	#[test]
+	fn reports_accesses_within_the_range() {
+		let vm = run(vec![Watchpoint::new(0x301..=0x302, Access::ReadWrite)], 11);
+		let hits = vm.watchpoint_hits.iter().cloned().collect::<Vec<_>>();
+		assert_eq!(
+			hits,
+			[
+				hit(0x20A, Access::Write, 0x301, &[0, 0], &[0x22, 0x33]),
+				hit(0x20E, Access::Read, 0x301, &[0x22, 0x33], &[0x22, 0x33]),
+				hit(0x212, Access::Read, 0x302, &[0x33], &[0x33]),
+				hit(0x214, Access::Write, 0x302, &[0x33], &[0x02]),
+			]
+		);
+		assert!(!vm.paused);
+	}
+
+	#[test]
+	fn ignores_other_accesses() {
+		let reads = run(vec![Watchpoint::new(0x300..=0x303, Access::Read)], 11);
+		assert!(reads
+			.watchpoint_hits
+			.iter()
+			.all(|hit| hit.access == Access::Read));
+		assert_eq!(reads.watchpoint_hits.len(), 2);
+
+		let writes = run(vec![Watchpoint::new(0x300..=0x300, Access::Write)], 11);
+		assert_eq!(writes.watchpoint_hits.len(), 1);
+
+		let outside = run(vec![Watchpoint::new(0x305..=0x3FF, Access::ReadWrite)], 11);
+		assert!(outside.watchpoint_hits.is_empty());
+
+		let disabled = Watchpoint {
+			enabled: false,
+			..Watchpoint::new(0x300..=0x303, Access::ReadWrite)
+		};
+		assert!(run(vec![disabled], 11).watchpoint_hits.is_empty());
+	}
+
+	#[test]
+	fn each_watchpoint_reports_an_access() {
+		let vm = run(
+			vec![
+				Watchpoint::new(0x300..=0x300, Access::Write),
+				Watchpoint::new(0x303..=0x303, Access::Write),
+			],
+			6,
+		);
+		let addresses = vm
+			.watchpoint_hits
+			.iter()
+			.map(|hit| (hit.address, hit.new.clone()))
+			.collect::<Vec<_>>();
+		assert_eq!(addresses, [(0x300, vec![0x11]), (0x303, vec![0xFF])]);
+	}
+
+	#[test]
+	fn pausing_watchpoints_stop_after_the_access() {
+		let watchpoint = Watchpoint {
+			pause: true,
+			..Watchpoint::new(0x300..=0x303, Access::Read)
+		};
+		let mut vm = run(vec![watchpoint], 0);
+		while !vm.paused {
+			vm.execute().unwrap();
+		}
+		assert_eq!(vm.program_counter, 0x210);
+		assert_eq!(vm.watchpoint_hits.len(), 1);
+	}
+
+	#[test]
+	fn keeps_the_latest_hits() {
+		let mut vm = run(vec![Watchpoint::new(0x300..=0x303, Access::Write)], 4);
+		for _ in 0..MAX_WATCHPOINT_HITS + 2 {
+			vm.program_counter = 0x208;
+			vm.execute().unwrap();
+			vm.execute().unwrap();
+		}
+		assert_eq!(vm.watchpoint_hits.len(), MAX_WATCHPOINT_HITS);
+		assert_eq!(vm.watchpoint_hits[0].old, [0x11, 0x22, 0x33, 0xFF]);
+	}
+
+	#[test]
+	fn text() {
+		let write = hit(0x20A, Access::Write, 0x301, &[0, 0], &[0x22, 0x33]);
+		assert_eq!(
+			write.text(Syntax::Cowgod, VmMode::Chip8, &Quirks::CHIP8),
+			"0x20A LD [I], V3       write 0x301 00 00 -> 22 33"
+		);
+		let read = hit(0x20E, Access::Read, 0x301, &[0x22], &[0x22]);
+		assert_eq!(
+			read.text(Syntax::Octo, VmMode::Chip8, &Quirks::CHIP8),
+			"0x20E load v3          read  0x301 22"
+		);
+		let unknown = WatchpointHit {
+			instruction: None,
+			..read
+		};
+		assert_eq!(
+			unknown.text(Syntax::Cowgod, VmMode::Chip8, &Quirks::CHIP8),
+			"0x20E ???              read  0x301 22"
+		);
+	}
+}
@@ -0,0 +1,617 @@
+//! Golden-image tests for the instruction set.
+//!
+//! Each test assembles a small program, runs it in every mode that supports it, each
+//! with that interpreter's quirks, and compares the display, registers, VF and I
+//! against its expectation in `tests/golden/`. The `quirk_` tests run a program that
+//! depends on one quirk, with it toggled on its own. After an intended change in behavior,
+//! record new expectations with:
+//!
+//! ```text
+//! GOLDEN_RECORD=1 cargo test -p chippy-core --test golden
+//! ```
+
+use chippy_core::{
+	assembler,
+	quirks::{IndexIncrement, Quirks},
+	vm::Vm,
+	vm::VmMode,
+};
+use std::{env, fs, iter, path::PathBuf};
+
+/// The environment variable that records expectations instead of checking them.
+const RECORD: &str = "GOLDEN_RECORD";
+/// How many frames each program runs for.
+const FRAMES: usize = 30;
+/// The seed of the random number generator, so that Cxkk is reproducible.
+const SEED: u64 = 0x8BADF00D;
+
+const ALL_MODES: &[VmMode] = &[
+	VmMode::Chip8,
+	VmMode::CosmacVip,
+	VmMode::Chip48,
+	VmMode::SuperChip,
+	VmMode::XoChip,
+];
+const SUPER_CHIP_MODES: &[VmMode] = &[VmMode::SuperChip, VmMode::XoChip];
+const XO_CHIP_MODES: &[VmMode] = &[VmMode::XoChip];
+
+/// Runs a program for [`FRAMES`] frames and describes the state it ends in.
+fn snapshot(source: &str, mode: VmMode, quirks: Quirks, label: &str) -> String {
+	let program = assembler::assemble(source, mode).unwrap_or_else(|errors| {
+		let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+		panic!(
+			"failed to assemble in {} mode:\n{}",
+			mode,
+			errors.join("\n")
+		)
+	});
+	let mut vm = Vm::new();
+	vm.set_mode(mode);
+	vm.quirks = quirks;
+	vm.set_seed(SEED);
+	vm.load_program(&program).unwrap();
+	for _ in 0..FRAMES {
+		if vm.run_frame().is_err() {
+			break;
+		}
+	}
+
+	let mut snapshot = format!(
+		"== {}\nPC 0x{:03X}  I 0x{:03X}  VF 0x{:02X}  DT {}\n",
+		label, vm.program_counter, vm.index_register, vm.registers[0xF], vm.delay_timer
+	);
+	for (index, value) in vm.registers.iter().enumerate() {
+		snapshot += &format!("V{:X} 0x{:02X}", index, value);
+		snapshot.push(if index % 8 == 7 { '\n' } else { ' ' });
+	}
+	if let Some(fault) = &vm.fault {
+		snapshot += &format!("Fault: {}\n", fault);
+	}
+	snapshot + &vm.display.to_ascii()
+}
+
+/// Checks a program against its expectation in every mode, or records it.
+fn check(name: &str, source: &str, modes: &[VmMode]) {
+	let actual = modes
+		.iter()
+		.map(|mode| snapshot(source, *mode, mode.quirks(), &mode.to_string()))
+		.collect::<Vec<_>>()
+		.join("\n");
+	compare(name, &actual);
+}
+
+/// Checks a program in one mode with its quirks, then with each labelled variant of them.
+fn check_quirks(name: &str, source: &str, mode: VmMode, variants: &[(&str, Quirks)]) {
+	let preset = snapshot(source, mode, mode.quirks(), &mode.to_string());
+	let actual = iter::once(preset)
+		.chain(variants.iter().map(|(label, quirks)| {
+			snapshot(source, mode, *quirks, &format!("{} with {}", mode, label))
+		}))
+		.collect::<Vec<_>>()
+		.join("\n");
+	compare(name, &actual);
+}
+
+/// Compares snapshots against the expectation called `name`, or records them.
+fn compare(name: &str, actual: &str) {
+	let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+		.join("tests/golden")
+		.join(name)
+		.with_extension("txt");
+	if env::var_os(RECORD).is_some() {
+		fs::create_dir_all(path.parent().unwrap()).unwrap();
+		fs::write(&path, actual).unwrap();
+		return;
+	}
+	let expected = fs::read_to_string(&path).unwrap_or_else(|error| {
+		panic!("{}: {}, record it with {}=1", path.display(), error, RECORD)
+	});
+	// Comparing line by line points at the first difference, rather than printing both in full.
+	let mismatch = expected
+		.lines()
+		.zip(actual.lines())
+		.position(|(expected, actual)| expected != actual);
+	if let Some(line) = mismatch {
+		panic!(
+			"{}:{} differs, record it with {}=1 if the change is intended\nexpected: {}\n  actual: {}\n\n{}",
+			path.display(),
+			line + 1,
+			RECORD,
+			expected.lines().nth(line).unwrap(),
+			actual.lines().nth(line).unwrap(),
+			actual
+		);
+	}
+	assert_eq!(
+		expected.lines().count(),
+		actual.lines().count(),
+		"{} has a different number of lines",
+		path.display()
+	);
+}
+
+#[test]
+fn load_store() {
+	// I ends up after the stored registers, depending on the increment quirk,
+	// and the first sprite is drawn from there.
+	let source = r#"
+		LD V0, 0x81
+		LD V1, 0x42
+		LD V2, 0x24
+		LD V3, 0x18
+		LD I, buffer
+		LD [I], V3
+		LD V6, 0
+		DRW V6, V6, 4
+		LD I, buffer
+		LD V7, 8
+		DRW V7, V6, 4
+		LD I, values
+		LD V2, [I]
+	end:
+		JP end
+	buffer:
+		DB 0, 0, 0, 0, 0xF0, 0xF0, 0xF0, 0xF0
+	values:
+		DB 0xAA, 0xBB, 0xCC
+	"#;
+	check("load_store", source, ALL_MODES);
+}
+
+#[test]
+fn rpl() {
+	// V7 and V6 are copied to V8 and V9 after restoring all eight flags,
+	// then only V0 to V3 are restored.
+	let source = r#"
+		LD V0, 1
+		LD V1, 2
+		LD V2, 3
+		LD V3, 4
+		LD V4, 5
+		LD V5, 6
+		LD V6, 7
+		LD V7, 8
+		LD R, V7
+		LD I, zeros
+		LD V7, [I]
+		LD V7, R
+		LD V8, V7
+		LD V9, V6
+		LD I, zeros
+		LD V7, [I]
+		LD V3, R
+	end:
+		JP end
+	zeros:
+		DB 0, 0, 0, 0, 0, 0, 0, 0
+	"#;
+	check("rpl", source, SUPER_CHIP_MODES);
+}
+
+#[test]
+fn arithmetic() {
+	// The flag of each operation is copied into VA to VD.
+	let source = r#"
+		LD V0, 0xF0
+		LD V1, 0x20
+		ADD V0, V1
+		LD VA, VF
+		LD V2, 0x10
+		LD V3, 0x20
+		SUB V2, V3
+		LD VB, VF
+		LD V4, 0x10
+		LD V5, 0x20
+		SUBN V4, V5
+		LD VC, VF
+		LD V6, 0x81
+		LD V7, 0x03
+		SHR V6, V7
+		LD VD, VF
+		LD V8, 0x01
+		LD V9, 0x80
+		SHL V8, V9
+		LD VE, VF
+		ADD V9, 0xFF
+		LD VF, 0x80
+		ADD VF, VF
+	end:
+		JP end
+	"#;
+	check("arithmetic", source, ALL_MODES);
+}
+
+#[test]
+fn logic() {
+	// VF is set before each operation and copied into VA to VC after it.
+	let source = r#"
+		LD V0, 0x0F
+		LD V1, 0xF0
+		LD VF, 5
+		OR V0, V1
+		LD VA, VF
+		LD V2, 0x3C
+		LD V3, 0x0F
+		LD VF, 5
+		AND V2, V3
+		LD VB, VF
+		LD V4, 0x3C
+		LD V5, 0x0F
+		LD VF, 5
+		XOR V4, V5
+		LD VC, VF
+	end:
+		JP end
+	"#;
+	check("logic", source, ALL_MODES);
+}
+
+#[test]
+fn draw() {
+	// Two overlapping digits collide, one is drawn past the bottom right corner,
+	// and one starts off the screen.
+	let source = r#"
+		LD V0, 0xA
+		LD F, V0
+		LD V1, 1
+		LD V2, 1
+		DRW V1, V2, 5
+		LD VA, VF
+		LD V1, 3
+		DRW V1, V2, 5
+		LD VB, VF
+		LD V0, 7
+		LD F, V0
+		LD V1, 60
+		LD V2, 29
+		DRW V1, V2, 5
+		LD VC, VF
+		LD V3, 70
+		LD V4, 40
+		DRW V3, V4, 5
+	end:
+		JP end
+	"#;
+	check("draw", source, ALL_MODES);
+}
+
+#[test]
+fn bcd() {
+	// The digits of 237 are stored, loaded back into V0 to V2 and drawn.
+	let source = r#"
+		LD V0, 237
+		LD I, digits
+		LD B, V0
+		LD V2, [I]
+		LD V3, 0
+		LD V4, 0
+		LD F, V0
+		DRW V3, V4, 5
+		LD V3, 5
+		LD F, V1
+		DRW V3, V4, 5
+		LD V3, 10
+		LD F, V2
+		DRW V3, V4, 5
+	end:
+		JP end
+	digits:
+		DB 0, 0, 0
+	"#;
+	check("bcd", source, ALL_MODES);
+}
+
+#[test]
+fn branches() {
+	// V5 counts the taken paths, and V6 records which entry of the table was jumped to,
+	// which depends on whether Bnnn adds V0 or V2.
+	let source = r#"
+		LD V0, 2
+		LD V2, 4
+		LD V3, 2
+		SE V0, 2
+		ADD V5, 0x10
+		SNE V0, 2
+		ADD V5, 1
+		SE V0, V3
+		ADD V5, 0x10
+		SNE V0, V3
+		ADD V5, 1
+		CALL increment
+		CALL increment
+		JP V0, table
+	table:
+		JP first
+		JP second
+		JP third
+	first:
+		LD V6, 1
+		JP end
+	second:
+		LD V6, 2
+		JP end
+	third:
+		LD V6, 3
+	end:
+		JP end
+	increment:
+		ADD V5, 0x10
+		RET
+	"#;
+	check("branches", source, ALL_MODES);
+}
+
+#[test]
+fn random() {
+	let source = r#"
+		RND V0, 0xFF
+		RND V1, 0xFF
+		RND V2, 0x0F
+		RND V3, 0x00
+	end:
+		JP end
+	"#;
+	check("random", source, ALL_MODES);
+}
+
+#[test]
+fn timers() {
+	// V1 is the delay timer after it has counted down for most of the run.
+	let source = r#"
+		LD V0, 40
+		LD DT, V0
+		LD ST, V0
+	end:
+		LD V1, DT
+		JP end
+	"#;
+	check("timers", source, ALL_MODES);
+}
+
+#[test]
+fn super_chip() {
+	// A big digit and a 16x16 sprite are drawn in high resolution, then scrolled.
+	let source = r#"
+		HIGH
+		LD V0, 8
+		LD HF, V0
+		LD V1, 2
+		LD V2, 2
+		DRW V1, V2, 10
+		LD I, square
+		LD V1, 20
+		DRW V1, V2, 0
+		LD VA, VF
+		DRW V1, V2, 0
+		LD VB, VF
+		DRW V1, V2, 0
+		SCD 4
+		SCR
+	end:
+		JP end
+	square:
+		DW 0xFFFF, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001
+		DW 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0xFFFF
+	"#;
+	check("super_chip", source, SUPER_CHIP_MODES);
+}
+
+#[test]
+fn xo_chip() {
+	// The skip has to step over the whole 4-byte F000 NNNN for V7 to be set.
+	let source = r#"
+		LD V0, 0
+		SE V0, 0
+		LD I, LONG 0x1234
+		LD V7, 1
+		LD I, LONG values
+		LD V2-V5, [I]
+		LD I, LONG copy
+		LD [I], V5-V2
+		LD V8-VB, [I]
+		PLANE 3
+		LD I, planes
+		LD V0, 4
+		LD V1, 4
+		DRW V0, V1, 4
+	end:
+		JP end
+	values:
+		DB 1, 2, 3, 4
+	copy:
+		DB 0, 0, 0, 0
+	planes:
+		DB 0xF0, 0xF0, 0xF0, 0xF0, 0xFF, 0xFF, 0x00, 0x00
+	"#;
+	check("xo_chip", source, XO_CHIP_MODES);
+}
+
+#[test]
+fn quirk_shift_vy() {
+	// V0 is shifted right and V2 left, from V1 and V3 only if Vy is shifted.
+	let source = r#"
+		LD V0, 0x10
+		LD V1, 0x81
+		SHR V0, V1
+		LD VA, VF
+		LD V2, 0x01
+		LD V3, 0x80
+		SHL V2, V3
+		LD VB, VF
+	end:
+		JP end
+	"#;
+	let quirks = Quirks {
+		shift_vy: false,
+		..Quirks::CHIP8
+	};
+	check_quirks(
+		"quirk_shift_vy",
+		source,
+		VmMode::Chip8,
+		&[("Vx shifted in place", quirks)],
+	);
+}
+
+#[test]
+fn quirk_index_increment() {
+	// I ends up after the stored registers, at the last of them, or where it started.
+	let source = r#"
+		LD V0, 1
+		LD V1, 2
+		LD V2, 3
+		LD I, buffer
+		LD [I], V2
+		LD V3, [I]
+	end:
+		JP end
+	buffer:
+		DB 0, 0, 0, 0xAA, 0xBB
+	"#;
+	let variants = [
+		(
+			"I incremented by X",
+			Quirks {
+				index_increment: IndexIncrement::X,
+				..Quirks::CHIP8
+			},
+		),
+		(
+			"I unchanged",
+			Quirks {
+				index_increment: IndexIncrement::Unchanged,
+				..Quirks::CHIP8
+			},
+		),
+	];
+	check_quirks("quirk_index_increment", source, VmMode::Chip8, &variants);
+}
+
+#[test]
+fn quirk_jump_vx() {
+	// B202 lands on the first entry with V0, or two entries on with V2.
+	let source = r#"
+		LD V0, 0
+		LD V2, 4
+		JP V0, table
+	table:
+		LD V6, 1
+		JP end
+		LD V6, 2
+		JP end
+		LD V6, 3
+	end:
+		JP end
+	"#;
+	let quirks = Quirks {
+		jump_vx: true,
+		..Quirks::CHIP8
+	};
+	check_quirks(
+		"quirk_jump_vx",
+		source,
+		VmMode::Chip8,
+		&[("Bxnn adding Vx", quirks)],
+	);
+}
+
+#[test]
+fn quirk_clip_sprites() {
+	// A digit drawn past the bottom right corner is either cut off or wraps around.
+	let source = r#"
+		LD V0, 8
+		LD F, V0
+		LD V1, 61
+		LD V2, 29
+		DRW V1, V2, 5
+	end:
+		JP end
+	"#;
+	let quirks = Quirks {
+		clip_sprites: false,
+		..Quirks::CHIP8
+	};
+	check_quirks(
+		"quirk_clip_sprites",
+		source,
+		VmMode::Chip8,
+		&[("wrapping sprites", quirks)],
+	);
+}
+
+#[test]
+fn quirk_logic_reset_vf() {
+	// VF is set before the OR and copied into VA after it.
+	let source = r#"
+		LD V0, 0x0F
+		LD V1, 0xF0
+		LD VF, 5
+		OR V0, V1
+		LD VA, VF
+	end:
+		JP end
+	"#;
+	let quirks = Quirks {
+		logic_reset_vf: false,
+		..Quirks::CHIP8
+	};
+	check_quirks(
+		"quirk_logic_reset_vf",
+		source,
+		VmMode::Chip8,
+		&[("VF kept", quirks)],
+	);
+}
+
+#[test]
+fn quirk_display_wait() {
+	// V6 is the delay timer after 20 draws, which take a frame each with the wait.
+	let source = r#"
+		LD V0, 60
+		LD DT, V0
+	loop:
+		DRW V1, V1, 1
+		ADD V5, 1
+		SE V5, 20
+		JP loop
+		LD V6, DT
+	end:
+		JP end
+	"#;
+	let quirks = Quirks {
+		display_wait: false,
+		..Quirks::CHIP8
+	};
+	check_quirks(
+		"quirk_display_wait",
+		source,
+		VmMode::Chip8,
+		&[("no display wait", quirks)],
+	);
+}
+
+#[test]
+fn quirk_lores_16x16() {
+	// Dxy0 in low resolution draws the first 16 bytes of the square as 8x16, or all of it.
+	let source = r#"
+		LD I, square
+		LD V1, 4
+		LD V2, 4
+		DRW V1, V2, 0
+	end:
+		JP end
+	square:
+		DW 0xFFFF, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001
+		DW 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0x8001, 0xFFFF
+	"#;
+	let quirks = Quirks {
+		lores_16x16: true,
+		..Quirks::SUPER_CHIP
+	};
+	check_quirks(
+		"quirk_lores_16x16",
+		source,
+		VmMode::SuperChip,
+		&[("16x16 sprites in low resolution", quirks)],
+	);
+}
@@ -1,102 +1,392 @@
-use crate::vm::Vm;
+use chippy_core::{
+	breakpoint::{Breakpoint, Condition},
+	disassembler::Disassembly,
+	instruction::{format::Syntax, Address, Instruction},
+	vm::{Vm, VmMode, PROGRAM_START},
+	watchpoint::{Access, Watchpoint},
+};
 use egui::{
 	containers::{Frame, ScrollArea},
-	Label, Window,
+	Color32, CtxRef, Label, Sense, Window,
 };
+use std::collections::BTreeMap;
+
+/// The background of the instruction the virtual machine is paused at.
+const PAUSED_COLOR: Color32 = Color32::from_rgb(90, 70, 0);
+
+/// A row of the disassembly, with the address and label on the first row of each line.
+type Row = (Option<Address>, Option<String>, String);
+
+/// The disassembly as it was last shown, which is only redone when it would change.
+struct DisassemblyCache {
+	/// The memory the program was disassembled from.
+	memory: Vec<u8>,
+	mode: VmMode,
+	syntax: Syntax,
+	rows: Vec<Row>,
+}
+
+impl DisassemblyCache {
+	fn new(vm: &Vm, syntax: Syntax) -> Self {
+		// Trailing zeros are left out, as they're almost always unused memory.
+		let program = &vm.memory[PROGRAM_START..];
+		let length = program
+			.iter()
+			.rposition(|byte| *byte != 0)
+			.map_or(0, |last| last + 1);
+		let disassembly = Disassembly::new(&program[..length], vm.mode, vm.quirks);
+		let rows = disassembly
+			.lines
+			.iter()
+			.flat_map(|line| {
+				let label = disassembly.label(line.address);
+				disassembly
+					.text(line, syntax)
+					.lines()
+					.enumerate()
+					.map(|(index, text)| {
+						// Only the first row of a sprite has an address and label.
+						let first = index == 0;
+						(
+							first.then_some(line.address as Address),
+							label.filter(|_| first).map(str::to_string),
+							text.to_string(),
+						)
+					})
+					.collect::<Vec<_>>()
+			})
+			.collect::<Vec<_>>();
+		DisassemblyCache {
+			memory: vm.memory.clone(),
+			mode: vm.mode,
+			syntax,
+			rows,
+		}
+	}
+
+	fn is_current(&self, vm: &Vm, syntax: Syntax) -> bool {
+		self.mode == vm.mode && self.syntax == syntax && self.memory == vm.memory
+	}
+}
+
+/// The debugger window and what it remembers between frames.
+pub struct Debugger {
+	/// Whether the window is shown.
+	pub open: bool,
+	/// The syntax of the disassembly.
+	pub syntax: Syntax,
+	/// The address typed in to add a breakpoint at.
+	address: String,
+	/// The conditions of breakpoints as they're typed, which may not parse yet.
+	conditions: BTreeMap<Address, String>,
+	/// The first address typed in to add a watchpoint on.
+	watch_start: String,
+	/// The last address typed in to add a watchpoint on, which is the first if it's empty.
+	watch_end: String,
+	/// The accesses that the added watchpoint reports.
+	watch_access: Access,
+	/// The last disassembly of the program.
+	disassembly: Option<DisassemblyCache>,
+}
+
+impl Default for Debugger {
+	fn default() -> Self {
+		Debugger {
+			open: false,
+			syntax: Syntax::Cowgod,
+			address: String::new(),
+			conditions: BTreeMap::new(),
+			watch_start: String::new(),
+			watch_end: String::new(),
+			watch_access: Access::Write,
+			disassembly: None,
+		}
+	}
+}
+
+/// Parses a hexadecimal address, with or without a leading 0x.
+fn parse_address(address: &str) -> Option<Address> {
+	Address::from_str_radix(address.trim().trim_start_matches("0x").trim(), 16).ok()
+}
+
+/// Sets a breakpoint at an address, or clears the one that's there.
+fn toggle_breakpoint(vm: &mut Vm, address: Address) {
+	if vm.breakpoints.remove(&address).is_none() {
+		vm.breakpoints.insert(address, Breakpoint::new());
+	}
+}
 
-pub fn debugger(vm: &mut Vm) {
-	egui_macroquad::ui(|egui_ctx| {
+impl Debugger {
+	pub fn draw(&mut self, ctx: &CtxRef, vm: &mut Vm) {
 		let mut frame = Frame::default();
 		frame.fill[3] = 0xF0;
-		Window::new("Debugger").frame(frame).show(egui_ctx, |ui| {
-			if vm.paused && ui.button("Unpause").clicked() {
-				vm.paused = false;
-			} else if !vm.paused && ui.button("Pause").clicked() {
-				vm.paused = true;
-			}
-			if ui.button("Single Step").clicked() {
-				vm.execute();
-			}
-			if ui.button("Step Over").clicked() {
-				vm.program_counter += 2;
-			}
-			ui.label(format!(
-				"Cached Instructions: {}",
-				vm.instruction_cache.len()
-			));
-			ui.label(format!("Cycle {}", vm.cycles));
-			ui.label(format!("Last cycle took {:?}", vm.last_cycle_time));
-			ui.label(format!("Average cycle time: {:?}", vm.average_cycle_time));
-			ui.collapsing("Registers", |ui| {
-				for v in 0x0..=0xF {
+		let mut open = self.open;
+		Window::new("Debugger")
+			.frame(frame)
+			.open(&mut open)
+			.show(ctx, |ui| {
+				if vm.paused && ui.button("Unpause").clicked() {
+					vm.paused = false;
+					vm.fault = None;
+				} else if !vm.paused && ui.button("Pause").clicked() {
+					vm.paused = true;
+				}
+				if ui.button("Single Step").clicked() {
+					let paused = vm.paused;
+					vm.paused = false;
+					let _ = vm.execute();
+					vm.paused |= paused;
+				}
+				if ui.button("Step Over").clicked() {
+					let size =
+						Instruction::decode(&vm.memory, vm.program_counter as usize, vm.mode)
+							.map_or(2, |instruction| instruction.size());
+					vm.program_counter = vm.program_counter.wrapping_add(size);
+				}
+				if let Some(address) = vm.breakpoint.filter(|_| vm.paused) {
+					ui.colored_label(
+						Color32::YELLOW,
+						format!("Paused at breakpoint 0x{:03X}", address),
+					);
+				}
+				ui.label(format!(
+					"Cached Instructions: {} in {} blocks",
+					vm.instruction_cache.len(),
+					vm.instruction_cache.block_count()
+				));
+				if let Some(fault) = vm.fault {
+					ui.colored_label(Color32::RED, fault.to_string());
+				}
+				ui.label(format!("Cycle {}", vm.cycles));
+				ui.label(format!("Last cycle took {:?}", vm.last_cycle_time));
+				ui.label(format!("Average cycle time: {:?}", vm.average_cycle_time));
+				ui.collapsing("Registers", |ui| {
+					for v in 0x0..=0xF {
+						ui.horizontal(|ui| {
+							ui.add(Label::new(format!("V{:X}", v)).strong().monospace());
+							if vm.paused {
+								let mut x = format!("0x{:X}", vm.registers[v as usize]);
+								ui.text_edit_singleline(&mut x);
+								vm.registers[v as usize] = u8::from_str_radix(
+									x.trim().trim_start_matches("0x").trim(),
+									16,
+								)
+								.unwrap_or(vm.registers[v as usize]);
+							} else {
+								ui.add(
+									Label::new(format!("0x{:X}", vm.registers[v as usize]))
+										.monospace(),
+								);
+							}
+						});
+					}
 					ui.horizontal(|ui| {
-						ui.add(Label::new(format!("V{:X}", v)).strong().monospace());
+						ui.add(Label::new("I ").strong().monospace());
 						if vm.paused {
-							let mut x = format!("0x{:X}", vm.registers[v as usize]);
+							let mut x = format!("0x{:X}", vm.index_register);
 							ui.text_edit_singleline(&mut x);
-							vm.registers[v as usize] =
-								u8::from_str_radix(x.trim().trim_start_matches("0x").trim(), 16)
-									.unwrap_or(vm.registers[v as usize]);
+							vm.index_register =
+								u16::from_str_radix(x.trim().trim_start_matches("0x").trim(), 16)
+									.unwrap_or(vm.index_register);
 						} else {
-							ui.add(
-								Label::new(format!("0x{:X}", vm.registers[v as usize])).monospace(),
-							);
+							ui.add(Label::new(format!("0x{:X}", vm.index_register)).monospace());
+						}
+					});
+					ui.horizontal(|ui| {
+						ui.add(Label::new("PC").strong().monospace());
+						if vm.paused {
+							let mut x = format!("0x{:X}", vm.program_counter);
+							ui.text_edit_singleline(&mut x);
+							vm.program_counter =
+								u16::from_str_radix(x.trim().trim_start_matches("0x").trim(), 16)
+									.unwrap_or(vm.program_counter);
+						} else {
+							ui.add(Label::new(format!("0x{:X}", vm.program_counter)).monospace());
 						}
 					});
-				}
-				ui.horizontal(|ui| {
-					ui.add(Label::new("I ").strong().monospace());
-					if vm.paused {
-						let mut x = format!("0x{:X}", vm.index_register);
-						ui.text_edit_singleline(&mut x);
-						vm.index_register =
-							u16::from_str_radix(x.trim().trim_start_matches("0x").trim(), 16)
-								.unwrap_or(vm.index_register);
-					} else {
-						ui.add(Label::new(format!("0x{:X}", vm.index_register)).monospace());
-					}
 				});
-				ui.horizontal(|ui| {
-					ui.add(Label::new("PC").strong().monospace());
-					if vm.paused {
-						let mut x = format!("0x{:X}", vm.program_counter);
-						ui.text_edit_singleline(&mut x);
-						vm.program_counter =
-							u16::from_str_radix(x.trim().trim_start_matches("0x").trim(), 16)
-								.unwrap_or(vm.program_counter);
-					} else {
-						ui.add(Label::new(format!("0x{:X}", vm.program_counter)).monospace());
+				ui.collapsing("Stack", |ui| {
+					for (idx, address) in vm.stack.iter().enumerate() {
+						ui.horizontal(|ui| {
+							ui.add(Label::new(format!("{:0<2} ", idx)).monospace());
+							ui.add(Label::new(format!("0x{:X}", address)).monospace());
+						});
 					}
 				});
+				ui.collapsing("Breakpoints", |ui| self.breakpoints(ui, vm));
+				ui.collapsing("Watchpoints", |ui| self.watchpoints(ui, vm));
+				ui.collapsing("Instructions", |ui| self.instructions(ui, vm));
 			});
-			ui.collapsing("Stack", |ui| {
-				for (idx, address) in vm.stack.iter().enumerate() {
-					ui.horizontal(|ui| {
-						ui.add(Label::new(format!("{:0<2} ", idx)).monospace());
-						ui.add(Label::new(format!("0x{:X}", address)).monospace());
-					});
+		self.open = open;
+	}
+
+	/// Lists the breakpoints, with their toggles and conditions.
+	fn breakpoints(&mut self, ui: &mut egui::Ui, vm: &mut Vm) {
+		ui.horizontal(|ui| {
+			ui.text_edit_singleline(&mut self.address);
+			if ui.button("Add").clicked() {
+				if let Some(address) = parse_address(&self.address) {
+					vm.breakpoints.entry(address).or_default();
+					self.address.clear();
 				}
+			}
+		});
+		// Conditions of breakpoints removed elsewhere, like from the instructions, are forgotten.
+		self.conditions
+			.retain(|address, _| vm.breakpoints.contains_key(address));
+		let mut removed = None;
+		for (address, breakpoint) in vm.breakpoints.iter_mut() {
+			let text = self.conditions.entry(*address).or_insert_with(|| {
+				breakpoint
+					.condition
+					.as_ref()
+					.map_or(String::new(), ToString::to_string)
 			});
-			ui.collapsing("Instructions", |ui| {
-				let row_height = ui.fonts()[egui::TextStyle::Body].row_height();
-				let range = (0x200 / 2)..(vm.memory.len() / 2);
-				ScrollArea::auto_sized().show_rows(ui, row_height, range.len(), |ui, row_range| {
-					for row in row_range {
-						let address = (0x200 + (row * 2)) as u16;
-						match vm.instruction_cache.get(&address) {
-							Some(instruction) => ui.horizontal(|ui| {
-								ui.add(Label::new(format!("0x{:03X} ", address)).monospace());
-								ui.add(Label::new(instruction.to_string()).monospace());
-							}),
-							None => ui.horizontal(|ui| {
-								ui.add(Label::new(format!("0x{:03X} ", address)).monospace());
-								ui.add(Label::new("???").monospace());
-							}),
-						};
+			ui.horizontal(|ui| {
+				ui.checkbox(&mut breakpoint.enabled, format!("0x{:03X}", address));
+				if ui.text_edit_singleline(text).changed() {
+					// The last condition that parsed is kept while another is typed.
+					if text.trim().is_empty() {
+						breakpoint.condition = None;
+					} else if let Ok(condition) = text.parse() {
+						breakpoint.condition = Some(condition);
 					}
-				})
+				}
+				if ui.small_button("Remove").clicked() {
+					removed = Some(*address);
+				}
 			});
+			if !text.trim().is_empty() {
+				if let Err(error) = text.parse::<Condition>() {
+					ui.colored_label(Color32::RED, error);
+				}
+			}
+		}
+		if let Some(address) = removed {
+			vm.breakpoints.remove(&address);
+		}
+	}
+
+	/// Lists the watchpoints and the accesses they've reported, newest first.
+	fn watchpoints(&mut self, ui: &mut egui::Ui, vm: &mut Vm) {
+		ui.horizontal(|ui| {
+			ui.text_edit_singleline(&mut self.watch_start);
+			ui.label("to");
+			ui.text_edit_singleline(&mut self.watch_end);
 		});
-	});
-	egui_macroquad::draw();
+		ui.horizontal(|ui| {
+			for access in Access::ALL {
+				ui.selectable_value(&mut self.watch_access, access, access.to_string());
+			}
+			if ui.button("Add").clicked() {
+				let start = parse_address(&self.watch_start);
+				let end = match self.watch_end.trim() {
+					"" => start,
+					end => parse_address(end),
+				};
+				if let (Some(start), Some(end)) = (start, end) {
+					let range = start.min(end)..=start.max(end);
+					vm.watchpoints
+						.push(Watchpoint::new(range, self.watch_access));
+					self.watch_start.clear();
+					self.watch_end.clear();
+				}
+			}
+		});
+		let mut removed = None;
+		for (index, watchpoint) in vm.watchpoints.iter_mut().enumerate() {
+			ui.horizontal(|ui| {
+				let range = format!(
+					"0x{:03X}-0x{:03X} {}",
+					watchpoint.range.start(),
+					watchpoint.range.end(),
+					watchpoint.access
+				);
+				ui.checkbox(&mut watchpoint.enabled, range);
+				ui.checkbox(&mut watchpoint.pause, "Pause");
+				if ui.small_button("Remove").clicked() {
+					removed = Some(index);
+				}
+			});
+		}
+		if let Some(index) = removed {
+			vm.watchpoints.remove(index);
+		}
+		ui.separator();
+		ui.horizontal(|ui| {
+			ui.label(format!("{} hits", vm.watchpoint_hits.len()));
+			if ui.small_button("Clear").clicked() {
+				vm.watchpoint_hits.clear();
+			}
+		});
+		let row_height = ui.fonts()[egui::TextStyle::Body].row_height();
+		let hits = vm.watchpoint_hits.len();
+		ScrollArea::from_max_height(row_height * 12.0).show_rows(
+			ui,
+			row_height,
+			hits,
+			|ui, row_range| {
+				for row in row_range {
+					let hit = &vm.watchpoint_hits[hits - 1 - row];
+					let text = hit.text(self.syntax, vm.mode, &vm.quirks);
+					ui.add(Label::new(text).monospace());
+				}
+			},
+		);
+	}
+
+	/// Disassembles the program, where clicking an address toggles its breakpoint.
+	fn instructions(&mut self, ui: &mut egui::Ui, vm: &mut Vm) {
+		ui.horizontal(|ui| {
+			for option in Syntax::ALL {
+				ui.selectable_value(&mut self.syntax, option, option.to_string());
+			}
+		});
+		// Disassembling every frame is slow, so it's only redone when memory,
+		// the mode or the syntax changes.
+		let syntax = self.syntax;
+		let cache = match self.disassembly.take() {
+			Some(cache) if cache.is_current(vm, syntax) => cache,
+			_ => DisassemblyCache::new(vm, syntax),
+		};
+		let rows = &self.disassembly.insert(cache).rows;
+		let row_height = ui.fonts()[egui::TextStyle::Body].row_height();
+		ScrollArea::auto_sized().show_rows(ui, row_height, rows.len(), |ui, row_range| {
+			for (address, label, text) in &rows[row_range] {
+				let background = match address {
+					Some(address) if vm.paused && *address == vm.program_counter => PAUSED_COLOR,
+					_ => Color32::TRANSPARENT,
+				};
+				ui.horizontal(|ui| {
+					let (marker, color) =
+						match address.and_then(|address| vm.breakpoints.get(&address)) {
+							Some(breakpoint) if breakpoint.enabled => ("●", Color32::RED),
+							Some(_) => ("○", Color32::GRAY),
+							None => (" ", Color32::GRAY),
+						};
+					let text_address =
+						address.map_or(String::new(), |address| format!("0x{:03X}", address));
+					let clicked = ui
+						.add(
+							Label::new(format!("{} {:<6} ", marker, text_address))
+								.monospace()
+								.text_color(color)
+								.background_color(background)
+								.sense(Sense::click()),
+						)
+						.clicked();
+					if let (true, Some(address)) = (clicked, address) {
+						toggle_breakpoint(vm, *address);
+					}
+					ui.add(
+						Label::new(format!("{:<10} ", label.as_deref().unwrap_or_default()))
+							.monospace()
+							.strong()
+							.background_color(background),
+					);
+					ui.add(Label::new(text).monospace().background_color(background));
+				});
+			}
+		})
+	}
 }
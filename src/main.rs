@@ -1,58 +1,102 @@
-#[macro_use]
-extern crate derive_more;
-
 pub mod debugger;
-pub mod instruction;
 pub mod subsystem;
 pub mod ui;
-pub mod vm;
 
-use crate::vm::Vm;
+use chippy_core::{
+	display::PALETTE,
+	rewind::RewindBuffer,
+	vm::{Speed, Vm, FRAME_TIME},
+};
+use debugger::Debugger;
 use macroquad::prelude::*;
-use std::time::{Duration, Instant};
+use std::time::Duration;
+use ui::status::Status;
+
+/// How many frames can be rewound by default (30 seconds at 60 FPS).
+const DEFAULT_REWIND_FRAMES: usize = 60 * 30;
+/// How much memory the rewind buffer may use by default.
+const DEFAULT_REWIND_BUDGET: usize = 16 * 1024 * 1024;
+/// The key that runs the game backwards while held down.
+const REWIND_KEY: KeyCode = KeyCode::Backspace;
+/// The most frames that will be run to catch up after a slow frame.
+const MAX_CATCH_UP_FRAMES: u32 = 4;
 
-fn step(vm: &mut Vm, last_time: &mut Instant) {
-	subsystem::key::handle(vm);
-	vm.execute();
-	if last_time.elapsed() > Duration::from_millis(16) {
-		vm.delay_timer = vm.delay_timer.saturating_sub(1);
-		vm.sound_timer = vm.sound_timer.saturating_sub(1);
-		*last_time = Instant::now();
+/// Runs as many emulated frames as have elapsed since the last call.
+fn step(vm: &mut Vm, rewind: &mut RewindBuffer, elapsed: &mut Duration, wants_keyboard: bool) {
+	subsystem::key::handle(vm, wants_keyboard);
+	*elapsed = (*elapsed + Duration::from_secs_f32(get_frame_time()))
+		.min(FRAME_TIME * MAX_CATCH_UP_FRAMES);
+	// An unlimited frame runs for a whole frame time, so it's run once for each render,
+	// as catching up would only fall further behind.
+	if vm.speed == Speed::Unlimited {
+		*elapsed = FRAME_TIME;
+	}
+	while *elapsed >= FRAME_TIME {
+		*elapsed -= FRAME_TIME;
+		// Faults pause the VM and are shown by the UI, so there's nothing to handle here.
+		let _ = vm.run_frame();
+		if !vm.paused {
+			rewind.push(vm);
+		}
+	}
+}
+
+/// Draws the display, scaled to fit the drawing area while keeping square pixels.
+fn draw_display(vm: &Vm, area: egui::Rect) {
+	let display = &vm.display;
+	let (width, height) = (display.width(), display.height());
+	let scale = (area.width() / width as f32).min(area.height() / height as f32);
+	let left = area.left() + (area.width() - scale * width as f32) / 2.0;
+	let top = area.top() + (area.height() - scale * height as f32) / 2.0;
+	for y in 0..height {
+		for x in 0..width {
+			let color = display.pixel(x, y);
+			if color == 0 {
+				continue;
+			}
+			let [r, g, b] = PALETTE[color];
+			draw_rectangle(
+				left + x as f32 * scale,
+				top + y as f32 * scale,
+				scale,
+				scale,
+				Color::from_rgba(r, g, b, 255),
+			);
+		}
 	}
 }
 
 #[macroquad::main("CHIP-8 Emulator")]
 async fn main() {
 	let mut vm = Vm::new();
-	let mut last_time = Instant::now();
-	let mut show_debugger = false;
-	let mut drawing_area = ui::draw(&mut vm);
-	let (vm_width, vm_height) = (128.0, 64.0);
+	let mut elapsed = Duration::ZERO;
+	let mut rewind = RewindBuffer::new(DEFAULT_REWIND_FRAMES, DEFAULT_REWIND_BUDGET);
+	let mut debugger = Debugger::default();
+	let mut status = Status::default();
+	let mut layout = ui::draw(&mut vm, &mut debugger, &mut status, &mut rewind);
 	loop {
-		step(&mut vm, &mut last_time);
-		clear_background(BLACK);
-		let (x_scale, y_scale) = (
-			drawing_area.width() / vm_width,
-			drawing_area.height() / vm_height,
-		);
-		let (left, top) = (drawing_area.left(), drawing_area.top());
-		for (idx, pixel) in vm.display.iter().enumerate() {
-			let x = (idx % vm_width as usize) as f32 * x_scale;
-			let y = (idx / vm_width as usize) as f32 * y_scale;
-			draw_rectangle(
-				left + x,
-				top + y,
-				x_scale,
-				y_scale,
-				if *pixel { WHITE } else { BLACK },
-			);
-		}
-		drawing_area = ui::draw(&mut vm);
-		if is_key_pressed(KeyCode::Period) {
-			show_debugger = !show_debugger;
+		// Backspace edits text fields, so it only rewinds when none has the keyboard.
+		// Rewinding while paused steps back through the recorded frames.
+		if is_key_down(REWIND_KEY) && !layout.wants_keyboard {
+			if let Err(error) = rewind.rewind(&mut vm) {
+				status.error(format!("Failed to rewind: {}", error));
+				rewind.clear();
+			}
+		} else {
+			step(&mut vm, &mut rewind, &mut elapsed, layout.wants_keyboard);
 		}
-		if is_key_pressed(KeyCode::Comma) {
-			vm.paused = !vm.paused;
+		clear_background(BLACK);
+		draw_display(&vm, layout.display);
+		layout = ui::draw(&mut vm, &mut debugger, &mut status, &mut rewind);
+		// Keys typed into text fields, such as the memory editor, aren't hotkeys.
+		if !layout.wants_keyboard {
+			if is_key_pressed(KeyCode::Period) {
+				debugger.open = !debugger.open;
+			}
+			if is_key_pressed(KeyCode::Comma) {
+				vm.paused = !vm.paused;
+			}
+			subsystem::state::handle(&mut vm, &mut status, &mut rewind);
 		}
 		next_frame().await;
 	}
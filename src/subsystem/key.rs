@@ -1,4 +1,4 @@
-use crate::vm::Vm;
+use chippy_core::vm::Vm;
 use macroquad::prelude::*;
 
 static KEY_BINDINGS: [(usize, KeyCode); 16] = [
@@ -20,38 +20,9 @@ static KEY_BINDINGS: [(usize, KeyCode); 16] = [
 	(0xF, KeyCode::V),
 ];
 
-pub static KEY_CHARS: [(char, usize); 16] = [
-	('1', 0x1),
-	('2', 0x2),
-	('3', 0x3),
-	('4', 0xC),
-	('q', 0x4),
-	('w', 0x5),
-	('e', 0x6),
-	('r', 0xD),
-	('a', 0x7),
-	('s', 0x8),
-	('d', 0x9),
-	('f', 0xE),
-	('z', 0xA),
-	('x', 0x0),
-	('c', 0xB),
-	('v', 0xF),
-];
-
-pub fn get_key() -> Option<usize> {
-	get_char_pressed()
-		.and_then(|c| {
-			KEY_CHARS
-				.iter()
-				.find(|(x, _)| *x == c.to_ascii_lowercase())
-				.copied()
-		})
-		.map(|(_, i)| i)
-}
-
-pub fn handle(vm: &mut Vm) {
+/// Updates the keypad from the keyboard, with every key released while the UI has the keyboard.
+pub fn handle(vm: &mut Vm, wants_keyboard: bool) {
 	for (index, key) in KEY_BINDINGS.iter() {
-		vm.keypad.set(*index, is_key_down(*key));
+		vm.keypad.set(*index, !wants_keyboard && is_key_down(*key));
 	}
 }
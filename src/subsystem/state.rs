@@ -0,0 +1,54 @@
+use crate::ui::status::Status;
+use chippy_core::{rewind::RewindBuffer, vm::Vm};
+use macroquad::prelude::*;
+use std::{io, path::PathBuf};
+
+/// The directory that quick-save slots are stored in.
+const STATE_DIR: &str = "states";
+
+/// The hotkeys for each quick-save slot.
+/// Pressing a key loads the slot, holding shift while pressing it saves to the slot.
+pub static SLOT_KEYS: [(u8, KeyCode); 4] = [
+	(1, KeyCode::F1),
+	(2, KeyCode::F2),
+	(3, KeyCode::F3),
+	(4, KeyCode::F4),
+];
+
+fn slot_path(slot: u8) -> PathBuf {
+	PathBuf::from(STATE_DIR).join(format!("slot{}.state", slot))
+}
+
+/// Saves the state of the VM into a quick-save slot.
+pub fn save_slot(vm: &Vm, slot: u8) -> io::Result<()> {
+	std::fs::create_dir_all(STATE_DIR)?;
+	std::fs::write(slot_path(slot), vm.save_state())
+}
+
+/// Restores the state of the VM from a quick-save slot.
+pub fn load_slot(vm: &mut Vm, slot: u8) -> io::Result<()> {
+	let state = std::fs::read(slot_path(slot))?;
+	vm.load_state(&state)
+		.map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+pub fn handle(vm: &mut Vm, status: &mut Status, rewind: &mut RewindBuffer) {
+	let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+	for (slot, key) in SLOT_KEYS.iter() {
+		if !is_key_pressed(*key) {
+			continue;
+		}
+		if shift {
+			if let Err(error) = save_slot(vm, *slot) {
+				status.error(format!("Failed to quick-save slot {}: {}", slot, error));
+			}
+		} else {
+			match load_slot(vm, *slot) {
+				Ok(()) => rewind.clear(),
+				Err(error) => {
+					status.error(format!("Failed to quick-load slot {}: {}", slot, error))
+				}
+			}
+		}
+	}
+}
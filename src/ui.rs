@@ -2,33 +2,57 @@ mod cpu;
 mod keypad;
 mod memory;
 mod menu;
+pub mod status;
 
-use crate::vm::Vm;
+use crate::debugger::Debugger;
+use chippy_core::{rewind::RewindBuffer, vm::Vm};
 use egui::{
 	containers::panel::{CentralPanel, SidePanel, TopBottomPanel},
 	Color32, Frame, Rect,
 };
+use status::Status;
 
-pub fn draw(vm: &mut Vm) -> Rect {
-	let mut rect = Rect::NOTHING;
+/// What the UI leaves to the emulator once it's drawn.
+pub struct Layout {
+	/// The area that the display is drawn in.
+	pub display: Rect,
+	/// Whether a text field has the keyboard, so keys shouldn't reach the emulator.
+	pub wants_keyboard: bool,
+}
+
+pub fn draw(
+	vm: &mut Vm,
+	debugger: &mut Debugger,
+	status: &mut Status,
+	rewind: &mut RewindBuffer,
+) -> Layout {
+	let mut layout = Layout {
+		display: Rect::NOTHING,
+		wants_keyboard: false,
+	};
 	egui_macroquad::ui(|ctx| {
 		SidePanel::left("left").show(ctx, |ui| {
 			cpu::draw(ui, vm);
 		});
 		SidePanel::right("right").show(ctx, |ui| {
-			ui.label("Hello World!");
+			memory::draw(ui, vm);
 		});
 		TopBottomPanel::bottom("bottom").show(ctx, |ui| {
 			keypad::draw(ui, vm);
 		});
 		TopBottomPanel::top("menu").show(ctx, |ui| {
-			menu::draw(ui, vm);
+			menu::draw(ui, vm, status, rewind);
 		});
 		let central = CentralPanel::default()
 			.frame(Frame::default().fill(Color32::TRANSPARENT))
 			.show(ctx, |_| {});
-		rect = central.response.rect;
+		layout.display = central.response.rect;
+		if debugger.open {
+			debugger.draw(ctx, vm);
+		}
+		status.draw(ctx);
+		layout.wants_keyboard = ctx.wants_keyboard_input();
 	});
 	egui_macroquad::draw();
-	rect
+	layout
 }
@@ -1,5 +1,5 @@
-use crate::vm::Vm;
-use egui::{containers::CollapsingHeader, Grid, Label, Ui};
+use chippy_core::{random::RandomMode, vm::Vm};
+use egui::{containers::CollapsingHeader, Color32, Grid, Id, Key, Label, Ui};
 
 fn register(ui: &mut Ui, name: impl ToString, value: u16) {
 	ui.vertical_centered_justified(|ui| {
@@ -9,16 +9,60 @@ fn register(ui: &mut Ui, name: impl ToString, value: u16) {
 	});
 }
 
+/// Edits the random seed.
+/// The text is kept in a buffer until it's applied with Enter or the button,
+/// so that each keystroke doesn't reseed the generator.
+fn seed_field(ui: &mut Ui, vm: &mut Vm) {
+	let id = Id::new("seed edit");
+	let mut text = ui
+		.memory()
+		.id_data_temp
+		.get::<String>(&id)
+		.cloned()
+		.unwrap_or_else(|| format!("0x{:X}", vm.random.seed()));
+	ui.horizontal(|ui| {
+		let response = ui.text_edit_singleline(&mut text);
+		let entered = response.lost_focus() && ui.input().key_pressed(Key::Enter);
+		if ui.button("Set").clicked() || entered {
+			// An invalid seed is dropped, and the field shows the current one again.
+			if let Ok(seed) = u64::from_str_radix(text.trim().trim_start_matches("0x").trim(), 16) {
+				vm.set_seed(seed);
+			}
+			ui.memory().id_data_temp.remove(&id);
+		} else if response.changed() || ui.memory().id_data_temp.get::<String>(&id).is_some() {
+			ui.memory().id_data_temp.insert(id, text);
+		}
+	});
+}
+
 pub fn draw(ui: &mut Ui, vm: &mut Vm) {
 	CollapsingHeader::new("CPU").show(ui, |ui| {
 		ui.label(format!("Running in {} mode", vm.mode));
+		if let Some(fault) = vm.fault {
+			ui.colored_label(Color32::RED, format!("Fault: {}", fault));
+		}
 		Grid::new("cpu info").num_columns(2).show(ui, |ui| {
+			ui.add(Label::new("Frame Count").strong());
+			ui.add(Label::new(format!("{}", vm.frames)).monospace());
+			ui.end_row();
 			ui.add(Label::new("Cycle Count").strong());
 			ui.add(Label::new(format!("{}", vm.cycles)).monospace());
 			ui.end_row();
+			ui.add(Label::new("VIP Machine Cycles").strong());
+			ui.add(Label::new(format!("{}", vm.machine_cycles)).monospace());
+			ui.end_row();
 			ui.add(Label::new("Cycle Length").strong());
 			ui.add(Label::new(format!("{:?}", vm.last_cycle_time)).monospace());
 			ui.end_row();
+			ui.add(Label::new("Random Seed").strong());
+			seed_field(ui, vm);
+			ui.end_row();
+			ui.add(Label::new("Random Mode").strong());
+			ui.horizontal(|ui| {
+				ui.selectable_value(&mut vm.random.mode, RandomMode::WyRand, "WyRand");
+				ui.selectable_value(&mut vm.random.mode, RandomMode::Vip, "VIP");
+			});
+			ui.end_row();
 		});
 		ui.separator();
 
@@ -1,4 +1,4 @@
-use crate::vm::Vm;
+use chippy_core::vm::Vm;
 use egui::{Button, Grid, TextStyle, Ui};
 
 fn key(key: usize, ui: &mut Ui, vm: &mut Vm) {
@@ -1,4 +1,210 @@
-use crate::vm::Vm;
-use egui::{containers::CollapsingHeader, Grid, Label, Ui};
+use chippy_core::{
+	instruction::{
+		draw,
+		load::{LoadInstruction, LoadTarget},
+		Instruction,
+	},
+	vm::{Vm, FONT_ADDRESS, PROGRAM_START},
+};
+use egui::{
+	containers::{CollapsingHeader, ScrollArea},
+	Color32, Id, Label, Sense, TextEdit, TextStyle, Ui,
+};
+use std::{mem, ops::Range};
 
-pub fn draw(ui: &mut Ui, vm: &mut Vm) {}
+/// How many bytes are shown on each row.
+const BYTES_PER_ROW: usize = 8;
+/// How many rows are shown at once.
+const VISIBLE_ROWS: usize = 16;
+/// The size of the small hex font.
+const FONT_SIZE: usize = 80;
+
+const FONT_COLOR: Color32 = Color32::from_rgb(25, 45, 90);
+const PROGRAM_COLOR: Color32 = Color32::from_rgb(25, 65, 35);
+const PC_COLOR: Color32 = Color32::from_rgb(130, 95, 0);
+const I_COLOR: Color32 = Color32::from_rgb(100, 35, 100);
+const CHANGED_COLOR: Color32 = Color32::from_rgb(255, 90, 90);
+
+/// What the memory viewer remembers between frames.
+#[derive(Clone, Default)]
+struct MemoryView {
+	/// The address typed in to go to.
+	goto: String,
+	/// The row to scroll to on the next frame.
+	scroll_to: Option<usize>,
+	/// The address of the byte being edited, and its text.
+	edit: Option<(usize, String)>,
+	/// Memory before the last step.
+	previous: Vec<u8>,
+	/// Memory when it was last drawn.
+	current: Vec<u8>,
+	/// The cycle count when memory was last drawn, which changes with each step.
+	cycles: usize,
+}
+
+impl MemoryView {
+	/// Remembers memory before each step, so that the bytes it changed stand out.
+	fn update(&mut self, vm: &Vm) {
+		if vm.cycles < self.cycles || self.current.len() != vm.memory.len() {
+			// A program was loaded or the mode changed, so nothing has been stepped yet.
+			self.previous = vm.memory.clone();
+			self.current = vm.memory.clone();
+		} else if vm.cycles != self.cycles {
+			self.previous = mem::replace(&mut self.current, vm.memory.clone());
+		}
+		self.cycles = vm.cycles;
+	}
+
+	fn changed(&self, address: usize, vm: &Vm) -> bool {
+		self.previous
+			.get(address)
+			.is_some_and(|byte| *byte != vm.memory[address])
+	}
+}
+
+/// The bytes at I that the instruction at the program counter accesses, or just the first.
+fn index_range(vm: &Vm) -> Range<usize> {
+	let instruction = Instruction::decode(&vm.memory, vm.program_counter as usize, vm.mode);
+	let size = match instruction {
+		Some(Instruction::Draw(_, _, rows)) => draw::sprite_bytes(vm, rows),
+		Some(Instruction::Load(LoadInstruction { from, into })) => match (from, into) {
+			(LoadTarget::Register(register), LoadTarget::I)
+			| (LoadTarget::I, LoadTarget::Register(register)) => register as usize + 1,
+			(LoadTarget::RegisterRange(first, last), LoadTarget::I)
+			| (LoadTarget::I, LoadTarget::RegisterRange(first, last)) => first.abs_diff(last) as usize + 1,
+			(_, LoadTarget::Bcd) => 3,
+			(LoadTarget::I, LoadTarget::AudioPattern) => 16,
+			_ => 1,
+		},
+		_ => 1,
+	};
+	let start = vm.index_register as usize;
+	start..start + size
+}
+
+/// The background of a byte, showing what it's used for.
+fn background(address: usize, vm: &Vm, index: &Range<usize>) -> Color32 {
+	let font = FONT_ADDRESS as usize..FONT_ADDRESS as usize + FONT_SIZE;
+	let program = PROGRAM_START..PROGRAM_START + vm.program_size;
+	let pc = vm.program_counter as usize;
+	if (pc..pc + 2).contains(&address) {
+		PC_COLOR
+	} else if index.contains(&address) {
+		I_COLOR
+	} else if program.contains(&address) {
+		PROGRAM_COLOR
+	} else if font.contains(&address) {
+		FONT_COLOR
+	} else {
+		Color32::TRANSPARENT
+	}
+}
+
+pub fn draw(ui: &mut Ui, vm: &mut Vm) {
+	CollapsingHeader::new("Memory").show(ui, |ui| {
+		let id = Id::new("memory view");
+		let mut view = mem::take(
+			ui.memory()
+				.id_data_temp
+				.get_mut_or_default::<MemoryView>(id),
+		);
+		view.update(vm);
+
+		ui.horizontal(|ui| {
+			let goto = ui.text_edit_singleline(&mut view.goto);
+			if ui.button("Go").clicked() || goto.lost_focus() {
+				let address = view.goto.trim().trim_start_matches("0x").trim();
+				if let Ok(address) = usize::from_str_radix(address, 16) {
+					view.scroll_to = Some(address.min(vm.memory.len() - 1) / BYTES_PER_ROW);
+				}
+			}
+		});
+		ui.horizontal_wrapped(|ui| {
+			ui.add(Label::new("PC").background_color(PC_COLOR));
+			ui.add(Label::new("I").background_color(I_COLOR));
+			ui.add(Label::new("Program").background_color(PROGRAM_COLOR));
+			ui.add(Label::new("Font").background_color(FONT_COLOR));
+			ui.add(Label::new("Changed").text_color(CHANGED_COLOR));
+		});
+		if vm.paused {
+			ui.label("Click a byte to edit it.");
+		}
+
+		let row_height = ui.fonts()[TextStyle::Monospace].row_height();
+		let mut scroll_area = ScrollArea::from_max_height(row_height * VISIBLE_ROWS as f32);
+		if let Some(row) = view.scroll_to.take() {
+			scroll_area =
+				scroll_area.scroll_offset(row as f32 * (row_height + ui.spacing().item_spacing.y));
+		}
+		let index = index_range(vm);
+		let rows = vm.memory.len().div_ceil(BYTES_PER_ROW);
+		scroll_area.show_rows(ui, row_height, rows, |ui, row_range| {
+			for row in row_range {
+				let start = row * BYTES_PER_ROW;
+				let end = (start + BYTES_PER_ROW).min(vm.memory.len());
+				ui.horizontal(|ui| {
+					ui.spacing_mut().item_spacing.x = 4.0;
+					ui.add(Label::new(format!("{:04X}", start)).monospace().strong());
+					for address in start..end {
+						draw_byte(ui, vm, &mut view, address, &index);
+					}
+					let ascii = vm.memory[start..end]
+						.iter()
+						.map(|byte| match *byte {
+							0x20..=0x7E => *byte as char,
+							_ => '.',
+						})
+						.collect::<String>();
+					ui.add(Label::new(ascii).monospace());
+				});
+			}
+		});
+
+		*ui.memory()
+			.id_data_temp
+			.get_mut_or_default::<MemoryView>(id) = view;
+	});
+}
+
+/// Draws a byte, or the field it's edited in.
+fn draw_byte(
+	ui: &mut Ui,
+	vm: &mut Vm,
+	view: &mut MemoryView,
+	address: usize,
+	index: &Range<usize>,
+) {
+	if let Some((_, text)) = view.edit.as_mut().filter(|(edited, _)| *edited == address) {
+		let response = ui.add(
+			TextEdit::singleline(text)
+				.text_style(TextStyle::Monospace)
+				.desired_width(ui.fonts().glyph_width(TextStyle::Monospace, '0') * 2.0),
+		);
+		if response.lost_focus() {
+			// The program may have been resumed while the byte was edited.
+			if let Some(byte) = u8::from_str_radix(text.trim(), 16)
+				.ok()
+				.filter(|_| vm.paused)
+			{
+				vm.memory[address] = byte;
+				vm.invalidate_cache(address..=address);
+			}
+			view.edit = None;
+		} else if !response.has_focus() {
+			// The field takes focus when it appears, and editing ends when it loses it.
+			response.request_focus();
+		}
+		return;
+	}
+	let mut label = Label::new(format!("{:02X}", vm.memory[address]))
+		.monospace()
+		.background_color(background(address, vm, index))
+		.sense(Sense::click());
+	if view.changed(address, vm) {
+		label = label.text_color(CHANGED_COLOR);
+	}
+	// Memory is only edited while paused, so that the program doesn't race the editor.
+	if ui.add(label).clicked() && vm.paused {
+		view.edit = Some((address, format!("{:02X}", vm.memory[address])));
+	}
+}
@@ -1,21 +1,161 @@
-use crate::vm::{Vm, VmMode};
-use egui::{menu, Ui};
+use super::status::Status;
+use crate::subsystem::state;
+use chippy_core::{
+	octo,
+	quirks::IndexIncrement,
+	rewind::RewindBuffer,
+	vm::{Speed, Vm, VmMode},
+};
+use egui::{menu, DragValue, Ui};
 use rfd::FileDialog;
 
-pub fn draw(ui: &mut Ui, vm: &mut Vm) {
+pub fn draw(ui: &mut Ui, vm: &mut Vm, status: &mut Status, rewind: &mut RewindBuffer) {
 	menu::bar(ui, |ui| {
 		menu::menu(ui, "File", |ui| {
 			if ui.button("Open").clicked() {
-				if let Some(file) = FileDialog::new()
+				if let Some(path) = FileDialog::new().pick_file() {
+					// Octo sources are compiled for the current mode.
+					let program = if path.extension().is_some_and(|extension| extension == "8o") {
+						octo::compile_file(&path, vm.mode)
+							.map_err(|error| format!("Failed to compile program: {}", error))
+					} else {
+						std::fs::read(&path)
+							.map_err(|error| format!("Failed to open program: {}", error))
+					};
+					match program {
+						Ok(program) => {
+							if let Err(error) = vm.load_program(&program) {
+								vm.fault = Some(error);
+							}
+							rewind.clear();
+						}
+						Err(error) => status.error(error),
+					}
+				}
+			}
+			ui.separator();
+			if ui.button("Save State").clicked() {
+				if let Some(path) = FileDialog::new()
+					.add_filter("Save State", &["state"])
+					.save_file()
+				{
+					if let Err(error) = std::fs::write(path, vm.save_state()) {
+						status.error(format!("Failed to save state: {}", error));
+					}
+				}
+			}
+			if ui.button("Load State").clicked() {
+				if let Some(path) = FileDialog::new()
+					.add_filter("Save State", &["state"])
 					.pick_file()
-					.and_then(|file| std::fs::read(file).ok())
 				{
-					vm.load_program(&file);
+					let result = std::fs::read(path)
+						.map_err(|error| error.to_string())
+						.and_then(|state| vm.load_state(&state).map_err(|error| error.to_string()));
+					match result {
+						Ok(()) => rewind.clear(),
+						Err(error) => status.error(format!("Failed to load state: {}", error)),
+					}
+				}
+			}
+			for (slot, _) in state::SLOT_KEYS.iter() {
+				if ui
+					.button(format!("Quick Save {} (Shift+F{})", slot, slot))
+					.clicked()
+				{
+					if let Err(error) = state::save_slot(vm, *slot) {
+						status.error(format!("Failed to quick-save slot {}: {}", slot, error));
+					}
+				}
+			}
+			for (slot, _) in state::SLOT_KEYS.iter() {
+				if ui
+					.button(format!("Quick Load {} (F{})", slot, slot))
+					.clicked()
+				{
+					match state::load_slot(vm, *slot) {
+						Ok(()) => rewind.clear(),
+						Err(error) => {
+							status.error(format!("Failed to quick-load slot {}: {}", slot, error))
+						}
+					}
 				}
 			}
-			ui.selectable_value(&mut vm.mode, VmMode::Chip8, "CHIP-8");
-			ui.selectable_value(&mut vm.mode, VmMode::Chip48, "CHIP-48");
-			ui.selectable_value(&mut vm.mode, VmMode::SuperChip, "SUPER-CHIP");
+			ui.separator();
+			for (mode, name) in [
+				(VmMode::Chip8, "CHIP-8"),
+				(VmMode::CosmacVip, "COSMAC VIP"),
+				(VmMode::Chip48, "CHIP-48"),
+				(VmMode::SuperChip, "SUPER-CHIP"),
+				(VmMode::XoChip, "XO-CHIP"),
+			] {
+				if ui.selectable_label(vm.mode == mode, name).clicked() {
+					vm.set_mode(mode);
+					rewind.clear();
+				}
+			}
+		});
+		menu::menu(ui, "Quirks", |ui| {
+			let quirks = &mut vm.quirks;
+			ui.checkbox(&mut quirks.shift_vy, "Shifts use Vy");
+			ui.label("Fx55/Fx65 increment I by");
+			for increment in [
+				IndexIncrement::XPlusOne,
+				IndexIncrement::X,
+				IndexIncrement::Unchanged,
+			] {
+				ui.radio_value(
+					&mut quirks.index_increment,
+					increment,
+					increment.to_string(),
+				);
+			}
+			ui.checkbox(&mut quirks.jump_vx, "Bxnn jumps with Vx");
+			ui.checkbox(&mut quirks.clip_sprites, "Clip sprites at screen edges");
+			ui.checkbox(&mut quirks.logic_reset_vf, "Logic ops reset VF");
+			ui.checkbox(&mut quirks.display_wait, "Drawing waits for vblank");
+			ui.checkbox(&mut quirks.lores_16x16, "16x16 sprites in low resolution");
+			ui.separator();
+			if ui.button(format!("Reset to {} quirks", vm.mode)).clicked() {
+				vm.quirks = vm.mode.quirks();
+			}
+		});
+		menu::menu(ui, "Speed", |ui| {
+			for speed in Speed::PRESETS.iter() {
+				ui.selectable_value(&mut vm.speed, *speed, speed.to_string());
+			}
+		});
+		menu::menu(ui, "Rewind", |ui| {
+			// The limits are edited in seconds and MiB, which are easier to reason about.
+			let mut seconds = rewind.max_frames / 60;
+			let mut megabytes = rewind.max_bytes / (1024 * 1024);
+			ui.horizontal(|ui| {
+				ui.label("Length");
+				ui.add(
+					DragValue::new(&mut seconds)
+						.clamp_range(1..=600)
+						.suffix(" s"),
+				);
+			});
+			ui.horizontal(|ui| {
+				ui.label("Memory");
+				ui.add(
+					DragValue::new(&mut megabytes)
+						.clamp_range(1..=1024)
+						.suffix(" MiB"),
+				);
+			});
+			if seconds != rewind.max_frames / 60 || megabytes != rewind.max_bytes / (1024 * 1024) {
+				rewind.set_limits(seconds * 60, megabytes * 1024 * 1024);
+			}
+			ui.label(format!(
+				"{} frames in {} KiB",
+				rewind.len(),
+				rewind.memory_usage() / 1024
+			));
+			if ui.button("Clear").clicked() {
+				rewind.clear();
+			}
 		});
 	});
 }
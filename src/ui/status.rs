@@ -0,0 +1,32 @@
+use egui::{Align2, Color32, CtxRef, Window};
+
+/// Errors for the user, such as files that failed to open, shown until dismissed.
+#[derive(Default)]
+pub struct Status {
+	errors: Vec<String>,
+}
+
+impl Status {
+	/// Shows an error.
+	pub fn error(&mut self, error: impl ToString) {
+		self.errors.push(error.to_string());
+	}
+
+	pub fn draw(&mut self, ctx: &CtxRef) {
+		if self.errors.is_empty() {
+			return;
+		}
+		Window::new("Error")
+			.collapsible(false)
+			.resizable(false)
+			.anchor(Align2::CENTER_CENTER, [0.0, 0.0])
+			.show(ctx, |ui| {
+				for error in &self.errors {
+					ui.colored_label(Color32::RED, error);
+				}
+				if ui.button("OK").clicked() {
+					self.errors.clear();
+				}
+			});
+	}
+}